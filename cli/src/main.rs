@@ -0,0 +1,572 @@
+//! Mobydick without a window, for scripts and headless machines.
+//!
+//! It shares the session and the list of downloads with the GTK app.
+
+use mobydick_core::{
+  api::{self, RequestContext},
+  db, downloads::{self, DlStatus, Download, DOWNLOADS},
+  history, scan,
+  schedule::{self, Repeat},
+  service, session, settings,
+  sync::{self, Source},
+};
+use chrono::Weekday;
+use std::{
+  collections::HashMap,
+  env,
+  io::{self, BufRead, Write},
+  path::{Path, PathBuf},
+  process::{self, Command},
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+const USAGE: &str = "Usage: mobydick-cli <command>
+
+Commands:
+  login <instance> <username>        Log in. The password is read from
+                                     MOBYDICK_PASSWORD, or asked.
+  logout                             Log out, and forget the list of downloads
+  search <query>                     Search for artists, albums and tracks
+  download artist|album|track <id>   Download an artist, an album or a track
+  download --search <query>          Download the tracks matching a search
+  queue list                         Show the downloads and their status
+  queue resume                       Restart the downloads that didn't end
+  history                            Show what you listened to recently
+  db rebuild                         Read the downloaded files again, and
+                                     forget the ones that were deleted
+  scan <folder>                      Index a music folder, so that tracks
+                                     already in it are not downloaded
+  sync add <folder> <selection>...   Keep a folder in sync with a selection:
+                                     artist:<id>, album:<id>, playlist:<id>,
+                                     favorites or library:<uuid>
+  sync list                          Show the folders that are kept in sync
+  sync remove <folder>               Stop syncing a folder (files are kept)
+  sync run [--dry-run] [--delete] [<folder>]
+                                     Sync a folder, or all of them. With
+                                     --delete, files of tracks that left the
+                                     selection are deleted. With --dry-run,
+                                     only show what would be done.
+  schedule add <selection>... [--every day|week|<weekday>]
+                                     Download a selection when the download
+                                     window opens, once or regularly
+  schedule list                      Show the scheduled downloads
+  schedule remove <id>               Cancel a scheduled download
+  schedule run                       Run the scheduled downloads that are
+                                     due, for cron or systemd timers
+  serve                              Serve on D-Bus as xyz.gelez.mobydick,
+                                     and download scheduled tracks";
+
+/// How many plays `history` shows
+const HISTORY_SIZE: &str = "30";
+
+fn main() {
+  settings::load();
+  if let Some(problem) = db::problem() {
+    eprintln!("Warning: {}", problem);
+  }
+  let args: Vec<String> = env::args().skip(1).collect();
+  let command = args.first().map(String::as_str).unwrap_or("");
+  let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or("");
+  let rest = |from: usize| args.get(from..).map(|a| a.join(" ")).unwrap_or_default();
+
+  let res = match (command, arg(1)) {
+    ("login", instance) if !instance.is_empty() && !arg(2).is_empty() => login(instance, arg(2)),
+    ("logout", _) => {
+      session::forget();
+      println!("Logged out.");
+      Ok(())
+    }
+    ("search", _) if !rest(1).is_empty() => client().and_then(|c| search(&c, &rest(1))),
+    ("download", "--search") if !rest(2).is_empty() => {
+      client().and_then(|c| download_search(&c, &rest(2)))
+    }
+    ("download", kind) if arg(2).parse::<i32>().is_ok() => {
+      let id = arg(2).parse().unwrap_or_default();
+      client().and_then(|c| match kind {
+        "artist" => download(downloads::of_artist(&c, id, None)),
+        "album" => {
+          let album = c.album(id).map_err(|e| e.to_string())?;
+          download(downloads::of_album(&c, &album))
+        }
+        "track" => {
+          let track: api::Track = api::send(c.api_get(format!("/tracks/{}/", id)))
+            .and_then(|r| r.json())
+            .map_err(|e| e.to_string())?;
+          download(vec![Download::new(track)])
+        }
+        _ => Err(USAGE.to_string()),
+      })
+    }
+    ("queue", "list") => client().map(|_| queue()),
+    ("queue", "resume") => client().and_then(|_| resume()),
+    ("history", _) => client().and_then(|c| listenings(&c)),
+    ("db", "rebuild") => client().and_then(|_| rebuild()),
+    ("scan", dir) if !dir.is_empty() => scan(&absolute(dir)),
+    ("sync", "add") if !arg(2).is_empty() && args.len() > 3 => args[3..]
+      .iter()
+      .map(|s| s.parse::<Source>())
+      .collect::<Result<Vec<_>, _>>()
+      .map(|sources| {
+        sync::add(absolute(arg(2)), sources);
+        println!("{} will be kept in sync. Use `mobydick-cli sync run` to sync it now.", arg(2));
+      }),
+    ("sync", "list") => {
+      mirrors();
+      Ok(())
+    }
+    ("sync", "remove") if !arg(2).is_empty() => {
+      if sync::remove(&absolute(arg(2))) {
+        println!("{} is not synced anymore. Its files were kept.", arg(2));
+        Ok(())
+      } else {
+        Err(format!("{} is not synced.", arg(2)))
+      }
+    }
+    ("sync", "run") => client().and_then(|c| {
+      let flags = &args[2..];
+      let dry_run = flags.iter().any(|f| f == "--dry-run");
+      let delete = flags.iter().any(|f| f == "--delete");
+      let dirs: Vec<PathBuf> = match flags.iter().find(|f| !f.starts_with("--")) {
+        Some(dir) => vec![absolute(dir)],
+        None => sync::list().into_iter().map(|m| m.dir).collect(),
+      };
+      sync_dirs(&c, &dirs, delete, dry_run)
+    }),
+    ("schedule", "add") if args.len() > 2 => schedule_add(&args[2..]),
+    ("schedule", "list") => {
+      jobs();
+      Ok(())
+    }
+    ("schedule", "remove") if arg(2).parse::<u32>().is_ok() => {
+      if schedule::remove(arg(2).parse().unwrap_or_default()) {
+        println!("Scheduled download {} was cancelled.", arg(2));
+        Ok(())
+      } else {
+        Err(format!("There is no scheduled download {}.", arg(2)))
+      }
+    }
+    ("schedule", "run") => client().and_then(|_| run_schedule()),
+    ("serve", _) => client().and_then(|_| {
+      schedule::watch();
+      println!("Serving {} on the session bus.", service::NAME);
+      service::serve()
+    }),
+    _ => Err(USAGE.to_string()),
+  };
+
+  if let Err(e) = res {
+    eprintln!("{}", e);
+    process::exit(1);
+  }
+}
+
+/// Restores the saved session, and the list of downloads that comes with it.
+fn client() -> Result<RequestContext, String> {
+  if !session::load().map_err(|e| e.to_string())? {
+    return Err("You are not logged in. Use `mobydick-cli login` first.".to_string());
+  }
+  downloads::load();
+  Ok(api::API.lock().unwrap().clone().unwrap())
+}
+
+fn login(instance: &str, username: &str) -> Result<(), String> {
+  let password = match env::var("MOBYDICK_PASSWORD") {
+    Ok(password) => password,
+    Err(_) => ask_password()?,
+  };
+  session::login(instance, username.to_string(), password)?;
+  println!("Logged in as {}.", username);
+  Ok(())
+}
+
+/// Reads a password from the terminal, without showing it.
+fn ask_password() -> Result<String, String> {
+  print!("Password: ");
+  io::stdout().flush().ok();
+  // Not a terminal (or no stty): the password is just read from the input
+  let hidden = Command::new("stty")
+    .arg("-echo")
+    .status()
+    .map(|s| s.success())
+    .unwrap_or(false);
+  let mut password = String::new();
+  let res = io::stdin().lock().read_line(&mut password);
+  if hidden {
+    Command::new("stty").arg("echo").status().ok();
+    println!();
+  }
+  res.map_err(|e| e.to_string())?;
+  Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn search_results(client: &RequestContext, query: &str) -> Result<api::SearchResult, String> {
+  api::send(client.api_get("/search").query(&api::SearchQuery {
+    query: query.to_string(),
+  }))
+  .and_then(|r| r.json())
+  .map_err(|e| format!("The search failed. {}", e))
+}
+
+fn search(client: &RequestContext, query: &str) -> Result<(), String> {
+  let res = search_results(client, query)?;
+  if res.artists.is_empty() && res.albums.is_empty() && res.tracks.is_empty() {
+    println!("No results. Try something else.");
+  }
+  if !res.artists.is_empty() {
+    println!("Artists");
+    for artist in res.artists {
+      println!("  {:>8}  {}", artist.id, artist.name);
+    }
+  }
+  if !res.albums.is_empty() {
+    println!("Albums");
+    for album in res.albums {
+      println!("  {:>8}  {}, by {}", album.id, album.title, album.artist.name);
+    }
+  }
+  if !res.tracks.is_empty() {
+    println!("Tracks");
+    for track in res.tracks {
+      println!(
+        "  {:>8}  {}, by {}, in {}",
+        track.id, track.title, track.artist.name, track.album.title
+      );
+    }
+  }
+  Ok(())
+}
+
+fn download_search(client: &RequestContext, query: &str) -> Result<(), String> {
+  let res = search_results(client, query)?;
+  download(res.tracks.into_iter().map(Download::new).collect())
+}
+
+/// Downloads what isn't on disk yet, and waits until it's done.
+fn download(dls: Vec<Download>) -> Result<(), String> {
+  let dls: Vec<Download> = dls.into_iter().filter(|dl| !downloads::on_disk(dl)).collect();
+  if dls.is_empty() {
+    println!("Everything is already downloaded.");
+    return Ok(());
+  }
+  let ids: Vec<i32> = dls.iter().map(|dl| dl.track.id).collect();
+  println!("Downloading {} tracks…", ids.len());
+  show_progress(ids.clone());
+  downloads::enqueue(dls);
+  downloads::wait();
+  downloads::save();
+  report(&ids)
+}
+
+fn resume() -> Result<(), String> {
+  let ids: Vec<i32> = DOWNLOADS
+    .lock()
+    .unwrap()
+    .values()
+    .filter(|dl| dl.status == DlStatus::Planned)
+    .map(|dl| dl.track.id)
+    .collect();
+  if ids.is_empty() {
+    println!("Nothing to resume.");
+    return Ok(());
+  }
+  println!("Resuming {} downloads…", ids.len());
+  show_progress(ids.clone());
+  downloads::resume();
+  downloads::wait();
+  downloads::save();
+  report(&ids)
+}
+
+/// Prints a line every time one of these tracks is downloaded.
+fn show_progress(ids: Vec<i32>) {
+  let shown = AtomicUsize::new(0);
+  let total = ids.len();
+  downloads::on_change(move || {
+    let done = {
+      let dls = DOWNLOADS.lock().unwrap();
+      ids
+        .iter()
+        .filter(|id| dls.get(id).map(|dl| dl.status == DlStatus::Done).unwrap_or(false))
+        .count()
+    };
+    if shown.swap(done, Ordering::SeqCst) < done {
+      println!("[{}/{}]", done, total);
+    }
+  });
+}
+
+fn report(ids: &[i32]) -> Result<(), String> {
+  let dls = DOWNLOADS.lock().unwrap();
+  let count = |status: DlStatus| {
+    ids
+      .iter()
+      .filter(|id| dls.get(id).map(|dl| dl.status == status).unwrap_or(false))
+      .count()
+  };
+  let (done, waiting, scheduled) = (
+    count(DlStatus::Done),
+    count(DlStatus::Planned),
+    count(DlStatus::Scheduled),
+  );
+  println!("{} of {} tracks were downloaded.", done, ids.len());
+  if waiting > 0 {
+    println!(
+      "{} tracks are waiting for your instance to be reachable. Use `mobydick-cli queue resume` later.",
+      waiting
+    );
+  }
+  if scheduled > 0 {
+    println!(
+      "{} tracks are waiting for the download window. Use `mobydick-cli schedule run` once it is open.",
+      scheduled
+    );
+  }
+  if done + waiting + scheduled < ids.len() {
+    return Err(format!(
+      "{} downloads failed.",
+      ids.len() - done - waiting - scheduled
+    ));
+  }
+  Ok(())
+}
+
+fn queue() {
+  let mut dls: Vec<Download> = DOWNLOADS.lock().unwrap().values().cloned().collect();
+  if dls.is_empty() {
+    println!("Nothing was downloaded yet.");
+  }
+  dls.sort_by(|a, b| a.output.cmp(&b.output));
+  for dl in dls {
+    let status = match dl.status {
+      DlStatus::Planned => "waiting",
+      DlStatus::Scheduled => "scheduled",
+      DlStatus::Started => "interrupted",
+      DlStatus::Done => "done",
+      DlStatus::Cancelled => "cancelled",
+      DlStatus::Failed => "failed",
+    };
+    println!("{:<11}  {}", status, dl.output.display());
+    if let Some(error) = dl.error {
+      println!("{:<11}  because {}", "", error);
+    }
+    if let Some(error) = dl.hook_error {
+      println!("{:<11}  but {}", "", error);
+    }
+  }
+}
+
+/// Sends the plays that were not sent yet, and shows the latest ones.
+fn listenings(client: &RequestContext) -> Result<(), String> {
+  history::flush();
+  let me: api::UserInfo = api::send(client.api_get("/users/users/me"))
+    .and_then(|r| r.json())
+    .map_err(|e| e.to_string())?;
+  let page: api::Page<api::Played> = api::send(
+    client
+      .api_get("/history/listenings/")
+      .query(&[("username", me.username.as_str()), ("page_size", HISTORY_SIZE)])
+      .uncached(),
+  )
+  .and_then(|r| r.json())
+  .map_err(|e| e.to_string())?;
+
+  if page.results.is_empty() {
+    println!("You didn't listen to anything yet.");
+  }
+  for played in page.results {
+    println!(
+      "{}  {}, by {}",
+      played.creation_date.get(..16).unwrap_or("").replace('T', " "),
+      played.track.title,
+      played.track.artist.name
+    );
+  }
+  Ok(())
+}
+
+fn rebuild() -> Result<(), String> {
+  println!("Reading your downloads again, this can take a while…");
+  let dls: Vec<Download> = DOWNLOADS.lock().unwrap().values().cloned().collect();
+  let rebuilt = db::rebuild(&dls).map_err(|e| format!("Couldn't rebuild the database. {}", e))?;
+  println!(
+    "{} files updated, {} added, {} deleted files forgotten.",
+    rebuilt.updated, rebuilt.added, rebuilt.removed
+  );
+  Ok(())
+}
+
+fn scan(dir: &Path) -> Result<(), String> {
+  if !dir.is_dir() {
+    return Err(format!("{} is not a folder.", dir.display()));
+  }
+  let scanned = scan::scan(dir, |done, total| {
+    print!("\r[{}/{}]", done, total);
+    io::stdout().flush().ok();
+  })
+  .map_err(|e| format!("Couldn't scan {}. {}", dir.display(), e))?;
+  println!();
+  println!(
+    "{} files indexed, {} unchanged, {} unreadable, {} deleted files forgotten.",
+    scanned.indexed, scanned.unchanged, scanned.unreadable, scanned.removed
+  );
+  Ok(())
+}
+
+fn absolute(dir: &str) -> PathBuf {
+  let dir = Path::new(dir);
+  if dir.is_absolute() {
+    dir.to_path_buf()
+  } else {
+    env::current_dir().unwrap_or_default().join(dir)
+  }
+}
+
+fn mirrors() {
+  let mirrors = sync::list();
+  if mirrors.is_empty() {
+    println!("No folder is synced. Use `mobydick-cli sync add` to sync one.");
+  }
+  for mirror in mirrors {
+    let sources: Vec<String> = mirror.sources.iter().map(Source::to_string).collect();
+    println!("{}  {}", mirror.dir.display(), sources.join(" "));
+  }
+}
+
+fn sync_dirs(client: &RequestContext, dirs: &[PathBuf], delete: bool, dry_run: bool) -> Result<(), String> {
+  let mut ids = vec![];
+  for dir in dirs {
+    println!("Syncing {}…", dir.display());
+    let changes = sync::run(client, dir, delete, dry_run)?;
+    for dl in &changes.new {
+      println!("  new      {}", dl.output.display());
+    }
+    for file in &changes.copied {
+      println!("  copied   {}", file.display());
+    }
+    for (from, to) in &changes.moved {
+      println!("  moved    {} → {}", from.display(), to.display());
+    }
+    for file in &changes.removed {
+      println!("  {}  {}", if delete { "deleted" } else { "stale  " }, file.display());
+    }
+    println!(
+      "  {} up to date, {} new, {} copied, {} moved, {} not in the selection anymore",
+      changes.up_to_date,
+      changes.new.len(),
+      changes.copied.len(),
+      changes.moved.len(),
+      changes.removed.len()
+    );
+    if !delete && !changes.removed.is_empty() {
+      println!("  Use --delete to delete the files that are not in the selection anymore.");
+    }
+    if dry_run {
+      println!("  Nothing was changed (dry run).");
+    } else {
+      ids.extend(changes.new.iter().map(|dl| dl.track.id));
+    }
+  }
+
+  if ids.is_empty() {
+    return Ok(());
+  }
+  println!("Downloading {} tracks…", ids.len());
+  show_progress(ids.clone());
+  downloads::wait();
+  downloads::save();
+  report(&ids)
+}
+
+fn schedule_add(args: &[String]) -> Result<(), String> {
+  let mut sources = vec![];
+  let mut every = None;
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    if arg == "--every" {
+      every = Some(args.next().ok_or_else(|| USAGE.to_string())?);
+    } else {
+      sources.push(arg.parse::<Source>()?);
+    }
+  }
+  if sources.is_empty() {
+    return Err(USAGE.to_string());
+  }
+  let (next, repeat) = match every.map(String::as_str) {
+    None => (schedule::tonight(), Repeat::Once),
+    Some("day") => (schedule::tonight(), Repeat::Daily),
+    Some("week") => (schedule::tonight(), Repeat::Weekly),
+    Some(day) => {
+      let day = day
+        .parse::<Weekday>()
+        .map_err(|_| format!("`{}` is not a day, a week or a weekday.", day))?;
+      (schedule::next(day), Repeat::Weekly)
+    }
+  };
+  for source in sources {
+    let job = schedule::add(source, next, repeat);
+    println!(
+      "{} will be downloaded on {} ({}).",
+      job.source,
+      job.next.format("%A %H:%M"),
+      job.id
+    );
+  }
+  Ok(())
+}
+
+fn jobs() {
+  let jobs = schedule::jobs();
+  if jobs.is_empty() {
+    println!("Nothing is scheduled. Use `mobydick-cli schedule add` to schedule a download.");
+  }
+  for job in jobs {
+    let repeat = match job.repeat {
+      Repeat::Once => "once",
+      Repeat::Daily => "every day",
+      Repeat::Weekly => "every week",
+    };
+    println!(
+      "{:>3}  {}  {:<10}  {}",
+      job.id,
+      job.next.format("%Y-%m-%d %H:%M"),
+      repeat,
+      job.source
+    );
+  }
+}
+
+/// Queues the jobs that are due, and downloads everything that is scheduled
+/// if the window is open.
+fn run_schedule() -> Result<(), String> {
+  let before: HashMap<i32, DlStatus> = DOWNLOADS
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(id, dl)| (*id, dl.status.clone()))
+    .collect();
+  schedule::check();
+  // The tracks that were just queued, or that were waiting for the window
+  let ids: Vec<i32> = DOWNLOADS
+    .lock()
+    .unwrap()
+    .values()
+    .filter(|dl| match before.get(&dl.track.id) {
+      None => true,
+      Some(DlStatus::Scheduled) => dl.status != DlStatus::Scheduled,
+      Some(_) => false,
+    })
+    .map(|dl| dl.track.id)
+    .collect();
+  if ids.is_empty() {
+    if !schedule::is_open() {
+      println!("The download window is closed.");
+    }
+    println!("Nothing to download.");
+    return Ok(());
+  }
+  println!("Downloading {} tracks…", ids.len());
+  show_progress(ids.clone());
+  downloads::wait();
+  downloads::save();
+  report(&ids)
+}
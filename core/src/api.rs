@@ -0,0 +1,628 @@
+use lazy_static::*;
+use reqwest::r#async::Client;
+use serde_derive::*;
+use std::sync::{Arc, Mutex};
+
+pub mod cache;
+mod call;
+pub mod compat;
+pub mod network;
+pub mod nodeinfo;
+
+pub use call::*;
+pub use compat::ApiVersion;
+
+lazy_static! {
+  pub static ref API: Arc<Mutex<Option<RequestContext>>> = Arc::new(Mutex::new(None));
+}
+
+#[derive(Clone)]
+pub struct RequestContext {
+  token: String,
+  /// Empty until we know it
+  username: String,
+  instance: String,
+  version: ApiVersion,
+  /// For the instance, trusting its pinned certificate if there is one
+  client: Client,
+  /// For other hosts, like the ones covers are stored on
+  others: Client,
+  cache: Arc<cache::Cache>,
+}
+
+fn host(url: &str) -> Option<String> {
+  reqwest::Url::parse(url)
+    .ok()
+    .and_then(|u| u.host_str().map(String::from))
+}
+
+impl RequestContext {
+  /// Fails if the network settings are invalid.
+  pub fn new(instance: String) -> Result<Self, network::SettingsError> {
+    let settings = network::NetworkSettings::load();
+    let host = host(&instance).unwrap_or_default();
+    Ok(RequestContext {
+      token: String::new(),
+      username: String::new(),
+      instance,
+      version: ApiVersion::default(),
+      client: settings.client_for(&host)?,
+      others: settings.client()?,
+      cache: cache::Cache::shared(),
+    })
+  }
+
+  pub fn auth(&mut self, token: String) {
+    self.token = token;
+  }
+
+  pub fn set_username(&mut self, username: String) {
+    self.username = username;
+  }
+
+  pub fn username(&self) -> &str {
+    &self.username
+  }
+
+  /// The base URL of the instance
+  pub fn instance(&self) -> &str {
+    &self.instance
+  }
+
+  pub fn negotiate(&mut self, version: ApiVersion) {
+    self.version = version;
+  }
+
+  pub fn version(&self) -> ApiVersion {
+    self.version
+  }
+
+  /// Builds a full URL from an absolute one, or a path on the instance
+  fn url(&self, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+      url.to_string()
+    } else {
+      format!("{}{}", self.instance, url)
+    }
+  }
+
+  /// The client for a full URL
+  fn client(&self, url: &str) -> &Client {
+    if host(url) == host(&self.instance) {
+      &self.client
+    } else {
+      &self.others
+    }
+  }
+
+  fn authenticated(&self, method: reqwest::Method, url: &str) -> reqwest::r#async::RequestBuilder {
+    let url = self.url(url);
+    self.client(&url).request(method, &url).header(
+      reqwest::header::AUTHORIZATION,
+      format!("JWT {}", self.token),
+    )
+  }
+
+  pub fn get<S: AsRef<str>>(&self, url: S) -> Request {
+    Request::new(
+      self.client(&self.url(url.as_ref())).clone(),
+      self.authenticated(reqwest::Method::GET, url.as_ref()),
+      Some((self.cache.clone(), format!("{}@{}", self.username, self.instance))),
+    )
+  }
+
+  /// GET an API endpoint (like `/albums/`), using the API version of the instance
+  pub fn api_get<S: AsRef<str>>(&self, path: S) -> Request {
+    self.get(format!("{}{}", self.version.prefix(), path.as_ref()))
+  }
+
+  /// Authenticated request to an API endpoint, that is never cached
+  fn api_write(&self, method: reqwest::Method, path: &str) -> Request {
+    Request::new(
+      self.client.clone(),
+      self.authenticated(method, &format!("{}{}", self.version.prefix(), path)),
+      None,
+    )
+  }
+
+  /// Authenticated POST to an API endpoint
+  pub fn api_post<S: AsRef<str>>(&self, path: S) -> Request {
+    self.api_write(reqwest::Method::POST, path.as_ref())
+  }
+
+  /// Authenticated PATCH to an API endpoint
+  pub fn api_patch<S: AsRef<str>>(&self, path: S) -> Request {
+    self.api_write(reqwest::Method::PATCH, path.as_ref())
+  }
+
+  /// Authenticated DELETE to an API endpoint
+  pub fn api_delete<S: AsRef<str>>(&self, path: S) -> Request {
+    self.api_write(reqwest::Method::DELETE, path.as_ref())
+  }
+
+  /// Follows the pages of a list until its end.
+  ///
+  /// This is blocking.
+  pub fn all_pages<T: serde::de::DeserializeOwned>(&self, first: Request) -> Result<Vec<T>, Error> {
+    self.first_items(first, usize::MAX)
+  }
+
+  /// Follows the pages of a list until `limit` items were fetched, or until
+  /// its end.
+  ///
+  /// This is blocking.
+  pub fn first_items<T: serde::de::DeserializeOwned>(
+    &self,
+    first: Request,
+    limit: usize,
+  ) -> Result<Vec<T>, Error> {
+    let mut req = first;
+    let mut items = vec![];
+    loop {
+      let page: Page<T> = send(req.uncached())?.json()?;
+      items.extend(page.results);
+      match page.next {
+        Some(next) if items.len() < limit => req = self.get(next),
+        _ => break,
+      }
+    }
+    items.truncate(limit);
+    Ok(items)
+  }
+
+  /// All the tracks of a playlist, in order.
+  ///
+  /// This is blocking.
+  pub fn playlist_tracks(&self, id: i32) -> Result<Vec<PlaylistTrack>, Error> {
+    let mut tracks: Vec<PlaylistTrack> = self.all_pages(
+      self
+        .api_get(format!("/playlists/{}/tracks/", id))
+        .query(&[("page_size", "100")]),
+    )?;
+    tracks.sort_by_key(|t| t.index);
+    Ok(tracks)
+  }
+
+  /// Fetches an album with its tracks.
+  ///
+  /// Since Funkwhale 1.0, they are not part of the album details anymore, and
+  /// have to be requested separately.
+  ///
+  /// This is blocking.
+  pub fn album(&self, id: i32) -> Result<Album, Error> {
+    let mut album: Album = send(self.api_get(format!("/albums/{}/", id)))?.json()?;
+    if !self.version.embeds_album_tracks() {
+      album.tracks = Some(
+        self.all_pages(
+          self
+            .api_get("/tracks/")
+            .query(&[("album", id.to_string()), ("page_size", "100".to_string())]),
+        )?,
+      );
+    }
+    Ok(album)
+  }
+
+  /// The albums of an artist, when they were not included in search results.
+  ///
+  /// This is blocking.
+  pub fn artist_albums(&self, id: i32) -> Result<Vec<ArtistAlbum>, Error> {
+    self.all_pages(
+      self
+        .api_get("/albums/")
+        .query(&[("artist", id.to_string()), ("page_size", "100".to_string())]),
+    )
+  }
+
+  /// Warning: no authentication, since it is only used for login
+  pub fn post<S: AsRef<str>>(&self, url: S) -> Request {
+    let url = self.url(url.as_ref());
+    let client = self.client(&url);
+    Request::new(client.clone(), client.post(&url), None)
+  }
+
+  pub fn cache(&self) -> &cache::Cache {
+    &self.cache
+  }
+
+  pub fn to_json(&self) -> serde_json::Value {
+    serde_json::json!({
+        "token": self.token,
+        "username": self.username,
+        "instance": self.instance,
+        "api_version": self.version,
+    })
+  }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LoginData {
+  pub password: String,
+  pub username: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LoginInfo {
+  pub token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UserInfo {
+  pub username: String,
+  #[serde(deserialize_with = "compat::image", default)]
+  pub avatar: Image,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Image {
+  pub medium_square_crop: Option<String>,
+  pub small_square_crop: Option<String>,
+  pub original: Option<String>,
+  pub square_crop: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SearchQuery {
+  pub query: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SearchResult {
+  pub artists: Vec<Artist>,
+  pub albums: Vec<Album>,
+  pub tracks: Vec<Track>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Artist {
+  pub id: i32,
+  pub name: String,
+  pub albums: Option<Vec<ArtistAlbum>>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+}
+
+/// A paginated list
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Page<T> {
+  pub count: i32,
+  pub next: Option<String>,
+  pub results: Vec<T>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Album {
+  pub title: String,
+  pub artist: ArtistPreview,
+  pub tracks: Option<Vec<AlbumTrack>>,
+  pub cover: Image,
+  pub id: i32,
+  pub tags: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ArtistAlbum {
+  pub title: String,
+  #[serde(default)]
+  pub tracks_count: i32,
+  pub id: i32,
+  #[serde(deserialize_with = "compat::image", default)]
+  pub cover: Image,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Track {
+  pub id: i32,
+  pub title: String,
+  pub album: Album,
+  pub artist: ArtistPreview,
+  pub listen_url: String,
+  pub tags: Vec<String>,
+  /// MusicBrainz ID of the recording
+  pub mbid: Option<String>,
+  /// In seconds
+  pub duration: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ArtistPreview {
+  pub name: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AlbumTrack {
+  pub id: i32,
+  pub title: String,
+  pub artist: ArtistPreview,
+  pub listen_url: String,
+  pub tags: Vec<String>,
+  pub mbid: Option<String>,
+  pub duration: Option<u64>,
+}
+
+impl AlbumTrack {
+  pub fn into_full(self, album: &Album) -> Track {
+    let mut album = album.clone();
+    album.tracks = None;
+    Track {
+      album,
+      id: self.id,
+      title: self.title,
+      artist: self.artist,
+      listen_url: self.listen_url,
+      tags: self.tags,
+      mbid: self.mbid,
+      duration: self.duration,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Favorite {
+  pub id: i32,
+  pub track: Track,
+}
+
+/// From `/favorites/tracks/all/`, that only lists IDs
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FavoriteIds {
+  pub results: Vec<FavoriteTrackId>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FavoriteTrackId {
+  pub track: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TrackRef {
+  pub track: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Playlist {
+  pub id: i32,
+  pub name: String,
+  pub user: PlaylistOwner,
+  #[serde(default)]
+  pub tracks_count: i32,
+  pub privacy_level: String,
+  pub modification_date: String,
+  #[serde(default)]
+  pub album_covers: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistOwner {
+  pub username: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistTrack {
+  pub index: i32,
+  pub track: Track,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistData {
+  pub name: String,
+  pub privacy_level: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistAdd {
+  pub tracks: Vec<i32>,
+  pub allow_duplicates: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistMove {
+  pub from: i32,
+  pub to: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaylistRemove {
+  pub index: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Library {
+  pub uuid: String,
+  pub name: String,
+  pub privacy_level: String,
+  #[serde(default)]
+  pub uploads_count: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UploadInfo {
+  pub uuid: String,
+  pub import_status: String,
+  #[serde(default)]
+  pub import_details: Option<serde_json::Value>,
+}
+
+impl UploadInfo {
+  /// Why the import failed, if it did
+  pub fn error(&self) -> Option<String> {
+    if self.import_status != "errored" {
+      return None;
+    }
+    let details = self.import_details.as_ref();
+    Some(
+      details
+        .and_then(|d| d["detail"].as_str().or_else(|| d["error_code"].as_str()))
+        .map(String::from)
+        .or_else(|| details.map(|d| d.to_string()))
+        .unwrap_or_else(|| "Unknown error".to_string()),
+    )
+  }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Channel {
+  pub uuid: String,
+  pub artist: ChannelArtist,
+  #[serde(default)]
+  pub rss_url: Option<String>,
+  #[serde(default)]
+  pub subscriptions_count: i32,
+}
+
+impl Channel {
+  pub fn is_podcast(&self) -> bool {
+    self.artist.content_category == "podcast"
+  }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ChannelArtist {
+  pub id: i32,
+  pub name: String,
+  /// `music`, `podcast` or `other`
+  #[serde(default)]
+  pub content_category: String,
+  #[serde(deserialize_with = "compat::image", default)]
+  pub cover: Image,
+}
+
+/// A track published in a channel, like a podcast episode. Unlike other
+/// tracks, it may not be part of an album.
+#[derive(Serialize, Debug, Clone)]
+pub struct Episode {
+  pub id: i32,
+  pub title: String,
+  pub listen_url: String,
+  /// When it was published, as an ISO 8601 date
+  pub creation_date: String,
+  pub cover: Image,
+}
+
+impl Episode {
+  /// The channel is used as the album and the artist of the episode.
+  pub fn into_track(self, channel: &Channel) -> Track {
+    let artist = ArtistPreview {
+      name: channel.artist.name.clone(),
+    };
+    Track {
+      id: self.id,
+      title: self.title,
+      listen_url: self.listen_url,
+      album: Album {
+        id: 0,
+        title: channel.artist.name.clone(),
+        artist: artist.clone(),
+        tracks: None,
+        cover: if self.cover.medium_square_crop.is_some() {
+          self.cover
+        } else {
+          channel.artist.cover.clone()
+        },
+        tags: vec![],
+      },
+      artist,
+      tags: vec![],
+      mbid: None,
+      duration: None,
+    }
+  }
+}
+
+/// From `/subscriptions/all/`, that only lists channel UUIDs
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SubscriptionIds {
+  pub results: Vec<SubscriptionId>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SubscriptionId {
+  pub channel: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RadioSessionRequest {
+  /// `random`, `favorites`, `less-listened`, `tag`, `artist` or `custom`
+  pub radio_type: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub related_object_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub custom_radio: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RadioSession {
+  pub id: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RadioTrackRequest {
+  pub session: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RadioTrack {
+  pub track: Track,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CustomRadio {
+  pub id: i32,
+  pub name: String,
+}
+
+/// A play of a track, for the listening history
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Listening {
+  pub track: i32,
+  /// When it was played, as an ISO 8601 date
+  pub creation_date: String,
+}
+
+/// A play from the listening history, as the instance lists it
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Played {
+  pub track: Track,
+  pub creation_date: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Tag {
+  pub name: String,
+}
+
+/// A library of another user, maybe on another instance
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RemoteLibrary {
+  pub uuid: String,
+  /// ActivityPub ID
+  pub fid: String,
+  pub name: String,
+  #[serde(default)]
+  pub uploads_count: i32,
+  #[serde(default)]
+  pub actor: RemoteActor,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RemoteActor {
+  pub full_username: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LibraryFollow {
+  pub uuid: String,
+  /// `None` while the owner of the library didn't answer
+  pub approved: Option<bool>,
+  pub target: RemoteLibrary,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FetchRequest {
+  pub fid: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FollowRequest {
+  pub target: String,
+}
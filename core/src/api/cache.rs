@@ -0,0 +1,258 @@
+//! An on-disk HTTP cache for API responses and images.
+//!
+//! Responses are kept according to their `Cache-Control` header, and
+//! revalidated with `If-None-Match` or `If-Modified-Since` once they expired.
+//! When the cache grows too big, the least recently used entries are removed.
+//!
+//! Entries are found by a key made of the URL and of the account that asked
+//! for it, see `call::execute`.
+
+use lazy_static::*;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde_derive::*;
+use std::{
+  collections::HashMap,
+  fs,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Default maximum size of the cache, in bytes
+pub const DEFAULT_MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+lazy_static! {
+  static ref SHARED: Arc<Cache> = Arc::new(Cache::open(
+    dirs::cache_dir().unwrap().join("mobydick").join("http"),
+    DEFAULT_MAX_SIZE,
+  ));
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Entry {
+  file: String,
+  etag: Option<String>,
+  last_modified: Option<String>,
+  /// When this entry should be revalidated (UNIX timestamp)
+  expires: u64,
+  size: u64,
+  last_used: u64,
+  headers: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+  entries: HashMap<String, Entry>,
+  next_file: u64,
+}
+
+/// A response we have on disk.
+pub struct Cached {
+  pub body: Vec<u8>,
+  pub headers: HeaderMap,
+  /// If false, it should be revalidated before being used
+  pub fresh: bool,
+  etag: Option<String>,
+  last_modified: Option<String>,
+}
+
+impl Cached {
+  /// Makes a request conditional, so that the server can answer
+  /// "304 Not Modified" instead of sending the same thing again.
+  pub fn add_validators(&self, headers: &mut HeaderMap) {
+    if let Some(val) = self.etag.as_ref().and_then(|e| HeaderValue::from_str(e).ok()) {
+      headers.insert(header::IF_NONE_MATCH, val);
+    }
+    if let Some(val) = self
+      .last_modified
+      .as_ref()
+      .and_then(|l| HeaderValue::from_str(l).ok())
+    {
+      headers.insert(header::IF_MODIFIED_SINCE, val);
+    }
+  }
+}
+
+pub struct Cache {
+  dir: PathBuf,
+  max_size: Mutex<u64>,
+  index: Mutex<Index>,
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default()
+}
+
+/// How long a response can be used without revalidating it, or `None` if it
+/// should not be stored at all.
+fn freshness(headers: &HeaderMap) -> Option<u64> {
+  let cache_control = headers
+    .get(header::CACHE_CONTROL)
+    .and_then(|h| h.to_str().ok())
+    .unwrap_or_default()
+    .to_lowercase();
+  let mut max_age = None;
+  for directive in cache_control.split(',').map(str::trim) {
+    if directive == "no-store" {
+      return None;
+    } else if directive == "no-cache" {
+      max_age = Some(0);
+    } else if directive.starts_with("max-age=") && max_age.is_none() {
+      max_age = directive["max-age=".len()..].parse().ok();
+    }
+  }
+
+  let has_validators =
+    headers.contains_key(header::ETAG) || headers.contains_key(header::LAST_MODIFIED);
+  match max_age {
+    Some(age) if age > 0 || has_validators => Some(age),
+    None if has_validators => Some(0),
+    _ => None,
+  }
+}
+
+impl Cache {
+  /// The cache shared by all the `RequestContext`s
+  pub fn shared() -> Arc<Cache> {
+    SHARED.clone()
+  }
+
+  pub fn open(dir: PathBuf, max_size: u64) -> Cache {
+    let index = fs::read(dir.join("index.json"))
+      .ok()
+      .and_then(|f| serde_json::from_slice(&f).ok())
+      .unwrap_or_default();
+    Cache {
+      dir,
+      max_size: Mutex::new(max_size),
+      index: Mutex::new(index),
+    }
+  }
+
+  pub fn set_max_size(&self, max_size: u64) {
+    *self.max_size.lock().unwrap() = max_size;
+    let mut index = self.index.lock().unwrap();
+    self.evict(&mut index);
+    self.save(&index);
+  }
+
+  pub fn lookup(&self, key: &str) -> Option<Cached> {
+    let mut index = self.index.lock().unwrap();
+    let entry = index.entries.get_mut(key)?;
+    let body = fs::read(self.dir.join(&entry.file)).ok()?;
+    entry.last_used = now();
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in entry.headers.iter() {
+      if let (Ok(name), Ok(value)) = (
+        HeaderName::from_bytes(name.as_bytes()),
+        HeaderValue::from_str(value),
+      ) {
+        headers.insert(name, value);
+      }
+    }
+
+    Some(Cached {
+      body,
+      headers,
+      fresh: entry.expires > now(),
+      etag: entry.etag.clone(),
+      last_modified: entry.last_modified.clone(),
+    })
+  }
+
+  /// Saves a response, if its headers allow it.
+  pub fn store(&self, key: &str, headers: &HeaderMap, body: &[u8]) {
+    let max_age = match freshness(headers) {
+      Some(age) => age,
+      None => return,
+    };
+
+    let mut index = self.index.lock().unwrap();
+    let file = match index.entries.get(key) {
+      Some(entry) => entry.file.clone(),
+      None => {
+        index.next_file += 1;
+        index.next_file.to_string()
+      }
+    };
+    if fs::create_dir_all(&self.dir).is_err() || fs::write(self.dir.join(&file), body).is_err() {
+      return;
+    }
+
+    let header_str = |name: HeaderName| {
+      headers
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .map(String::from)
+    };
+    index.entries.insert(
+      key.to_string(),
+      Entry {
+        file,
+        etag: header_str(header::ETAG),
+        last_modified: header_str(header::LAST_MODIFIED),
+        expires: now() + max_age,
+        size: body.len() as u64,
+        last_used: now(),
+        headers: headers
+          .iter()
+          .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+          .collect(),
+      },
+    );
+    self.evict(&mut index);
+    self.save(&index);
+  }
+
+  /// The server told us our copy is still valid.
+  pub fn revalidated(&self, key: &str, headers: &HeaderMap) {
+    let mut index = self.index.lock().unwrap();
+    if let Some(entry) = index.entries.get_mut(key) {
+      entry.expires = now() + freshness(headers).unwrap_or_default();
+      entry.last_used = now();
+    }
+    self.save(&index);
+  }
+
+  /// Removes everything, for instance when logging out.
+  pub fn clear(&self) {
+    let mut index = self.index.lock().unwrap();
+    *index = Index::default();
+    fs::remove_dir_all(&self.dir).ok();
+  }
+
+  fn evict(&self, index: &mut Index) {
+    let max_size = *self.max_size.lock().unwrap();
+    let mut total: u64 = index.entries.values().map(|e| e.size).sum();
+    if total <= max_size {
+      return;
+    }
+
+    let mut by_age: Vec<(String, u64, u64)> = index
+      .entries
+      .iter()
+      .map(|(url, e)| (url.clone(), e.last_used, e.size))
+      .collect();
+    by_age.sort_by_key(|&(_, last_used, _)| last_used);
+    for (url, _, size) in by_age {
+      if total <= max_size {
+        break;
+      }
+      if let Some(entry) = index.entries.remove(&url) {
+        fs::remove_file(self.dir.join(entry.file)).ok();
+      }
+      total -= size;
+    }
+  }
+
+  fn save(&self, index: &Index) {
+    fs::create_dir_all(&self.dir).ok();
+    if let Ok(json) = serde_json::to_vec(index) {
+      fs::write(self.dir.join("index.json"), json).ok();
+    }
+  }
+}
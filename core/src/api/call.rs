@@ -0,0 +1,488 @@
+//! Asynchronous requests.
+//!
+//! Requests run on a small Tokio runtime. Their result can either be waited
+//! for (from a worker thread), or handed to a callback (front-ends use it to
+//! get it back on their main loop).
+
+use futures::{
+  future::{self, Either},
+  stream,
+  sync::oneshot,
+  Future, Stream,
+};
+use lazy_static::*;
+use super::cache::Cache;
+use reqwest::{
+  header::{HeaderMap, HeaderName},
+  r#async::{
+    multipart::{Form, Part},
+    Client, RequestBuilder,
+  },
+  Method, StatusCode,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  fmt,
+  fs::File,
+  io,
+  io::{Read, Write},
+  path::Path,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+  },
+  thread,
+  time::Duration,
+};
+use tokio::{runtime::Runtime, timer::Timeout};
+
+/// How long we wait for an API response
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a download may stall before we give up
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static! {
+  static ref RUNTIME: Mutex<Runtime> =
+    Mutex::new(Runtime::new().expect("Couldn't start the network runtime"));
+}
+
+fn spawn<F: Future<Item = (), Error = ()> + Send + 'static>(f: F) {
+  RUNTIME.lock().unwrap().executor().spawn(f);
+}
+
+#[derive(Debug)]
+pub enum Error {
+  Http(reqwest::Error),
+  Json(serde_json::Error),
+  Io(io::Error),
+  Timeout,
+  Cancelled,
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::Http(e) => write!(f, "Network error: {}", e),
+      Error::Json(e) => write!(f, "Unexpected response from the server: {}", e),
+      Error::Io(e) => write!(f, "{}", e),
+      Error::Timeout => write!(f, "The server took too long to answer"),
+      Error::Cancelled => write!(f, "Cancelled"),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+  fn from(e: reqwest::Error) -> Self {
+    Error::Http(e)
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Self {
+    Error::Io(e)
+  }
+}
+
+impl<E: Into<Error>> From<tokio::timer::timeout::Error<E>> for Error {
+  fn from(e: tokio::timer::timeout::Error<E>) -> Self {
+    if e.is_elapsed() {
+      Error::Timeout
+    } else {
+      e.into_inner().map(Into::into).unwrap_or(Error::Timeout)
+    }
+  }
+}
+
+/// A response, with its body fully read.
+#[derive(Debug, Clone)]
+pub struct Response {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Vec<u8>,
+}
+
+impl Response {
+  pub fn status(&self) -> StatusCode {
+    self.status
+  }
+
+  pub fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  pub fn body(&self) -> &[u8] {
+    &self.body
+  }
+
+  pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+    serde_json::from_slice(&self.body).map_err(Error::Json)
+  }
+
+  pub fn copy_to<W: Write + ?Sized>(&self, w: &mut W) -> Result<u64, Error> {
+    w.write_all(&self.body)?;
+    Ok(self.body.len() as u64)
+  }
+}
+
+/// A request that is being built, see `RequestContext::get`.
+pub struct Request {
+  client: Client,
+  builder: RequestBuilder,
+  /// With the account the response is for: the same URL gives different
+  /// answers to different users
+  cache: Option<(Arc<Cache>, String)>,
+}
+
+impl Request {
+  pub(super) fn new(
+    client: Client,
+    builder: RequestBuilder,
+    cache: Option<(Arc<Cache>, String)>,
+  ) -> Self {
+    Request {
+      client,
+      builder,
+      cache,
+    }
+  }
+
+  pub fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Self {
+    self.builder = self.builder.query(query);
+    self
+  }
+
+  pub fn json<T: Serialize + ?Sized>(mut self, json: &T) -> Self {
+    self.builder = self.builder.json(json);
+    self
+  }
+
+  /// Adds a header, like `Range`
+  pub fn header(mut self, name: HeaderName, value: &str) -> Self {
+    self.builder = self.builder.header(name, value);
+    self
+  }
+
+  pub fn multipart(mut self, form: Form) -> Self {
+    self.builder = self.builder.multipart(form);
+    self
+  }
+
+  /// Don't read or store the response in the HTTP cache
+  pub fn uncached(mut self) -> Self {
+    self.cache = None;
+    self
+  }
+}
+
+/// Stops a request. Dropping it does *not* cancel anything.
+#[derive(Clone)]
+pub struct Cancel {
+  cancelled: Arc<AtomicBool>,
+  tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+}
+
+impl Cancel {
+  fn new() -> (Cancel, impl Future<Item = (), Error = ()>) {
+    let (tx, rx) = oneshot::channel();
+    (
+      Cancel {
+        cancelled: Arc::new(AtomicBool::new(false)),
+        tx: Arc::new(Mutex::new(Some(tx))),
+      },
+      // If the sender is dropped without cancelling, never resolve
+      rx.or_else(|_| future::empty()),
+    )
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::SeqCst);
+    if let Some(tx) = self.tx.lock().unwrap().take() {
+      tx.send(()).ok();
+    }
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst)
+  }
+}
+
+/// Something that is being computed in the background.
+pub struct Call<T> {
+  rx: oneshot::Receiver<T>,
+  cancel: Cancel,
+}
+
+impl<T: Send + 'static> Call<T> {
+  pub fn cancel_handle(&self) -> Cancel {
+    self.cancel.clone()
+  }
+
+  /// Calls `f` with the result, from a network thread. It is never called if
+  /// the call was cancelled.
+  pub fn then<F: FnOnce(T) + Send + 'static>(self, f: F) {
+    let cancel = self.cancel;
+    spawn(
+      self
+        .rx
+        .map(move |res| {
+          if !cancel.is_cancelled() {
+            f(res)
+          }
+        })
+        .map_err(|_| ()),
+    );
+  }
+
+  /// Blocks until the result is there. Returns `None` if the call was cancelled.
+  pub fn wait(self) -> Option<T> {
+    self.rx.wait().ok()
+  }
+}
+
+/// Sends a request and reads its response.
+///
+/// GET requests go through the HTTP cache: fresh responses are returned
+/// without touching the network, and stale ones are revalidated.
+pub fn execute(req: Request) -> Call<Result<Response, Error>> {
+  let (tx, rx) = oneshot::channel();
+  let (cancel, cancelled) = Cancel::new();
+
+  let mut request = match req.builder.build() {
+    Ok(r) => r,
+    Err(e) => {
+      tx.send(Err(Error::Http(e))).ok();
+      return Call { rx, cancel };
+    }
+  };
+  let (cache, key) = match req.cache.filter(|_| *request.method() == Method::GET) {
+    Some((cache, account)) => (Some(cache), format!("{} {}", account, request.url())),
+    None => (None, String::new()),
+  };
+  let cached = cache.as_ref().and_then(|c| c.lookup(&key));
+  match cached {
+    Some(ref cached) if cached.fresh => {
+      tx.send(Ok(Response {
+        status: StatusCode::OK,
+        headers: cached.headers.clone(),
+        body: cached.body.clone(),
+      }))
+      .ok();
+      return Call { rx, cancel };
+    }
+    Some(ref cached) => cached.add_validators(request.headers_mut()),
+    None => {}
+  }
+
+  let res = req.client.execute(request).and_then(read_body);
+  let res = Timeout::new(res, TIMEOUT)
+    .map_err(Error::from)
+    .map(move |res| match (cache, cached) {
+      (Some(cache), Some(cached)) if res.status == StatusCode::NOT_MODIFIED => {
+        cache.revalidated(&key, &res.headers);
+        Response {
+          status: StatusCode::OK,
+          headers: cached.headers,
+          body: cached.body,
+        }
+      }
+      (Some(cache), _) => {
+        if res.status.is_success() {
+          cache.store(&key, &res.headers, &res.body);
+        }
+        res
+      }
+      (None, _) => res,
+    });
+
+  spawn(res.select2(cancelled).then(move |res| {
+    match res {
+      Ok(Either::A((res, _))) => tx.send(Ok(res)).ok(),
+      Err(Either::A((err, _))) => tx.send(Err(err)).ok(),
+      // Cancelled: nobody is waiting for the result anymore
+      Ok(Either::B(_)) | Err(Either::B(_)) => None,
+    };
+    Ok(())
+  }));
+
+  Call { rx, cancel }
+}
+
+fn read_body(res: reqwest::r#async::Response) -> impl Future<Item = Response, Error = reqwest::Error> {
+  let status = res.status();
+  let headers = res.headers().clone();
+  res
+    .into_body()
+    .fold(Vec::new(), |mut body, chunk| {
+      body.extend_from_slice(&chunk);
+      Ok::<_, reqwest::Error>(body)
+    })
+    .map(move |body| Response {
+      status,
+      headers,
+      body,
+    })
+}
+
+/// Sends a request with a large body, like a file upload.
+///
+/// Unlike `execute`, there is no overall timeout, since sending the body may
+/// take a long time. It never goes through the HTTP cache.
+pub fn upload(req: Request) -> Call<Result<Response, Error>> {
+  let (tx, rx) = oneshot::channel();
+  let (cancel, cancelled) = Cancel::new();
+
+  let res = req.builder.send().and_then(read_body).map_err(Error::from);
+  spawn(res.select2(cancelled).then(move |res| {
+    match res {
+      Ok(Either::A((res, _))) => tx.send(Ok(res)).ok(),
+      Err(Either::A((err, _))) => tx.send(Err(err)).ok(),
+      Ok(Either::B(_)) | Err(Either::B(_)) => None,
+    };
+    Ok(())
+  }));
+
+  Call { rx, cancel }
+}
+
+/// Reads a file chunk by chunk, telling how many bytes were read so far.
+struct FileChunks<F> {
+  file: File,
+  read: u64,
+  progress: F,
+}
+
+impl<F: Fn(u64)> Iterator for FileChunks<F> {
+  type Item = Result<Vec<u8>, io::Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let mut buf = vec![0; 64 * 1024];
+    match self.file.read(&mut buf) {
+      Ok(0) => None,
+      Ok(n) => {
+        buf.truncate(n);
+        self.read += n as u64;
+        (self.progress)(self.read);
+        Some(Ok(buf))
+      }
+      Err(e) => Some(Err(e)),
+    }
+  }
+}
+
+/// A multipart part with the content of a file, which is streamed instead of
+/// being loaded in memory.
+///
+/// `progress` is called with the number of bytes sent so far.
+pub fn file_part<F>(path: &Path, progress: F) -> io::Result<Part>
+where
+  F: Fn(u64) + Send + 'static,
+{
+  let chunks = FileChunks {
+    file: File::open(path)?,
+    read: 0,
+    progress,
+  };
+  let name = path
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default();
+  Ok(Part::stream(stream::iter_result(chunks)).file_name(name))
+}
+
+/// Sends a request and blocks until the response is there.
+pub fn send(req: Request) -> Result<Response, Error> {
+  execute(req).wait().unwrap_or(Err(Error::Cancelled))
+}
+
+/// Runs some blocking code on another thread.
+pub fn background<T, F>(f: F) -> Call<T>
+where
+  T: Send + 'static,
+  F: FnOnce() -> T + Send + 'static,
+{
+  let (tx, rx) = oneshot::channel();
+  let (cancel, _) = Cancel::new();
+  let cancelled = cancel.clone();
+  thread::spawn(move || {
+    // The code can't be stopped, but its result is dropped
+    let res = f();
+    if !cancelled.is_cancelled() {
+      tx.send(res).ok();
+    }
+  });
+  Call { rx, cancel }
+}
+
+/// A response whose body is read progressively, for large downloads.
+pub struct BodyStream {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+  chunks: mpsc::Receiver<Result<Vec<u8>, Error>>,
+}
+
+impl BodyStream {
+  /// Writes the body to `out`, checking regularly if we should stop.
+  pub fn copy_to<W: Write + ?Sized>(
+    self,
+    out: &mut W,
+    cancelled: &dyn Fn() -> bool,
+  ) -> Result<u64, Error> {
+    let mut written = 0;
+    for chunk in self.chunks.iter() {
+      if cancelled() {
+        return Err(Error::Cancelled);
+      }
+      let chunk = chunk?;
+      out.write_all(&chunk)?;
+      written += chunk.len() as u64;
+    }
+    Ok(written)
+  }
+}
+
+/// Sends a request and blocks until the headers of the response are there.
+///
+/// The body is then read chunk by chunk with `BodyStream::copy_to`. The
+/// request is aborted if it stalls, or if the `BodyStream` is dropped. It
+/// never goes through the HTTP cache.
+pub fn stream(req: Request) -> Result<BodyStream, Error> {
+  let (head_tx, head_rx) = oneshot::channel();
+  let (chunk_tx, chunk_rx) = mpsc::channel();
+
+  spawn(
+    Timeout::new(req.builder.send(), TIMEOUT)
+      .map_err(Error::from)
+      .then(move |res| match res {
+        Err(err) => {
+          head_tx.send(Err(err)).ok();
+          Either::A(future::ok::<(), ()>(()))
+        }
+        Ok(res) => {
+          head_tx.send(Ok((res.status(), res.headers().clone()))).ok();
+          let err_tx = chunk_tx.clone();
+          Either::B(
+            Timeout::new(res.into_body(), IDLE_TIMEOUT)
+              .map_err(Error::from)
+              .for_each(move |chunk| {
+                chunk_tx
+                  .send(Ok(chunk.to_vec()))
+                  .map_err(|_| Error::Cancelled)
+              })
+              .or_else(move |err| {
+                err_tx.send(Err(err)).ok();
+                Ok::<(), ()>(())
+              }),
+          )
+        }
+      }),
+  );
+
+  let (status, headers) = head_rx.wait().map_err(|_| Error::Cancelled)??;
+  Ok(BodyStream {
+    status,
+    headers,
+    chunks: chunk_rx,
+  })
+}
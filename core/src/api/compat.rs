@@ -0,0 +1,231 @@
+//! Deserialization of the various shapes Funkwhale gave to its API over time.
+//!
+//! The rest of the app only deals with the structures defined in `api`, and
+//! these raw structures are converted to them as soon as they are parsed.
+
+use super::{Album, AlbumTrack, ArtistPreview, Episode, Image, Track};
+use serde::{Deserialize, Deserializer};
+use serde_derive::*;
+
+/// The API flavours we know about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiVersion {
+  /// Funkwhale 0.x: album details embed their tracks, covers are plain images
+  #[default]
+  Legacy,
+  /// Funkwhale 1.x: covers are attachments, tracks have a list of uploads
+  V1,
+  /// Funkwhale 2.x: `/api/v2/`, artists are credited through `artist_credit`
+  V2,
+}
+
+impl ApiVersion {
+  pub fn from_version(version: &super::nodeinfo::Version) -> ApiVersion {
+    match version.major {
+      0 => ApiVersion::Legacy,
+      1 => ApiVersion::V1,
+      _ => ApiVersion::V2,
+    }
+  }
+
+  pub fn prefix(self) -> &'static str {
+    match self {
+      ApiVersion::Legacy | ApiVersion::V1 => "/api/v1",
+      ApiVersion::V2 => "/api/v2",
+    }
+  }
+
+  /// Whether album details include the list of their tracks
+  pub fn embeds_album_tracks(self) -> bool {
+    self == ApiVersion::Legacy
+  }
+}
+
+#[derive(Deserialize, Default)]
+struct ImageUrls {
+  medium_square_crop: Option<String>,
+  small_square_crop: Option<String>,
+  original: Option<String>,
+  square_crop: Option<String>,
+  large_square_crop: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawImage {
+  Attachment { urls: ImageUrls },
+  Legacy(ImageUrls),
+}
+
+/// Reads an image that may be missing, a legacy image or an attachment.
+pub fn image<'de, D: Deserializer<'de>>(d: D) -> Result<Image, D::Error> {
+  let urls = match Option::<RawImage>::deserialize(d)? {
+    Some(RawImage::Attachment { urls }) | Some(RawImage::Legacy(urls)) => urls,
+    None => ImageUrls::default(),
+  };
+  let large = urls.large_square_crop;
+  Ok(Image {
+    small_square_crop: urls.small_square_crop,
+    square_crop: urls.square_crop.or_else(|| large.clone()),
+    medium_square_crop: urls.medium_square_crop.or(large),
+    original: urls.original,
+  })
+}
+
+#[derive(Deserialize)]
+struct ArtistCredit {
+  credit: String,
+  #[serde(default)]
+  joinphrase: String,
+}
+
+#[derive(Deserialize)]
+struct Upload {
+  listen_url: String,
+  #[serde(default)]
+  duration: Option<u64>,
+}
+
+/// Finds who made something, whether it uses `artist` or `artist_credit`.
+fn artist(artist: Option<ArtistPreview>, credits: Vec<ArtistCredit>) -> ArtistPreview {
+  artist.unwrap_or_else(|| ArtistPreview {
+    name: credits
+      .into_iter()
+      .map(|c| format!("{}{}", c.credit, c.joinphrase))
+      .collect(),
+  })
+}
+
+/// Tracks that we saved ourselves have a duration, the ones of the API have it
+/// in their uploads.
+fn duration(duration: Option<u64>, uploads: &[Upload]) -> Option<u64> {
+  duration.or_else(|| uploads.iter().filter_map(|u| u.duration).next())
+}
+
+fn listen_url(url: Option<String>, uploads: Vec<Upload>) -> String {
+  url
+    .or_else(|| uploads.into_iter().next().map(|u| u.listen_url))
+    .unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct RawTrack {
+  id: i32,
+  title: String,
+  album: Album,
+  artist: Option<ArtistPreview>,
+  #[serde(default)]
+  artist_credit: Vec<ArtistCredit>,
+  listen_url: Option<String>,
+  #[serde(default)]
+  uploads: Vec<Upload>,
+  #[serde(default)]
+  tags: Vec<String>,
+  #[serde(default)]
+  mbid: Option<String>,
+  #[serde(default)]
+  duration: Option<u64>,
+}
+
+impl<'de> Deserialize<'de> for Track {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    let raw = RawTrack::deserialize(d)?;
+    Ok(Track {
+      id: raw.id,
+      title: raw.title,
+      album: raw.album,
+      artist: artist(raw.artist, raw.artist_credit),
+      duration: duration(raw.duration, &raw.uploads),
+      listen_url: listen_url(raw.listen_url, raw.uploads),
+      tags: raw.tags,
+      mbid: raw.mbid,
+    })
+  }
+}
+
+#[derive(Deserialize)]
+struct RawAlbumTrack {
+  id: i32,
+  title: String,
+  artist: Option<ArtistPreview>,
+  #[serde(default)]
+  artist_credit: Vec<ArtistCredit>,
+  listen_url: Option<String>,
+  #[serde(default)]
+  uploads: Vec<Upload>,
+  #[serde(default)]
+  tags: Vec<String>,
+  #[serde(default)]
+  mbid: Option<String>,
+  #[serde(default)]
+  duration: Option<u64>,
+}
+
+impl<'de> Deserialize<'de> for AlbumTrack {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    let raw = RawAlbumTrack::deserialize(d)?;
+    Ok(AlbumTrack {
+      id: raw.id,
+      title: raw.title,
+      artist: artist(raw.artist, raw.artist_credit),
+      duration: duration(raw.duration, &raw.uploads),
+      listen_url: listen_url(raw.listen_url, raw.uploads),
+      tags: raw.tags,
+      mbid: raw.mbid,
+    })
+  }
+}
+
+#[derive(Deserialize)]
+struct RawAlbum {
+  id: i32,
+  title: String,
+  artist: Option<ArtistPreview>,
+  #[serde(default)]
+  artist_credit: Vec<ArtistCredit>,
+  tracks: Option<Vec<AlbumTrack>>,
+  #[serde(deserialize_with = "image", default)]
+  cover: Image,
+  #[serde(default)]
+  tags: Vec<String>,
+}
+
+impl<'de> Deserialize<'de> for Album {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    let raw = RawAlbum::deserialize(d)?;
+    Ok(Album {
+      id: raw.id,
+      title: raw.title,
+      artist: artist(raw.artist, raw.artist_credit),
+      tracks: raw.tracks,
+      cover: raw.cover,
+      tags: raw.tags,
+    })
+  }
+}
+
+#[derive(Deserialize)]
+struct RawEpisode {
+  id: i32,
+  title: String,
+  listen_url: Option<String>,
+  #[serde(default)]
+  uploads: Vec<Upload>,
+  #[serde(default)]
+  creation_date: String,
+  #[serde(deserialize_with = "image", default)]
+  cover: Image,
+}
+
+impl<'de> Deserialize<'de> for Episode {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    let raw = RawEpisode::deserialize(d)?;
+    Ok(Episode {
+      id: raw.id,
+      title: raw.title,
+      listen_url: listen_url(raw.listen_url, raw.uploads),
+      creation_date: raw.creation_date,
+      cover: raw.cover,
+    })
+  }
+}
@@ -0,0 +1,250 @@
+//! Network settings: proxy, extra certificate authorities, client
+//! certificates, and self-signed certificates the user chose to trust.
+
+use serde_derive::*;
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  fmt, fs,
+  io,
+  net::TcpStream,
+  path::PathBuf,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NetworkSettings {
+  /// `http://`, `https://` or `socks5://` proxy URL
+  pub proxy: Option<String>,
+  /// A PEM file with additional trusted certificates
+  pub ca_bundle: Option<PathBuf>,
+  /// A PKCS#12 (`.p12`/`.pfx`) file with a client certificate and its key
+  pub client_cert: Option<PathBuf>,
+  /// Kept in the system keyring, not in `network.json`
+  #[serde(default, skip_serializing)]
+  pub client_cert_password: String,
+  /// Self-signed certificates, by host, with their SHA-256 fingerprint. They
+  /// are only trusted for their host.
+  #[serde(default)]
+  pub pinned: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+  Proxy(String),
+  File(PathBuf, io::Error),
+  Certificate(PathBuf, String),
+  PinMismatch(String),
+  Client(String),
+  Keyring(String),
+}
+
+impl fmt::Display for SettingsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SettingsError::Proxy(e) => write!(f, "Invalid proxy: {}", e),
+      SettingsError::File(path, e) => write!(f, "Couldn't read {}: {}", path.display(), e),
+      SettingsError::Certificate(path, e) => {
+        write!(f, "Invalid certificate in {}: {}", path.display(), e)
+      }
+      SettingsError::PinMismatch(host) => write!(
+        f,
+        "The saved certificate for {} was modified, you need to trust it again.",
+        host
+      ),
+      SettingsError::Client(e) => write!(f, "Couldn't set up the network: {}", e),
+      SettingsError::Keyring(e) => write!(f, "Couldn't save the password in the keyring: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for SettingsError {}
+
+/// Both reqwest clients have the same builder methods, but no common trait.
+/// `$host` is the host the client is for, if its pinned certificate should be
+/// trusted.
+macro_rules! configure {
+  ($settings:expr, $builder:expr, $host:expr) => {{
+    let settings = $settings;
+    let mut builder = $builder;
+    if let Some(proxy) = settings.proxy.as_ref().filter(|p| !p.is_empty()) {
+      builder = builder.proxy(
+        reqwest::Proxy::all(proxy.as_str()).map_err(|e| SettingsError::Proxy(e.to_string()))?,
+      );
+    }
+    for cert in settings.certificates()? {
+      builder = builder.add_root_certificate(cert);
+    }
+    // It could sign certificates for other hosts too, so the client that
+    // trusts it is only used for its own host
+    if let Some(cert) = settings.pinned_certificate($host)? {
+      builder = builder.add_root_certificate(cert);
+    }
+    if let Some(identity) = settings.identity()? {
+      builder = builder.identity(identity);
+    }
+    builder
+      .build()
+      .map_err(|e| SettingsError::Client(e.to_string()))
+  }};
+}
+
+fn settings_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("network.json")
+}
+
+fn certs_dir() -> PathBuf {
+  dirs::config_dir().unwrap().join("mobydick").join("certs")
+}
+
+/// Where the password of the client certificate is saved.
+fn keyring_entry() -> keyring::Result<keyring::Entry> {
+  keyring::Entry::new("mobydick", "client-certificate")
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+  Sha256::digest(der)
+    .iter()
+    .map(|b| format!("{:02X}", b))
+    .collect::<Vec<_>>()
+    .join(":")
+}
+
+impl NetworkSettings {
+  pub fn load() -> NetworkSettings {
+    let mut settings: NetworkSettings = fs::read(settings_file())
+      .ok()
+      .and_then(|f| serde_json::from_slice(&f).ok())
+      .unwrap_or_default();
+    if !settings.client_cert_password.is_empty() {
+      // Saved in plain text by older versions: move it to the keyring
+      settings.save().ok();
+    } else if settings.client_cert.is_some() {
+      settings.client_cert_password = keyring_entry()
+        .and_then(|e| e.get_password())
+        .unwrap_or_default();
+    }
+    settings
+  }
+
+  pub fn save(&self) -> Result<(), SettingsError> {
+    if self.client_cert.is_some() && !self.client_cert_password.is_empty() {
+      keyring_entry()
+        .and_then(|e| e.set_password(&self.client_cert_password))
+        .map_err(|e| SettingsError::Keyring(e.to_string()))?;
+    } else if let Ok(entry) = keyring_entry() {
+      entry.delete_credential().ok();
+    }
+    fs::create_dir_all(settings_file().parent().unwrap()).ok();
+    if let Ok(json) = serde_json::to_string_pretty(self) {
+      fs::write(settings_file(), json).ok();
+    }
+    Ok(())
+  }
+
+  /// A client for any host, that doesn't trust the pinned certificates.
+  pub fn client(&self) -> Result<reqwest::r#async::Client, SettingsError> {
+    configure!(self, reqwest::r#async::Client::builder(), None)
+  }
+
+  /// A client that also trusts the certificate pinned for `host`. It must only
+  /// be used for requests to `host`.
+  pub fn client_for(&self, host: &str) -> Result<reqwest::r#async::Client, SettingsError> {
+    configure!(self, reqwest::r#async::Client::builder(), Some(host))
+  }
+
+  /// Like `client_for`, but blocking.
+  pub fn blocking_client_for(&self, host: &str) -> Result<reqwest::Client, SettingsError> {
+    configure!(self, reqwest::Client::builder(), Some(host))
+  }
+
+  /// Trusts a self-signed certificate for `host`.
+  pub fn pin(&mut self, host: &str, der: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(certs_dir())?;
+    fs::write(certs_dir().join(format!("{}.der", host)), der)?;
+    self.pinned.insert(host.to_string(), fingerprint(der));
+    self.save().map_err(|e| io::Error::other(e.to_string()))
+  }
+
+  fn certificates(&self) -> Result<Vec<reqwest::Certificate>, SettingsError> {
+    let mut certs = vec![];
+    if let Some(ref path) = self.ca_bundle {
+      let pem = fs::read_to_string(path).map_err(|e| SettingsError::File(path.clone(), e))?;
+      // native-tls only reads one certificate at a time
+      for cert in pem
+        .split_terminator("-----END CERTIFICATE-----")
+        .filter(|c| c.contains("-----BEGIN CERTIFICATE-----"))
+      {
+        let cert = format!("{}-----END CERTIFICATE-----\n", cert.trim_start());
+        certs.push(
+          reqwest::Certificate::from_pem(cert.as_bytes())
+            .map_err(|e| SettingsError::Certificate(path.clone(), e.to_string()))?,
+        );
+      }
+    }
+    Ok(certs)
+  }
+
+  /// The certificate pinned for `host`, if its SHA-256 fingerprint is still
+  /// the one the user trusted.
+  fn pinned_certificate(
+    &self,
+    host: Option<&str>,
+  ) -> Result<Option<reqwest::Certificate>, SettingsError> {
+    let (host, expected) = match host.and_then(|h| self.pinned.get_key_value(h)) {
+      Some(pin) => pin,
+      None => return Ok(None),
+    };
+    let path = certs_dir().join(format!("{}.der", host));
+    let der = fs::read(&path).map_err(|e| SettingsError::File(path.clone(), e))?;
+    if &fingerprint(&der) != expected {
+      return Err(SettingsError::PinMismatch(host.clone()));
+    }
+    reqwest::Certificate::from_der(&der)
+      .map(Some)
+      .map_err(|e| SettingsError::Certificate(path.clone(), e.to_string()))
+  }
+
+  fn identity(&self) -> Result<Option<reqwest::Identity>, SettingsError> {
+    match self.client_cert {
+      Some(ref path) => {
+        let der = fs::read(path).map_err(|e| SettingsError::File(path.clone(), e))?;
+        reqwest::Identity::from_pkcs12_der(&der, &self.client_cert_password)
+          .map(Some)
+          .map_err(|e| SettingsError::Certificate(path.clone(), e.to_string()))
+      }
+      None => Ok(None),
+    }
+  }
+}
+
+/// Gets the certificate a server presents, without checking it, so that the
+/// user can decide to trust it.
+///
+/// The connection doesn't go through the proxy of the settings: when the
+/// server can only be reached through it, its certificate can't be fetched.
+///
+/// Returns its DER encoding. This is blocking.
+pub fn peer_certificate(host: &str, port: u16) -> Result<Vec<u8>, String> {
+  let connector = native_tls::TlsConnector::builder()
+    .danger_accept_invalid_certs(true)
+    .danger_accept_invalid_hostnames(true)
+    .build()
+    .map_err(|e| e.to_string())?;
+  let stream = TcpStream::connect((host, port)).map_err(|e| {
+    if NetworkSettings::load().proxy.is_some_and(|p| !p.is_empty()) {
+      format!("{} (the certificate is fetched without the proxy)", e)
+    } else {
+      e.to_string()
+    }
+  })?;
+  let stream = connector.connect(host, stream).map_err(|e| e.to_string())?;
+  stream
+    .peer_certificate()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "The server didn't send any certificate".to_string())?
+    .to_der()
+    .map_err(|e| e.to_string())
+}
@@ -0,0 +1,267 @@
+//! Instance discovery, using the NodeInfo protocol.
+//!
+//! Before asking for credentials, we check that the URL the user typed points
+//! to a Funkwhale instance we can talk to.
+
+use super::network::NetworkSettings;
+use serde_derive::*;
+use std::{error::Error, fmt};
+
+const NODEINFO_SCHEMA: &str = "http://nodeinfo.diaspora.software/ns/schema/2.0";
+const FALLBACK_PATH: &str = "/api/v1/instance/nodeinfo/2.0/";
+
+/// Oldest Funkwhale release mobydick knows how to talk to.
+pub const MIN_VERSION: Version = Version {
+  major: 0,
+  minor: 17,
+  patch: 0,
+};
+
+#[derive(Deserialize)]
+struct Links {
+  links: Vec<Link>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+  rel: String,
+  href: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+  pub software: Software,
+  #[serde(default)]
+  pub open_registrations: bool,
+  #[serde(default)]
+  pub metadata: Metadata,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Software {
+  pub name: String,
+  pub version: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+  pub node_name: Option<String>,
+  pub library: Option<Library>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Library {
+  #[serde(default)]
+  pub anonymous_can_listen: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+  pub major: u32,
+  pub minor: u32,
+  pub patch: u32,
+}
+
+impl Version {
+  /// Parses versions like `0.18.3`, `1.0-rc1` or `0.19.0+git.1a2b3c`.
+  pub fn parse(version: &str) -> Option<Version> {
+    let release = version
+      .split(['+', '-', ' '])
+      .next()?;
+    let mut parts = release.split('.').map(|p| p.parse::<u32>());
+    Some(Version {
+      major: parts.next()?.ok()?,
+      minor: parts.next().unwrap_or(Ok(0)).ok()?,
+      patch: parts.next().unwrap_or(Ok(0)).ok()?,
+    })
+  }
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+  }
+}
+
+/// What we know about an instance before logging in.
+#[derive(Debug, Clone)]
+pub struct InstanceInfo {
+  /// Normalized base URL, without trailing slash
+  pub url: String,
+  pub name: String,
+  pub version: Version,
+  pub open_registrations: bool,
+  pub anonymous_access: bool,
+}
+
+impl InstanceInfo {
+  pub fn summary(&self) -> String {
+    format!(
+      "{} — Funkwhale {}\nRegistrations are {}. Anonymous listening is {}.",
+      self.name,
+      self.version,
+      if self.open_registrations {
+        "open"
+      } else {
+        "closed"
+      },
+      if self.anonymous_access {
+        "allowed"
+      } else {
+        "not allowed"
+      },
+    )
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum DiscoveryError {
+  InvalidUrl,
+  Settings(String),
+  Dns(String),
+  /// The host and port we tried to connect to, and the error
+  Tls(String, u16, String),
+  Connection(String),
+  NotFunkwhale(Option<String>),
+  Unsupported(String),
+}
+
+impl fmt::Display for DiscoveryError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DiscoveryError::InvalidUrl => write!(f, "This is not a valid instance URL."),
+      DiscoveryError::Settings(e) => write!(f, "{}", e),
+      DiscoveryError::Dns(host) => write!(
+        f,
+        "Couldn't find a server named {}. Check the address of your instance.",
+        host
+      ),
+      DiscoveryError::Tls(_, _, e) => write!(
+        f,
+        "The secure connection to your instance failed ({}). Its certificate may be invalid.",
+        e
+      ),
+      DiscoveryError::Connection(e) => write!(f, "Couldn't connect to your instance ({}).", e),
+      DiscoveryError::NotFunkwhale(Some(software)) => write!(
+        f,
+        "This server runs {}, not Funkwhale.",
+        software
+      ),
+      DiscoveryError::NotFunkwhale(None) => write!(f, "This server doesn't look like a Funkwhale instance."),
+      DiscoveryError::Unsupported(version) => write!(
+        f,
+        "This instance runs Funkwhale {}, but Mobydick needs at least {}.",
+        version, MIN_VERSION
+      ),
+    }
+  }
+}
+
+impl Error for DiscoveryError {}
+
+/// Adds a scheme if needed and removes the trailing slash.
+pub fn normalize_url(url: &str) -> String {
+  let url = url.trim().trim_end_matches('/');
+  if url.starts_with("http://") || url.starts_with("https://") {
+    url.to_string()
+  } else {
+    format!("https://{}", url)
+  }
+}
+
+/// Fetches and validates the NodeInfo of an instance.
+///
+/// This is blocking, so it should be called from another thread.
+pub fn discover(url: &str) -> Result<InstanceInfo, DiscoveryError> {
+  let url = normalize_url(url);
+  let parsed = reqwest::Url::parse(&url).map_err(|_| DiscoveryError::InvalidUrl)?;
+  let host = parsed
+    .host_str()
+    .map(String::from)
+    .ok_or(DiscoveryError::InvalidUrl)?;
+  let port = parsed.port_or_known_default().unwrap_or(443);
+
+  let client = NetworkSettings::load()
+    .blocking_client_for(&host)
+    .map_err(|e| DiscoveryError::Settings(e.to_string()))?;
+  let classify = |e: reqwest::Error| classify(e, &host, port);
+
+  let mut res = client
+    .get(&format!("{}/.well-known/nodeinfo", url))
+    .send()
+    .map_err(classify)?;
+  let nodeinfo_url = if res.status().is_success() {
+    res
+      .json::<Links>()
+      .ok()
+      .and_then(|l| l.links.into_iter().find(|l| l.rel == NODEINFO_SCHEMA))
+      .map(|l| l.href)
+  } else {
+    None
+  }
+  .unwrap_or_else(|| format!("{}{}", url, FALLBACK_PATH));
+
+  let mut res = client.get(&nodeinfo_url).send().map_err(classify)?;
+  if !res.status().is_success() {
+    return Err(DiscoveryError::NotFunkwhale(None));
+  }
+  let info: NodeInfo = res
+    .json()
+    .map_err(|_| DiscoveryError::NotFunkwhale(None))?;
+
+  if info.software.name.to_lowercase() != "funkwhale" {
+    return Err(DiscoveryError::NotFunkwhale(Some(info.software.name)));
+  }
+  let version = Version::parse(&info.software.version)
+    .ok_or_else(|| DiscoveryError::Unsupported(info.software.version.clone()))?;
+  if version < MIN_VERSION {
+    return Err(DiscoveryError::Unsupported(info.software.version));
+  }
+
+  Ok(InstanceInfo {
+    name: info.metadata.node_name.clone().unwrap_or_else(|| host.clone()),
+    anonymous_access: info
+      .metadata
+      .library
+      .map(|l| l.anonymous_can_listen)
+      .unwrap_or_default(),
+    open_registrations: info.open_registrations,
+    version,
+    url,
+  })
+}
+
+/// reqwest doesn't tell us precisely what failed, so we look at the whole chain
+/// of errors to find out.
+fn classify(err: reqwest::Error, host: &str, port: u16) -> DiscoveryError {
+  let mut messages = vec![err.to_string()];
+  let mut source = err.source();
+  while let Some(e) = source {
+    messages.push(e.to_string());
+    source = e.source();
+  }
+  let all = messages.join(": ").to_lowercase();
+
+  if all.contains("dns")
+    || all.contains("lookup address")
+    || all.contains("name or service not known")
+    || all.contains("no such host")
+  {
+    DiscoveryError::Dns(host.to_string())
+  } else if all.contains("certificate")
+    || all.contains("tls")
+    || all.contains("ssl")
+    || all.contains("handshake")
+  {
+    DiscoveryError::Tls(
+      host.to_string(),
+      port,
+      messages.last().cloned().unwrap_or_default(),
+    )
+  } else {
+    DiscoveryError::Connection(messages.last().cloned().unwrap_or_default())
+  }
+}
@@ -0,0 +1,155 @@
+//! Channel episodes, and the automatic download of new podcast episodes.
+
+use crate::{
+  api,
+  downloads::{self, Download},
+  offline, paths,
+};
+use serde_derive::*;
+use std::{
+  collections::HashMap,
+  fs,
+  path::PathBuf,
+  sync::{Mutex, Once},
+  thread,
+  time::Duration,
+};
+
+/// How often we look for new episodes
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static SCHEDULE: Once = Once::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AutoChannel {
+  channel: api::Channel,
+  /// Publication date of the latest episode we downloaded
+  last: Option<String>,
+}
+
+lazy_static::lazy_static! {
+  /// Podcasts whose new episodes are downloaded automatically, by UUID
+  static ref AUTO: Mutex<HashMap<String, AutoChannel>> = Mutex::new(
+    fs::read(auto_file())
+      .ok()
+      .and_then(|f| serde_json::from_slice(&f).ok())
+      .unwrap_or_default()
+  );
+}
+
+fn auto_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("channels.json")
+}
+
+fn save(auto: &HashMap<String, AutoChannel>) {
+  fs::create_dir_all(auto_file().parent().unwrap()).ok();
+  if let Ok(json) = serde_json::to_string(auto) {
+    fs::write(auto_file(), json).ok();
+  }
+}
+
+/// A planned download of an episode (see `paths::episode`).
+pub fn download(channel: &api::Channel, episode: api::Episode) -> Download {
+  Download::to(paths::episode(channel, &episode), episode.into_track(channel))
+}
+
+/// The episodes of a channel, newest first.
+///
+/// At most `limit` of them are fetched. This is blocking.
+pub fn episodes(
+  client: &api::RequestContext,
+  channel: &api::Channel,
+  limit: usize,
+) -> Result<Vec<api::Episode>, api::Error> {
+  client.first_items(
+    client.api_get("/tracks/").query(&[
+      ("channel", channel.uuid.as_str()),
+      ("ordering", "-creation_date"),
+      ("page_size", "50"),
+    ]),
+    limit,
+  )
+}
+
+pub fn is_auto(uuid: &str) -> bool {
+  AUTO.lock().unwrap().contains_key(uuid)
+}
+
+/// Starts or stops downloading new episodes of a podcast automatically.
+pub fn set_auto(channel: &api::Channel, enabled: bool) {
+  let mut auto = AUTO.lock().unwrap();
+  if enabled {
+    auto.insert(
+      channel.uuid.clone(),
+      AutoChannel {
+        channel: channel.clone(),
+        last: None,
+      },
+    );
+  } else {
+    auto.remove(&channel.uuid);
+  }
+  save(&auto);
+  drop(auto);
+
+  if enabled {
+    thread::spawn(check);
+  }
+}
+
+/// Looks for new episodes now, and then regularly.
+pub fn schedule() {
+  thread::spawn(check);
+  SCHEDULE.call_once(|| {
+    thread::spawn(|| loop {
+      thread::sleep(CHECK_INTERVAL);
+      check();
+    });
+  });
+}
+
+/// Downloads the episodes published since the last check. The first time, only
+/// the latest episode is downloaded.
+///
+/// This is blocking.
+pub fn check() {
+  if offline::is_offline() {
+    return;
+  }
+  let client = match *api::API.lock().unwrap() {
+    Some(ref client) => client.clone(),
+    None => return,
+  };
+
+  let channels: Vec<AutoChannel> = AUTO.lock().unwrap().values().cloned().collect();
+  for auto in channels {
+    let limit = if auto.last.is_some() { 50 } else { 1 };
+    let new: Vec<api::Episode> = match episodes(&client, &auto.channel, limit) {
+      Ok(episodes) => episodes
+        .into_iter()
+        .filter(|e| auto.last.as_ref().map(|l| e.creation_date > *l).unwrap_or(true))
+        .collect(),
+      Err(_) => continue,
+    };
+    let latest = match new.first() {
+      Some(e) => e.creation_date.clone(),
+      None => continue,
+    };
+
+    let dls = new
+      .into_iter()
+      .map(|e| download(&auto.channel, e))
+      .filter(|dl| !downloads::on_disk(dl))
+      .collect();
+    downloads::enqueue(dls);
+
+    let mut all = AUTO.lock().unwrap();
+    if let Some(a) = all.get_mut(&auto.channel.uuid) {
+      a.last = Some(latest);
+    }
+    save(&all);
+  }
+}
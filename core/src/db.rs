@@ -0,0 +1,353 @@
+//! A local database of the downloaded files.
+//!
+//! Every file that is downloaded is recorded with the track it comes from, so
+//! that we know what is on disk even without the instance.
+
+use crate::{
+  api,
+  downloads::{DlStatus, Download},
+  tags,
+};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use sha2::{Digest, Sha256};
+use std::{
+  fs,
+  io::{self, Read},
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+/// Each step brings the database to the next version (`PRAGMA user_version`).
+/// Steps must never be changed once released: add new ones instead.
+const MIGRATIONS: &[&str] = &["
+  CREATE TABLE files (
+    path TEXT PRIMARY KEY NOT NULL,
+    track_id INTEGER NOT NULL,
+    instance TEXT NOT NULL,
+    account TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    checksum TEXT NOT NULL,
+    format TEXT NOT NULL,
+    bitrate INTEGER,
+    downloaded_at TEXT NOT NULL,
+    -- The track, as JSON, to show it offline
+    track TEXT NOT NULL
+  );
+  CREATE INDEX files_track ON files (instance, track_id);
+", "
+  -- Files that were already there, found by scanning a folder (see `scan`)
+  CREATE TABLE local_files (
+    path TEXT PRIMARY KEY NOT NULL,
+    musicbrainz_id TEXT,
+    -- Normalised, see `scan::normalize`
+    artist TEXT NOT NULL,
+    album_artist TEXT NOT NULL,
+    album TEXT NOT NULL,
+    title TEXT NOT NULL,
+    duration INTEGER NOT NULL,
+    -- Seconds since the Unix epoch, to skip files that didn't change
+    modified INTEGER NOT NULL
+  );
+  CREATE INDEX local_files_mbid ON local_files (musicbrainz_id);
+  CREATE INDEX local_files_title ON local_files (title);
+"];
+
+/// A downloaded file
+#[derive(Debug, Clone)]
+pub struct File {
+  pub path: PathBuf,
+  pub track: api::Track,
+  pub instance: String,
+  pub account: String,
+  pub size: u64,
+  /// SHA-256, in hexadecimal
+  pub checksum: String,
+  pub format: String,
+  /// In kbit/s
+  pub bitrate: Option<u32>,
+  /// ISO 8601 date
+  pub downloaded_at: String,
+}
+
+impl File {
+  /// Describes a file as it is on disk now.
+  fn read(path: &Path, track: api::Track, instance: String, account: String) -> io::Result<File> {
+    let info = tags::read(path);
+    Ok(File {
+      path: path.to_path_buf(),
+      track,
+      instance,
+      account,
+      size: fs::metadata(path)?.len(),
+      checksum: checksum(path)?,
+      format: info
+        .as_ref()
+        .map(|i| i.format.clone())
+        .unwrap_or_else(|| tags::format(path)),
+      bitrate: info.and_then(|i| i.bitrate),
+      downloaded_at: chrono::Utc::now().to_rfc3339(),
+    })
+  }
+
+  pub fn download(&self) -> Download {
+    let mut dl = Download::to(self.path.clone(), self.track.clone());
+    dl.status = DlStatus::Done;
+    dl
+  }
+}
+
+lazy_static::lazy_static! {
+  pub(crate) static ref DB: Mutex<Connection> = Mutex::new(open());
+  /// Why the database couldn't be opened or updated, if it couldn't
+  static ref PROBLEM: Mutex<Option<String>> = Mutex::new(None);
+}
+
+fn db_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("library.sqlite")
+}
+
+fn open() -> Connection {
+  fs::create_dir_all(db_file().parent().unwrap()).ok();
+  let mut conn = match Connection::open(db_file()) {
+    Ok(conn) => conn,
+    Err(e) => {
+      *PROBLEM.lock().unwrap() = Some(format!(
+        "Couldn't open the database, downloaded files won't be remembered. {}",
+        e
+      ));
+      Connection::open_in_memory().unwrap()
+    }
+  };
+  if let Err(e) = migrate(&mut conn) {
+    *PROBLEM.lock().unwrap() = Some(format!("Couldn't update the database. {}", e));
+  }
+  conn
+}
+
+/// Opens the database if it wasn't yet, and tells why it couldn't be used as
+/// expected, if that's the case.
+pub fn problem() -> Option<String> {
+  lazy_static::initialize(&DB);
+  PROBLEM.lock().unwrap().clone()
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+  let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+  for (i, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+    // Rolled back when dropped, if a step fails
+    let tx = conn.transaction()?;
+    tx.execute_batch(step)?;
+    tx.execute_batch(&format!("PRAGMA user_version = {};", i + 1))?;
+    tx.commit()?;
+  }
+  Ok(())
+}
+
+fn checksum(path: &Path) -> io::Result<String> {
+  let mut file = fs::File::open(path)?;
+  let mut hasher = Sha256::new();
+  let mut buf = [0; 64 * 1024];
+  loop {
+    let read = file.read(&mut buf)?;
+    if read == 0 {
+      break;
+    }
+    hasher.input(&buf[..read]);
+  }
+  Ok(
+    hasher
+      .result()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect(),
+  )
+}
+
+/// The instance and the account we are logged in with
+fn account() -> Option<(String, String)> {
+  api::API
+    .lock()
+    .unwrap()
+    .as_ref()
+    .map(|c| (c.instance().to_string(), c.username().to_string()))
+}
+
+fn save(file: &File) -> rusqlite::Result<()> {
+  DB.lock().unwrap().execute(
+    "INSERT OR REPLACE INTO files
+       (path, track_id, instance, account, size, checksum, format, bitrate, downloaded_at, track)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    params![
+      file.path.to_string_lossy().into_owned(),
+      file.track.id,
+      file.instance,
+      file.account,
+      file.size as i64,
+      file.checksum,
+      file.format,
+      file.bitrate,
+      file.downloaded_at,
+      serde_json::to_string(&file.track).unwrap_or_default(),
+    ],
+  )?;
+  Ok(())
+}
+
+/// Records a download that just ended.
+pub fn record(dl: &Download) {
+  let (instance, account) = match account() {
+    Some(a) => a,
+    None => return,
+  };
+  let res = File::read(&dl.output, dl.track.clone(), instance, account)
+    .map_err(|e| e.to_string())
+    .and_then(|file| save(&file).map_err(|e| e.to_string()));
+  if let Err(e) = res {
+    println!("Couldn't record {}: {}", dl.output.display(), e);
+  }
+}
+
+fn row_to_file(row: &rusqlite::Row) -> rusqlite::Result<File> {
+  let path: String = row.get(0)?;
+  let track: String = row.get(9)?;
+  Ok(File {
+    path: PathBuf::from(path),
+    track: serde_json::from_str(&track).map_err(|e| {
+      rusqlite::Error::FromSqlConversionFailure(9, rusqlite::types::Type::Text, Box::new(e))
+    })?,
+    instance: row.get(2)?,
+    account: row.get(3)?,
+    size: row.get::<_, i64>(4)? as u64,
+    checksum: row.get(5)?,
+    format: row.get(6)?,
+    bitrate: row.get(7)?,
+    downloaded_at: row.get(8)?,
+  })
+}
+
+const COLUMNS: &str =
+  "path, track_id, instance, account, size, checksum, format, bitrate, downloaded_at, track";
+
+/// The files downloaded from the current instance.
+pub fn files() -> Vec<File> {
+  let instance = match account() {
+    Some((instance, _)) => instance,
+    None => return vec![],
+  };
+  let db = DB.lock().unwrap();
+  let mut stmt = match db.prepare(&format!(
+    "SELECT {} FROM files WHERE instance = ?1 ORDER BY path",
+    COLUMNS
+  )) {
+    Ok(stmt) => stmt,
+    Err(_) => return vec![],
+  };
+  let files = stmt
+    .query_map(params![instance], row_to_file)
+    .map(|rows| rows.filter_map(|f| f.ok()).collect())
+    .unwrap_or_default();
+  files
+}
+
+/// A file where a track of the current instance was downloaded, if there is
+/// still one on disk.
+pub fn file_of(track_id: i32) -> Option<PathBuf> {
+  let (instance, _) = account()?;
+  let db = DB.lock().unwrap();
+  let mut stmt = db
+    .prepare("SELECT path FROM files WHERE instance = ?1 AND track_id = ?2")
+    .ok()?;
+  let paths: Vec<String> = stmt
+    .query_map(params![instance, track_id], |row| row.get(0))
+    .ok()?
+    .filter_map(|p| p.ok())
+    .collect();
+  paths.into_iter().map(PathBuf::from).find(|p| p.is_file())
+}
+
+/// Keeps track of a file that was moved.
+pub fn moved(from: &Path, to: &Path) {
+  DB.lock()
+    .unwrap()
+    .execute(
+      "UPDATE files SET path = ?2 WHERE path = ?1",
+      params![from.to_string_lossy().into_owned(), to.to_string_lossy().into_owned()],
+    )
+    .ok();
+}
+
+/// Forgets a file that was deleted.
+pub fn forget(path: &Path) {
+  DB.lock()
+    .unwrap()
+    .execute("DELETE FROM files WHERE path = ?1", params![path.to_string_lossy().into_owned()])
+    .ok();
+}
+
+#[derive(Debug, Default)]
+pub struct Rebuilt {
+  /// Files that were read again
+  pub updated: usize,
+  /// Files that are not on disk anymore
+  pub removed: usize,
+  /// Downloads that were not in the database yet
+  pub added: usize,
+}
+
+/// Reads every recorded file again (size, checksum, format and tags), forgets
+/// the ones that were deleted, and adds the downloads of the current account
+/// that are missing.
+///
+/// This is blocking, and slow for large collections.
+pub fn rebuild(dls: &[Download]) -> rusqlite::Result<Rebuilt> {
+  let mut rebuilt = Rebuilt::default();
+  let all: Vec<File> = {
+    let db = DB.lock().unwrap();
+    let mut stmt = db.prepare(&format!("SELECT {} FROM files", COLUMNS))?;
+    let rows = stmt.query_map(NO_PARAMS, row_to_file)?;
+    rows.filter_map(|f| f.ok()).collect()
+  };
+
+  for file in all {
+    match File::read(&file.path, file.track.clone(), file.instance.clone(), file.account.clone()) {
+      Ok(new) => {
+        save(&File {
+          downloaded_at: file.downloaded_at,
+          ..new
+        })?;
+        rebuilt.updated += 1;
+      }
+      Err(_) if !file.path.exists() => {
+        forget(&file.path);
+        rebuilt.removed += 1;
+      }
+      Err(e) => println!("Couldn't read {}: {}", file.path.display(), e),
+    }
+  }
+
+  if let Some((instance, account)) = account() {
+    for dl in dls.iter().filter(|dl| dl.status == DlStatus::Done) {
+      let known = DB
+        .lock()
+        .unwrap()
+        .query_row(
+          "SELECT 1 FROM files WHERE path = ?1",
+          params![dl.output.to_string_lossy().into_owned()],
+          |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+      if known {
+        continue;
+      }
+      if let Ok(file) = File::read(&dl.output, dl.track.clone(), instance.clone(), account.clone()) {
+        save(&file)?;
+        rebuilt.added += 1;
+      }
+    }
+  }
+  Ok(rebuilt)
+}
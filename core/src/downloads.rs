@@ -0,0 +1,547 @@
+//! The download engine: a queue of tracks, downloaded a few at a time, and
+//! remembered on disk so that they can be browsed offline.
+
+use crate::{api, db, export, hooks, notifications, offline, paths, scan, schedule, settings};
+use serde_derive::*;
+use std::{
+  collections::HashMap,
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+/// How often `Event::Progress` is sent for a download
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DlStatus {
+  Planned,
+  /// Waiting for a download window to open (see `schedule`), or for the
+  /// downloads to be unpaused
+  Scheduled,
+  Started,
+  Done,
+  Cancelled,
+  /// It can be enqueued again, see `Download::error` for why it failed
+  Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Download {
+  pub url: String,
+  pub status: DlStatus,
+  pub output: PathBuf,
+  pub track: api::Track,
+  /// Why the hooks failed after the download, if they did
+  #[serde(default)]
+  pub hook_error: Option<String>,
+  /// Why the download failed, if it did
+  #[serde(default)]
+  pub error: Option<String>,
+  /// If it was queued with other tracks of its album, that are handed to the
+  /// album hook together once they are downloaded
+  #[serde(default)]
+  pub with_album: bool,
+}
+
+impl Download {
+  /// A planned download of a track, to the path given by the current template.
+  pub fn new(track: api::Track) -> Download {
+    Download::to(paths::track(&track), track)
+  }
+
+  /// A planned download of a track, to a given path. The extension of `output`
+  /// is replaced by the one of the downloaded file.
+  pub fn to(output: PathBuf, track: api::Track) -> Download {
+    Download {
+      url: track.listen_url.clone(),
+      status: DlStatus::Planned,
+      output,
+      track,
+      hook_error: None,
+      error: None,
+      with_album: false,
+    }
+  }
+
+  pub fn ended(&mut self, out: PathBuf) {
+    self.status = DlStatus::Done;
+    self.output = out;
+    self.hook_error = None;
+    self.error = None;
+  }
+
+  /// If it is still to be downloaded
+  pub(crate) fn pending(&self) -> bool {
+    match self.status {
+      DlStatus::Planned | DlStatus::Scheduled | DlStatus::Started => true,
+      DlStatus::Done | DlStatus::Cancelled | DlStatus::Failed => false,
+    }
+  }
+}
+
+/// What happens to a single download, see `on_event`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+  Started(i32),
+  /// Bytes written so far, and the size of the file if it is known
+  Progress(i32, u64, Option<u64>),
+  Finished(i32),
+  /// With the error. Cancelled downloads and the ones that wait to be back
+  /// online don't fail.
+  Failed(i32, String),
+}
+
+type Listener = Box<dyn Fn() + Send + Sync>;
+type EventListener = Box<dyn Fn(&Event) + Send + Sync>;
+
+lazy_static::lazy_static! {
+  pub static ref DOWNLOADS: Arc<Mutex<HashMap<i32, Download>>> = Arc::new(Mutex::new(HashMap::new()));
+
+  pub(crate) static ref DL_JOBS: workerpool::Pool<TrackDl> = workerpool::Pool::new(5);
+
+  static ref LISTENERS: Mutex<Vec<Listener>> = Mutex::new(Vec::new());
+
+  static ref EVENT_LISTENERS: Mutex<Vec<EventListener>> = Mutex::new(Vec::new());
+}
+
+/// Calls `f` every time a download is added, starts or ends. It is called from
+/// the thread that made the change.
+pub fn on_change<F: Fn() + Send + Sync + 'static>(f: F) {
+  LISTENERS.lock().unwrap().push(Box::new(f));
+}
+
+pub(crate) fn changed() {
+  for listener in LISTENERS.lock().unwrap().iter() {
+    listener();
+  }
+}
+
+/// Calls `f` with what happens to each download, from the thread that
+/// downloads it.
+pub fn on_event<F: Fn(&Event) + Send + Sync + 'static>(f: F) {
+  EVENT_LISTENERS.lock().unwrap().push(Box::new(f));
+}
+
+fn emit(event: Event) {
+  for listener in EVENT_LISTENERS.lock().unwrap().iter() {
+    listener(&event);
+  }
+}
+
+fn downloads_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("downloads.json")
+}
+
+/// Keeps track of what was downloaded, so it can be browsed offline.
+pub fn save() {
+  let dls = DOWNLOADS.lock().unwrap().clone();
+  fs::create_dir_all(downloads_file().parent().unwrap()).ok();
+  if let Ok(json) = serde_json::to_string(&dls.values().collect::<Vec<_>>()) {
+    fs::write(downloads_file(), json).ok();
+  }
+}
+
+/// Loads the previous downloads, and the files of the database that they
+/// don't mention. The downloads that were interrupted are planned again, but
+/// only start with `resume`. Scheduled ones stay scheduled.
+pub fn load() {
+  let saved: Vec<Download> = fs::read(downloads_file())
+    .ok()
+    .and_then(|f| serde_json::from_slice(&f).ok())
+    .unwrap_or_default();
+  let mut dls = DOWNLOADS.lock().unwrap();
+  for file in db::files() {
+    dls.insert(file.track.id, file.download());
+  }
+  for mut dl in saved {
+    if dl.status == DlStatus::Started {
+      dl.status = DlStatus::Planned;
+    }
+    dls.insert(dl.track.id, dl);
+  }
+}
+
+/// Starts the planned downloads again, and the scheduled ones if a download
+/// window is open.
+pub fn resume() {
+  for dl in DOWNLOADS.lock().unwrap().values() {
+    if dl.status == DlStatus::Planned {
+      DL_JOBS.execute(dl.clone());
+    }
+  }
+  if schedule::is_open() {
+    start_scheduled();
+  }
+  changed();
+}
+
+/// Starts the downloads that were waiting for their window.
+pub fn start_scheduled() {
+  if is_paused() {
+    return;
+  }
+  let mut started = false;
+  for dl in DOWNLOADS.lock().unwrap().values_mut() {
+    if dl.status == DlStatus::Scheduled {
+      dl.status = DlStatus::Planned;
+      DL_JOBS.execute(dl.clone());
+      started = true;
+    }
+  }
+  if started {
+    changed();
+  }
+}
+
+/// Stops starting downloads: the ones that didn't start yet wait in the
+/// `Scheduled` state, and the current ones go on.
+pub fn pause() {
+  PAUSED.store(true, Ordering::SeqCst);
+}
+
+/// Starts the downloads that were paused, if the download window is open.
+pub fn unpause() {
+  PAUSED.store(false, Ordering::SeqCst);
+  if schedule::is_open() {
+    start_scheduled();
+  }
+}
+
+pub fn is_paused() -> bool {
+  PAUSED.load(Ordering::SeqCst)
+}
+
+/// Changes how many tracks are downloaded at the same time.
+pub fn set_concurrency(jobs: usize) {
+  // Clones are handles to the same pool
+  DL_JOBS.clone().set_num_threads(jobs.max(1));
+}
+
+/// Blocks until there is nothing left to download, or until the ones that are
+/// left wait for the instance to be reachable again.
+pub fn wait() {
+  DL_JOBS.join();
+}
+
+/// Forgets every download, when logging out. Files are kept.
+pub fn clear() {
+  *DOWNLOADS.lock().unwrap() = HashMap::new();
+  fs::remove_file(downloads_file()).ok();
+}
+
+/// Starts downloading tracks, unless they are already being downloaded.
+/// Outside of the download windows, or when paused, they are scheduled
+/// instead.
+pub fn enqueue(dls: Vec<Download>) {
+  let open = !is_paused() && schedule::is_open();
+  // Podcast episodes don't have a real album, their ID is 0
+  let mut per_album: HashMap<i32, usize> = HashMap::new();
+  for dl in dls.iter().filter(|dl| dl.track.album.id != 0) {
+    *per_album.entry(dl.track.album.id).or_default() += 1;
+  }
+  {
+    let mut downloads = DOWNLOADS.lock().unwrap();
+    for mut dl in dls {
+      dl.with_album = per_album.get(&dl.track.album.id).is_some_and(|n| *n > 1);
+      let active = downloads
+        .get(&dl.track.id)
+        .map(Download::pending)
+        .unwrap_or(false);
+      if active {
+        continue;
+      }
+      if open {
+        downloads.insert(dl.track.id, dl.clone());
+        DL_JOBS.execute(dl);
+      } else {
+        dl.status = DlStatus::Scheduled;
+        downloads.insert(dl.track.id, dl);
+      }
+    }
+  }
+  changed();
+}
+
+/// The tracks of an album. They are fetched if the album doesn't include them.
+///
+/// This is blocking.
+pub fn of_album(client: &api::RequestContext, album: &api::Album) -> Vec<Download> {
+  let tracks = match album.tracks.clone() {
+    Some(tracks) => tracks,
+    None => client
+      .album(album.id)
+      .ok()
+      .and_then(|a| a.tracks)
+      .unwrap_or_default(),
+  };
+  tracks
+    .into_iter()
+    .map(|track| Download::new(track.into_full(album)))
+    .collect()
+}
+
+/// The tracks of every album of an artist. `albums` are fetched if they are not
+/// given.
+///
+/// This is blocking.
+pub fn of_artist(
+  client: &api::RequestContext,
+  id: i32,
+  albums: Option<Vec<api::ArtistAlbum>>,
+) -> Vec<Download> {
+  let albums = match albums {
+    Some(albums) => albums,
+    None => client.artist_albums(id).unwrap_or_default(),
+  };
+  let mut dls = vec![];
+  for album in albums {
+    let album = match client.album(album.id) {
+      Ok(album) => album,
+      Err(_) => continue,
+    };
+    dls.extend(of_album(client, &album));
+  }
+  dls
+}
+
+/// Stops a download, if it didn't end yet. Returns `false` if there was
+/// nothing to stop.
+pub fn cancel(track_id: i32) -> bool {
+  let cancelled = match DOWNLOADS.lock().unwrap().get_mut(&track_id) {
+    Some(dl) if dl.pending() => {
+      dl.status = DlStatus::Cancelled;
+      true
+    }
+    _ => false,
+  };
+  changed();
+  cancelled
+}
+
+/// Whether a track was already downloaded, wherever it was saved, if there
+/// is a file where it would be saved (see `paths::existing`), or if it is in a
+/// scanned music folder (see `scan::find`).
+pub fn on_disk(dl: &Download) -> bool {
+  if let Some(d) = DOWNLOADS.lock().unwrap().get(&dl.track.id) {
+    if d.status == DlStatus::Done && d.output.exists() {
+      return true;
+    }
+  }
+  paths::existing(&dl.output).is_some()
+    || db::file_of(dl.track.id).is_some()
+    || scan::find(&dl.track).is_some()
+}
+
+/// The extension of a downloaded file, from the name the instance gives it in
+/// `Content-Disposition`. Defaults to MP3.
+fn extension(headers: &reqwest::header::HeaderMap) -> String {
+  headers
+    .get(reqwest::header::CONTENT_DISPOSITION)
+    .and_then(|h| h.to_str().ok())
+    .and_then(|h| {
+      h.split(';')
+        .map(str::trim)
+        .find(|param| param.starts_with("filename="))
+    })
+    .map(|param| param["filename=".len()..].trim_matches('"'))
+    .and_then(|name| Path::new(name).extension())
+    .map(|ext| ext.to_string_lossy().into_owned())
+    .unwrap_or_else(|| "mp3".to_string())
+}
+
+/// Marks a download as failed, unless it was cancelled in the meantime.
+fn fail(track_id: i32, error: String) {
+  let failed = match DOWNLOADS.lock().unwrap().get_mut(&track_id) {
+    Some(dl) if dl.status != DlStatus::Cancelled => {
+      dl.status = DlStatus::Failed;
+      dl.error = Some(error.clone());
+      true
+    }
+    _ => false,
+  };
+  save();
+  changed();
+  if failed {
+    emit(Event::Failed(track_id, error));
+  }
+}
+
+/// A file being downloaded, that tells how it goes every now and then.
+struct Progress {
+  file: fs::File,
+  track_id: i32,
+  written: u64,
+  size: Option<u64>,
+  last: Instant,
+}
+
+impl Write for Progress {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let written = self.file.write(buf)?;
+    self.written += written as u64;
+    if self.last.elapsed() >= PROGRESS_INTERVAL {
+      self.last = Instant::now();
+      emit(Event::Progress(self.track_id, self.written, self.size));
+    }
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
+
+#[derive(Default)]
+pub(crate) struct TrackDl;
+
+impl workerpool::Worker for TrackDl {
+  type Input = Download;
+  type Output = ();
+
+  fn execute(&mut self, dl: Self::Input) -> Self::Output {
+    if dl.status == DlStatus::Cancelled {
+      return;
+    }
+
+    // The window may have closed while it was in the queue
+    let open = !is_paused() && schedule::is_open();
+    {
+      let mut dls = DOWNLOADS.lock().unwrap();
+      match dls.get_mut(&dl.track.id) {
+        Some(d) if d.status != DlStatus::Cancelled => {
+          d.status = if open {
+            DlStatus::Started
+          } else {
+            DlStatus::Scheduled
+          }
+        }
+        _ => return,
+      }
+    }
+    changed();
+    if !open {
+      return;
+    }
+
+    let track_id = dl.track.id;
+    emit(Event::Started(track_id));
+    let cancelled = move || {
+      DOWNLOADS
+        .lock()
+        .unwrap()
+        .get(&track_id)
+        .map(|dl| dl.status == DlStatus::Cancelled)
+        .unwrap_or(true)
+    };
+
+    let req = match *api::API.lock().unwrap() {
+      Some(ref client) => {
+        let req = client.get(&dl.url).uncached();
+        match settings::get().format.transcode() {
+          Some(format) => req.query(&[("to", format)]),
+          None => req,
+        }
+      }
+      None => return fail(track_id, "You are not logged in".to_string()),
+    };
+    let res = match api::stream(req) {
+      Ok(res) => res,
+      Err(ref e) if offline::is_network_error(e) => {
+        offline::queue(dl);
+        changed();
+        return;
+      }
+      Err(e) => return fail(track_id, e.to_string()),
+    };
+
+    if !res.status.is_success() {
+      return fail(track_id, format!("The instance answered {}", res.status));
+    }
+
+    if let Some(dir) = dl.output.parent() {
+      if let Err(e) = fs::create_dir_all(dir) {
+        return fail(track_id, format!("{} couldn't be created: {}", dir.display(), e));
+      }
+    }
+    let mut out = dl.output.clone();
+    out.set_extension(extension(&res.headers));
+    let size = res
+      .headers
+      .get(reqwest::header::CONTENT_LENGTH)
+      .and_then(|h| h.to_str().ok())
+      .and_then(|h| h.parse().ok());
+    let file = match fs::File::create(&out) {
+      Ok(file) => file,
+      Err(e) => return fail(track_id, format!("{} couldn't be created: {}", out.display(), e)),
+    };
+    let mut file = Progress {
+      file,
+      track_id,
+      written: 0,
+      size,
+      last: Instant::now(),
+    };
+
+    if let Err(e) = res.copy_to(&mut file, &cancelled) {
+      fs::remove_file(&out).ok();
+      if offline::is_network_error(&e) {
+        offline::queue(dl);
+        changed();
+      } else {
+        fail(track_id, e.to_string());
+      }
+      return;
+    }
+
+    let (done, album) = {
+      let mut dls = DOWNLOADS.lock().unwrap();
+      let done = match dls.get_mut(&dl.track.id) {
+        Some(d) if d.status != DlStatus::Cancelled => {
+          d.ended(out);
+          d.clone()
+        }
+        // Cancelled once everything was written
+        _ => {
+          fs::remove_file(&out).ok();
+          return;
+        }
+      };
+      // Checked with the lock, so that only the last track of the album sees it
+      let album_id = dl.track.album.id;
+      let same_album = |d: &&Download| d.with_album && d.track.album.id == album_id;
+      let album: Vec<Download> = if !done.with_album || dls.values().filter(same_album).any(Download::pending) {
+        vec![]
+      } else {
+        dls
+          .values()
+          .filter(same_album)
+          .filter(|d| d.status == DlStatus::Done)
+          .cloned()
+          .collect()
+      };
+      (done, album)
+    };
+    db::record(&done);
+    if let Some(error) = hooks::after(&done, &album) {
+      if let Some(d) = DOWNLOADS.lock().unwrap().get_mut(&track_id) {
+        d.hook_error = Some(error);
+      }
+    }
+    save();
+    export::track_done(track_id);
+    changed();
+    emit(Event::Finished(track_id));
+    notifications::download_done();
+  }
+}
@@ -0,0 +1,213 @@
+//! Playlist files (`.m3u8`, and optionally `.xspf`) written next to the
+//! downloads, so that downloaded playlists can be played with any player.
+//!
+//! They are rewritten every time one of their tracks is downloaded, or when
+//! the playlist changed on the instance.
+
+use crate::{
+  api, db,
+  downloads::{DlStatus, DOWNLOADS},
+  paths, scan,
+};
+use serde_derive::*;
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Export {
+  pub playlist_id: i32,
+  pub name: String,
+  /// To know when the playlist changed
+  pub modification_date: String,
+  /// Every track, in the order of the playlist
+  pub tracks: Vec<api::Track>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Exports {
+  #[serde(default)]
+  xspf: bool,
+  playlists: HashMap<i32, Export>,
+}
+
+lazy_static::lazy_static! {
+  static ref EXPORTS: Mutex<Exports> = Mutex::new(
+    fs::read(exports_file())
+      .ok()
+      .and_then(|f| serde_json::from_slice(&f).ok())
+      .unwrap_or_default()
+  );
+}
+
+fn exports_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("playlists.json")
+}
+
+fn playlists_dir() -> PathBuf {
+  paths::music_dir().join("Playlists")
+}
+
+fn save(exports: &Exports) {
+  fs::create_dir_all(exports_file().parent().unwrap()).ok();
+  if let Ok(json) = serde_json::to_string(exports) {
+    fs::write(exports_file(), json).ok();
+  }
+}
+
+pub fn xspf_enabled() -> bool {
+  EXPORTS.lock().unwrap().xspf
+}
+
+pub fn set_xspf(enabled: bool) {
+  let mut exports = EXPORTS.lock().unwrap();
+  exports.xspf = enabled;
+  save(&exports);
+  for export in exports.playlists.values() {
+    write(export, enabled);
+  }
+}
+
+/// Starts exporting a playlist (or updates it if it changed).
+pub fn watch(export: Export) {
+  let mut exports = EXPORTS.lock().unwrap();
+  if let Some(old) = exports.playlists.get(&export.playlist_id) {
+    if old.name != export.name {
+      remove_files(old);
+    }
+  }
+  write(&export, exports.xspf);
+  exports.playlists.insert(export.playlist_id, export);
+  save(&exports);
+}
+
+/// Whether the playlist was downloaded, but changed since.
+pub fn is_outdated(playlist_id: i32, modification_date: &str) -> bool {
+  EXPORTS
+    .lock()
+    .unwrap()
+    .playlists
+    .get(&playlist_id)
+    .map(|e| e.modification_date != modification_date)
+    .unwrap_or(false)
+}
+
+/// Called when a track was downloaded, to update the playlists it is in.
+pub fn track_done(track_id: i32) {
+  let exports = EXPORTS.lock().unwrap();
+  for export in exports.playlists.values() {
+    if export.tracks.iter().any(|t| t.id == track_id) {
+      write(export, exports.xspf);
+    }
+  }
+}
+
+/// Paths are relative to the playlist file, so that the whole music folder
+/// can be moved or synced to another device.
+fn relative_path(file: &Path) -> PathBuf {
+  match file.strip_prefix(paths::music_dir()) {
+    Ok(rel) => Path::new("..").join(rel),
+    Err(_) => file.to_path_buf(),
+  }
+}
+
+/// With the ID of the playlist, since playlists of other users can have the
+/// same name as yours.
+fn file_name(export: &Export, ext: &str) -> PathBuf {
+  playlists_dir().join(format!(
+    "{} ({}).{}",
+    export.name.replace('/', "-"),
+    export.playlist_id,
+    ext
+  ))
+}
+
+fn remove_files(export: &Export) {
+  fs::remove_file(file_name(export, "m3u8")).ok();
+  fs::remove_file(file_name(export, "xspf")).ok();
+}
+
+/// Where a track is on disk: where it was downloaded, or a file of a scanned
+/// music folder.
+fn file_of(track: &api::Track) -> Option<PathBuf> {
+  DOWNLOADS
+    .lock()
+    .unwrap()
+    .get(&track.id)
+    .filter(|dl| dl.status == DlStatus::Done && dl.output.is_file())
+    .map(|dl| dl.output.clone())
+    .or_else(|| db::file_of(track.id))
+    .or_else(|| scan::find(track))
+}
+
+fn xml_escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Writes the playlist files, with the tracks that are already on disk.
+fn write(export: &Export, xspf: bool) {
+  let tracks: Vec<_> = export
+    .tracks
+    .iter()
+    .filter_map(|track| file_of(track).map(|file| (track, file)))
+    .collect();
+
+  let mut m3u = format!("#EXTM3U\n#PLAYLIST:{}\n", export.name);
+  for (track, file) in tracks.iter() {
+    m3u.push_str(&format!(
+      "#EXTINF:-1,{} - {}\n{}\n",
+      track.artist.name,
+      track.title,
+      relative_path(file).display()
+    ));
+  }
+
+  if fs::create_dir_all(playlists_dir()).is_err() {
+    return;
+  }
+  fs::write(file_name(export, "m3u8"), m3u).ok();
+
+  if xspf {
+    let mut xml = format!(
+      "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <title>{}</title>\n  <trackList>\n",
+      xml_escape(&export.name)
+    );
+    for (track, file) in tracks.iter() {
+      let location: String = relative_path(file)
+        .to_string_lossy()
+        .split('/')
+        .map(percent_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+      xml.push_str(&format!(
+        "    <track>\n      <location>{}</location>\n      <title>{}</title>\n      <creator>{}</creator>\n      <album>{}</album>\n    </track>\n",
+        xml_escape(&location),
+        xml_escape(&track.title),
+        xml_escape(&track.artist.name),
+        xml_escape(&track.album.title),
+      ));
+    }
+    xml.push_str("  </trackList>\n</playlist>\n");
+    fs::write(file_name(export, "xspf"), xml).ok();
+  }
+}
+
+/// XSPF locations are URIs
+fn percent_encode(segment: &str) -> String {
+  segment
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
@@ -0,0 +1,137 @@
+//! Listening history: plays of downloaded tracks are sent to the instance, so
+//! that its statistics and recommendations know about them.
+//!
+//! Plays are saved on disk until they are sent, so that nothing is lost while
+//! offline.
+
+use crate::{
+  api,
+  downloads::{DlStatus, DOWNLOADS},
+  offline,
+};
+use reqwest::StatusCode;
+use std::{
+  collections::HashMap,
+  fs,
+  path::PathBuf,
+  sync::Mutex,
+  thread,
+  time::{Duration, Instant},
+};
+
+pub mod mpris;
+
+/// How long before a track can be recorded again
+const REPLAY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+lazy_static::lazy_static! {
+  /// Plays that were not sent yet
+  static ref PENDING: Mutex<Vec<api::Listening>> = Mutex::new(
+    fs::read(pending_file())
+      .ok()
+      .and_then(|f| serde_json::from_slice(&f).ok())
+      .unwrap_or_default()
+  );
+
+  /// Only one thread sends plays at a time, to avoid sending them twice
+  static ref FLUSHING: Mutex<()> = Mutex::new(());
+
+  /// When tracks were last recorded
+  static ref RECENT: Mutex<HashMap<i32, Instant>> = Mutex::new(HashMap::new());
+}
+
+fn pending_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("listenings.json")
+}
+
+fn save(pending: &[api::Listening]) {
+  fs::create_dir_all(pending_file().parent().unwrap()).ok();
+  if let Ok(json) = serde_json::to_string(pending) {
+    fs::write(pending_file(), json).ok();
+  }
+}
+
+/// Records that a track was played now. Can be called from any thread.
+///
+/// A play started from mobydick is also seen by the MPRIS watcher, so the
+/// same track is only recorded once in a short time.
+pub fn record(track_id: i32) {
+  {
+    let mut recent = RECENT.lock().unwrap();
+    let now = Instant::now();
+    if let Some(last) = recent.get(&track_id) {
+      if now.duration_since(*last) < REPLAY_DELAY {
+        return;
+      }
+    }
+    recent.insert(track_id, now);
+  }
+  {
+    let mut pending = PENDING.lock().unwrap();
+    pending.push(api::Listening {
+      track: track_id,
+      creation_date: chrono::Utc::now().to_rfc3339(),
+    });
+    save(&pending);
+  }
+  thread::spawn(flush);
+}
+
+/// Sends the plays that were recorded, unless we are offline.
+///
+/// This is blocking.
+pub fn flush() {
+  let _lock = FLUSHING.lock().unwrap();
+  let listenings = PENDING.lock().unwrap().clone();
+  for listening in listenings {
+    if offline::is_offline() {
+      return;
+    }
+    let req = match *api::API.lock().unwrap() {
+      Some(ref client) => client.api_post("/history/listenings/").json(&listening),
+      None => return,
+    };
+
+    match api::send(req) {
+      // The instance refused this play (the track may have been deleted): don't
+      // try again.
+      Ok(ref res)
+        if res.status().is_success()
+          || res.status() == StatusCode::BAD_REQUEST
+          || res.status() == StatusCode::NOT_FOUND
+          || res.status() == StatusCode::UNPROCESSABLE_ENTITY =>
+      {
+        let mut pending = PENDING.lock().unwrap();
+        pending.retain(|l| {
+          l.track != listening.track || l.creation_date != listening.creation_date
+        });
+        save(&pending);
+      }
+      // The session expired: the other plays would be refused too
+      Ok(ref res)
+        if res.status() == StatusCode::UNAUTHORIZED || res.status() == StatusCode::FORBIDDEN =>
+      {
+        return;
+      }
+      Err(ref e) if offline::is_network_error(e) => {
+        offline::went_offline();
+        return;
+      }
+      // Not about the play itself, it is kept for the next time
+      Ok(_) | Err(_) => {}
+    }
+  }
+}
+
+/// Finds the track that was downloaded to `path`, if any.
+pub fn track_at(path: &std::path::Path) -> Option<i32> {
+  DOWNLOADS
+    .lock()
+    .unwrap()
+    .values()
+    .find(|dl| dl.status == DlStatus::Done && dl.output == path)
+    .map(|dl| dl.track.id)
+}
@@ -0,0 +1,102 @@
+//! Watches MPRIS media players, to record the plays of our downloads whatever
+//! the player that is used.
+
+use dbus::{
+  arg::{RefArg, Variant},
+  stdintf::org_freedesktop_dbus::Properties,
+  BusType, Connection, Message,
+};
+use std::{collections::HashMap, path::PathBuf, thread, time::Duration};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+/// Like most scrobblers, a track counts as played after half of it or four
+/// minutes (in microseconds)
+const MIN_PLAYED: i64 = 4 * 60 * 1_000_000;
+
+struct Playing {
+  url: String,
+  /// In microseconds, 0 if unknown
+  length: i64,
+  position: i64,
+}
+
+impl Playing {
+  fn played_enough(&self) -> bool {
+    self.position >= MIN_PLAYED || (self.length > 0 && self.position * 2 >= self.length)
+  }
+
+  fn file(&self) -> Option<PathBuf> {
+    reqwest::Url::parse(&self.url).ok()?.to_file_path().ok()
+  }
+}
+
+/// Starts watching the players in the background.
+pub fn watch() {
+  thread::spawn(|| {
+    let conn = match Connection::get_private(BusType::Session) {
+      Ok(conn) => conn,
+      Err(_) => return,
+    };
+    // The last file each player played, and whether we recorded it
+    let mut seen: HashMap<String, (String, bool)> = HashMap::new();
+    loop {
+      for (player, playing) in players(&conn) {
+        let last = seen
+          .entry(player)
+          .or_insert_with(|| (playing.url.clone(), false));
+        if last.0 != playing.url {
+          *last = (playing.url.clone(), false);
+        }
+        if !last.1 && playing.played_enough() {
+          if let Some(track) = playing.file().and_then(|f| super::track_at(&f)) {
+            super::record(track);
+          }
+          last.1 = true;
+        }
+      }
+      thread::sleep(POLL_INTERVAL);
+    }
+  });
+}
+
+/// What the players are currently playing.
+fn players(conn: &Connection) -> Vec<(String, Playing)> {
+  let names: Vec<String> = Message::new_method_call(
+    "org.freedesktop.DBus",
+    "/",
+    "org.freedesktop.DBus",
+    "ListNames",
+  )
+  .ok()
+  .and_then(|msg| conn.send_with_reply_and_block(msg, 500).ok())
+  .and_then(|reply| reply.get1())
+  .unwrap_or_default();
+
+  names
+    .into_iter()
+    .filter(|name| name.starts_with(PLAYER_PREFIX))
+    .filter_map(|name| {
+      let path = conn.with_path(name.as_str(), "/org/mpris/MediaPlayer2", 500);
+      let status: Variant<String> = path.get(PLAYER_INTERFACE, "PlaybackStatus").ok()?;
+      if status.0 != "Playing" {
+        return None;
+      }
+      let metadata: Variant<HashMap<String, Variant<Box<dyn RefArg>>>> =
+        path.get(PLAYER_INTERFACE, "Metadata").ok()?;
+      let position: Variant<i64> = path.get(PLAYER_INTERFACE, "Position").ok()?;
+
+      let playing = Playing {
+        url: metadata.0.get("xesam:url")?.0.as_str()?.to_string(),
+        length: metadata
+          .0
+          .get("mpris:length")
+          .and_then(|l| l.0.as_i64())
+          .unwrap_or(0),
+        position: position.0,
+      };
+      Some((name.clone(), playing))
+    })
+    .collect()
+}
@@ -0,0 +1,155 @@
+//! Commands run after downloads, to hand the files to other programs.
+//!
+//! The track hook runs after each download, and the album hook once all the
+//! tracks of an album that were queued together are downloaded (to
+//! `beet import` it, for instance). Hooks are shell commands that get the track in `MOBYDICK_*`
+//! environment variables, or URLs that are sent the same values as JSON in a
+//! POST request, to start the scan of a local media server.
+//!
+//! A hook that fails doesn't fail the download: its error is kept in
+//! `Download::hook_error`, to be shown with it. Hooks run on the download
+//! threads, so they are stopped if they take longer than `TIMEOUT`.
+
+use crate::{downloads::Download, settings};
+use std::{
+  collections::HashMap,
+  io::Read,
+  process::{Command, Stdio},
+  sync::mpsc,
+  thread,
+  time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+type Env = Vec<(&'static str, String)>;
+
+fn track_env(dl: &Download) -> Env {
+  vec![
+    ("MOBYDICK_FILE", dl.output.display().to_string()),
+    ("MOBYDICK_DIR", dir(dl)),
+    ("MOBYDICK_TRACK_ID", dl.track.id.to_string()),
+    ("MOBYDICK_TITLE", dl.track.title.clone()),
+    ("MOBYDICK_ARTIST", dl.track.artist.name.clone()),
+    ("MOBYDICK_ALBUM_ID", dl.track.album.id.to_string()),
+    ("MOBYDICK_ALBUM", dl.track.album.title.clone()),
+    ("MOBYDICK_ALBUM_ARTIST", dl.track.album.artist.name.clone()),
+    ("MOBYDICK_MBID", dl.track.mbid.clone().unwrap_or_default()),
+  ]
+}
+
+/// `dls` are the downloaded tracks of the album, and can't be empty.
+fn album_env(dls: &[Download]) -> Env {
+  let files: Vec<String> = dls.iter().map(|dl| dl.output.display().to_string()).collect();
+  let album = &dls[0].track.album;
+  vec![
+    ("MOBYDICK_DIR", dir(&dls[0])),
+    ("MOBYDICK_FILES", files.join("\n")),
+    ("MOBYDICK_TRACK_COUNT", dls.len().to_string()),
+    ("MOBYDICK_ALBUM_ID", album.id.to_string()),
+    ("MOBYDICK_ALBUM", album.title.clone()),
+    ("MOBYDICK_ALBUM_ARTIST", album.artist.name.clone()),
+  ]
+}
+
+fn dir(dl: &Download) -> String {
+  dl.output
+    .parent()
+    .map(|dir| dir.display().to_string())
+    .unwrap_or_default()
+}
+
+/// Runs a hook and waits for it. The error tells what went wrong, as in "the
+/// hook exited with status 1".
+fn run(hook: &str, env: &Env) -> Result<(), String> {
+  let hook = hook.trim();
+  if hook.starts_with("http://") || hook.starts_with("https://") {
+    // MOBYDICK_ALBUM_ID becomes album_id
+    let body: HashMap<String, &str> = env
+      .iter()
+      .map(|(name, value)| (name["MOBYDICK_".len()..].to_lowercase(), value.as_str()))
+      .collect();
+    let res = reqwest::Client::builder()
+      .timeout(TIMEOUT)
+      .build()
+      .map_err(|e| format!("couldn't be sent: {}", e))?
+      .post(hook)
+      .json(&body)
+      .send()
+      .map_err(|e| format!("couldn't be sent: {}", e))?;
+    if res.status().is_success() {
+      Ok(())
+    } else {
+      Err(format!("was answered {}", res.status()))
+    }
+  } else {
+    let mut child = Command::new("sh")
+      .arg("-c")
+      .arg(hook)
+      .envs(env.iter().cloned())
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| format!("couldn't be started: {}", e))?;
+    // Read as it comes, so that the hook doesn't wait for the pipe to be emptied
+    let mut stderr = child.stderr.take().unwrap();
+    let (errors, received) = mpsc::channel();
+    thread::spawn(move || {
+      let mut read = vec![];
+      stderr.read_to_end(&mut read).ok();
+      errors.send(read).ok();
+    });
+    let start = Instant::now();
+    let status = loop {
+      match child.try_wait() {
+        Ok(Some(status)) => break status,
+        Ok(None) if start.elapsed() < TIMEOUT => thread::sleep(Duration::from_millis(100)),
+        Ok(None) => {
+          child.kill().ok();
+          child.wait().ok();
+          return Err(format!("was stopped after {} seconds", TIMEOUT.as_secs()));
+        }
+        Err(e) => return Err(format!("couldn't be waited for: {}", e)),
+      }
+    };
+    if status.success() {
+      return Ok(());
+    }
+    let status = match status.code() {
+      Some(code) => format!("exited with status {}", code),
+      None => "was killed".to_string(),
+    };
+    // Programs it started in the background may still have it open
+    let stderr = received.recv_timeout(Duration::from_secs(1)).unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr);
+    match stderr.trim().lines().last() {
+      Some(line) => Err(format!("{}: {}", status, line)),
+      None => Err(status),
+    }
+  }
+}
+
+/// Runs the hooks of a download that just ended. `album` are the downloads of
+/// its album if it was the last one of it, or nothing.
+///
+/// This is blocking, and returns the errors of the hooks that failed.
+pub(crate) fn after(dl: &Download, album: &[Download]) -> Option<String> {
+  let settings = settings::get();
+  let mut errors = vec![];
+  if !settings.track_hook.trim().is_empty() {
+    if let Err(e) = run(&settings.track_hook, &track_env(dl)) {
+      errors.push(format!("the track hook {}", e));
+    }
+  }
+  if !album.is_empty() && !settings.album_hook.trim().is_empty() {
+    if let Err(e) = run(&settings.album_hook, &album_env(album)) {
+      errors.push(format!("the album hook {}", e));
+    }
+  }
+  if errors.is_empty() {
+    None
+  } else {
+    Some(errors.join(", "))
+  }
+}
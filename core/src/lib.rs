@@ -0,0 +1,25 @@
+//! Everything Mobydick does that doesn't need a window: talking to a Funkwhale
+//! instance, downloading tracks and deciding where they go on disk.
+//!
+//! Front-ends get notified of what changes in the background by registering
+//! listeners (see `downloads::on_change`, `offline::on_change` and
+//! `upload::on_change`). Listeners are called from worker threads.
+
+pub mod api;
+pub mod channels;
+pub mod db;
+pub mod downloads;
+pub mod export;
+pub mod history;
+pub mod hooks;
+pub mod notifications;
+pub mod offline;
+pub mod paths;
+pub mod scan;
+pub mod schedule;
+pub mod service;
+pub mod session;
+pub mod settings;
+pub mod sync;
+pub mod tags;
+pub mod upload;
@@ -0,0 +1,63 @@
+//! Desktop notifications, through the freedesktop notification service.
+
+use crate::{
+  downloads::{DlStatus, DOWNLOADS},
+  settings,
+};
+use dbus::{
+  arg::{RefArg, Variant},
+  BusType, Connection, Message,
+};
+use std::{
+  collections::HashMap,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Downloads that ended since the last notification
+static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a download that ended, and tells the user once there is nothing
+/// left to download (if they want to know).
+pub(crate) fn download_done() {
+  let finished = FINISHED.fetch_add(1, Ordering::SeqCst) + 1;
+  let busy = DOWNLOADS
+    .lock()
+    .unwrap()
+    .values()
+    .any(|dl| dl.status == DlStatus::Planned || dl.status == DlStatus::Started);
+  if busy {
+    return;
+  }
+  FINISHED.store(0, Ordering::SeqCst);
+  if settings::get().notifications {
+    let body = if finished == 1 {
+      "1 track was downloaded.".to_string()
+    } else {
+      format!("{} tracks were downloaded.", finished)
+    };
+    send("Downloads finished", &body);
+  }
+}
+
+/// Shows a notification. Nothing happens if there is no notification service.
+pub fn send(summary: &str, body: &str) {
+  let conn = match Connection::get_private(BusType::Session) {
+    Ok(conn) => conn,
+    Err(_) => return,
+  };
+  let msg = match Message::new_method_call(
+    "org.freedesktop.Notifications",
+    "/org/freedesktop/Notifications",
+    "org.freedesktop.Notifications",
+    "Notify",
+  ) {
+    Ok(msg) => msg,
+    Err(_) => return,
+  };
+  let hints: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+  let msg = msg
+    .append3("Mobydick", 0u32, "folder-music")
+    .append3(summary, body, Vec::<&str>::new())
+    .append2(hints, -1i32);
+  conn.send_with_reply_and_block(msg, 500).ok();
+}
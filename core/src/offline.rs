@@ -0,0 +1,108 @@
+//! Keeping the app usable without network.
+//!
+//! When a request fails because the instance can't be reached, we switch to
+//! offline mode: the session is kept, downloaded tracks can still be played,
+//! and new downloads wait until the instance is reachable again.
+
+use crate::{
+  api,
+  downloads::{self, DlStatus, Download},
+  history,
+};
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+  thread,
+  time::Duration,
+};
+
+/// How often we check if the instance is back
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+type Listener = Box<dyn Fn(bool) + Send + Sync>;
+
+lazy_static::lazy_static! {
+  /// Downloads that will be started once we are back online
+  static ref WAITING: Mutex<Vec<Download>> = Mutex::new(Vec::new());
+
+  static ref LISTENERS: Mutex<Vec<Listener>> = Mutex::new(Vec::new());
+}
+
+pub fn is_offline() -> bool {
+  OFFLINE.load(Ordering::SeqCst)
+}
+
+/// Whether an error means that we couldn't reach the instance at all, rather
+/// than the instance telling us something went wrong.
+pub fn is_network_error(err: &api::Error) -> bool {
+  match err {
+    api::Error::Http(e) => e.status().is_none(),
+    api::Error::Timeout => true,
+    _ => false,
+  }
+}
+
+/// Calls `f` every time we go offline (`true`) or come back online (`false`).
+/// It is called from the thread that noticed the change.
+pub fn on_change<F: Fn(bool) + Send + Sync + 'static>(f: F) {
+  LISTENERS.lock().unwrap().push(Box::new(f));
+}
+
+/// Can be called from any thread.
+pub fn went_offline() {
+  if OFFLINE.swap(true, Ordering::SeqCst) {
+    return;
+  }
+
+  notify(true);
+  thread::spawn(|| {
+    while is_offline() {
+      thread::sleep(CHECK_INTERVAL);
+      if reachable() {
+        back_online();
+      }
+    }
+  });
+}
+
+/// Keeps a download for later.
+pub fn queue(mut dl: Download) {
+  dl.status = DlStatus::Planned;
+  if let Some(d) = downloads::DOWNLOADS.lock().unwrap().get_mut(&dl.track.id) {
+    d.status = DlStatus::Planned;
+  }
+  WAITING.lock().unwrap().push(dl);
+  went_offline();
+}
+
+/// Whether the instance answers again. This is blocking.
+fn reachable() -> bool {
+  let req = match *api::API.lock().unwrap() {
+    Some(ref client) => client.get("/api/v1/instance/nodeinfo/2.0/").uncached(),
+    None => return false,
+  };
+  api::send(req).is_ok()
+}
+
+fn back_online() {
+  if !OFFLINE.swap(false, Ordering::SeqCst) {
+    return;
+  }
+  let waiting: Vec<Download> = WAITING.lock().unwrap().drain(..).collect();
+  for dl in waiting {
+    downloads::DL_JOBS.execute(dl);
+  }
+  notify(false);
+  downloads::changed();
+  thread::spawn(history::flush);
+}
+
+fn notify(offline: bool) {
+  for listener in LISTENERS.lock().unwrap().iter() {
+    listener(offline);
+  }
+}
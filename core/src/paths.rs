@@ -0,0 +1,135 @@
+//! Where downloads are saved.
+//!
+//! Tracks are placed in the music folder following a template, where
+//! `{artist}`, `{album_artist}`, `{album}` and `{title}` are replaced by the
+//! metadata of the track. Every `/` of the template starts a new folder. The
+//! extension is added once the file is downloaded.
+
+use crate::api;
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+/// Where tracks were saved before templates existed, so that the ones that
+/// were downloaded then are still found.
+pub const DEFAULT_TEMPLATE: &str = "{artist}/{album}/{title}";
+
+lazy_static::lazy_static! {
+  static ref TEMPLATE: Mutex<String> = Mutex::new(DEFAULT_TEMPLATE.to_string());
+  /// `None` for the music folder of the system
+  static ref MUSIC_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+pub fn template() -> String {
+  TEMPLATE.lock().unwrap().clone()
+}
+
+/// Changes the template used for the next downloads.
+pub fn set_template(template: &str) {
+  *TEMPLATE.lock().unwrap() = template.to_string();
+}
+
+/// The folder in which everything is downloaded.
+///
+/// Headless systems often don't have a music folder: `~/Music` is used then.
+pub fn music_dir() -> PathBuf {
+  MUSIC_DIR.lock().unwrap().clone().unwrap_or_else(|| {
+    dirs::audio_dir().unwrap_or_else(|| dirs::home_dir().unwrap_or_default().join("Music"))
+  })
+}
+
+/// Changes the folder used for the next downloads. `None` brings back the
+/// music folder of the system.
+pub fn set_music_dir(dir: Option<PathBuf>) {
+  *MUSIC_DIR.lock().unwrap() = dir;
+}
+
+/// Where a track is downloaded, with the current template.
+pub fn track(track: &api::Track) -> PathBuf {
+  music_dir().join(render(&template(), track))
+}
+
+/// Where an episode is saved: files are named after their publication date,
+/// so that they are sorted chronologically.
+pub fn episode(channel: &api::Channel, episode: &api::Episode) -> PathBuf {
+  let mut dir = music_dir();
+  if channel.is_podcast() {
+    dir = dir.join("Podcasts");
+  }
+  let date = episode.creation_date.get(..10).unwrap_or("");
+  dir
+    .join(clean(&channel.artist.name))
+    .join(clean(&format!("{} - {}.mp3", date, episode.title)))
+}
+
+/// Checks that a template only uses known placeholders, and that the names of
+/// the files include the title, so that the tracks of an album don't overwrite
+/// each other. An empty template is the default one.
+pub fn check_template(template: &str) -> Result<(), String> {
+  if template.trim().is_empty() {
+    return Ok(());
+  }
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    let end = match rest[start..].find('}') {
+      Some(len) => start + len,
+      None => return Err("A { is not closed".to_string()),
+    };
+    match &rest[start..=end] {
+      "{artist}" | "{album_artist}" | "{album}" | "{title}" => {}
+      unknown => return Err(format!("{} is not a known placeholder", unknown)),
+    }
+    rest = &rest[end + 1..];
+  }
+  match template.split('/').rfind(|part| !part.is_empty()) {
+    Some(file) if file.contains("{title}") => Ok(()),
+    _ => Err("The names of the files must include {title}".to_string()),
+  }
+}
+
+/// Fills a template with the metadata of a track. The result is relative to
+/// the music folder.
+pub fn render(template: &str, track: &api::Track) -> PathBuf {
+  let mut parts: Vec<String> = template
+    .split('/')
+    .filter(|part| !part.is_empty())
+    .map(|part| {
+      clean(
+        &part
+          .replace("{artist}", &track.artist.name)
+          .replace("{album_artist}", &track.album.artist.name)
+          .replace("{album}", &track.album.title)
+          .replace("{title}", &track.title),
+      )
+    })
+    .collect();
+  // A placeholder extension, replaced by the real one once downloaded. It is
+  // appended rather than set, so that titles with a dot are not cut.
+  if let Some(file) = parts.last_mut() {
+    file.push_str(".mp3");
+  }
+  parts.iter().collect()
+}
+
+/// The file that was downloaded to `planned`. Since we only know the extension
+/// of a file once it is downloaded, any file with the same name in the same
+/// folder counts.
+pub fn existing(planned: &Path) -> Option<PathBuf> {
+  let stem = planned.file_stem()?.to_os_string();
+  fs::read_dir(planned.parent()?)
+    .ok()?
+    .filter_map(|f| f.ok())
+    .map(|f| f.path())
+    .find(|f| f.is_file() && f.file_stem().map(|s| s.to_os_string()) == Some(stem.clone()))
+}
+
+/// Makes a name safe to use as a single file or folder name.
+fn clean(name: &str) -> String {
+  let name = name.replace('/', "-").replace('\0', "");
+  match name.trim() {
+    "" | "." | ".." => "_".to_string(),
+    name => name.to_string(),
+  }
+}
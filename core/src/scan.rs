@@ -0,0 +1,183 @@
+//! Finding the tracks of the instance in a music folder that was not
+//! downloaded with Mobydick.
+//!
+//! Scanning a folder reads the tags of every audio file in it and indexes them
+//! in the database. Tracks are then matched to these files by MusicBrainz ID,
+//! or by their artist, album, title and duration.
+
+use crate::{api, db::DB, tags, upload};
+use rusqlite::{params, OptionalExtension, NO_PARAMS};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::UNIX_EPOCH,
+};
+
+/// How far durations can be for two tracks to be the same, in seconds.
+const DURATION_TOLERANCE: u64 = 3;
+
+#[derive(Debug, Default)]
+pub struct Scanned {
+  /// Files that were read
+  pub indexed: usize,
+  /// Files that didn't change since the last scan
+  pub unchanged: usize,
+  /// Files that are not audio files we can read
+  pub unreadable: usize,
+  /// Files that were indexed but are not there anymore
+  pub removed: usize,
+}
+
+/// Lowercase, without punctuation and with single spaces, so that
+/// "The Beatles" and "beatles", or "Rock & Roll" and "Rock and roll" are the
+/// same.
+pub fn normalize(s: &str) -> String {
+  let s = s.to_lowercase().replace('&', " and ");
+  let words: Vec<String> = s
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|w| !w.is_empty())
+    .map(String::from)
+    .collect();
+  match words.split_first() {
+    Some((first, rest)) if first == "the" && !rest.is_empty() => rest.join(" "),
+    _ => words.join(" "),
+  }
+}
+
+fn modified(path: &Path) -> i64 {
+  fs::metadata(path)
+    .and_then(|m| m.modified())
+    .ok()
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or_default()
+}
+
+/// Indexes the audio files of a folder, and forgets the ones that were
+/// removed from it since the last scan. `progress` is called with the number
+/// of files seen so far, and the total.
+///
+/// This is blocking, and slow the first time a large folder is scanned.
+pub fn scan<F: Fn(usize, usize)>(dir: &Path, progress: F) -> rusqlite::Result<Scanned> {
+  let mut scanned = Scanned::default();
+  let files = upload::audio_files(dir);
+  for (i, file) in files.iter().enumerate() {
+    progress(i, files.len());
+    let path = file.to_string_lossy().into_owned();
+    let mtime = modified(file);
+    let known: Option<i64> = DB
+      .lock()
+      .unwrap()
+      .query_row(
+        "SELECT modified FROM local_files WHERE path = ?1",
+        params![path],
+        |row| row.get(0),
+      )
+      .optional()?;
+    if known == Some(mtime) {
+      scanned.unchanged += 1;
+      continue;
+    }
+
+    let info = match tags::read(file) {
+      Some(info) => info,
+      None => {
+        scanned.unreadable += 1;
+        continue;
+      }
+    };
+    // Untagged files are often named after their title
+    let title = info.title.clone().unwrap_or_else(|| {
+      file
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+    });
+    DB.lock().unwrap().execute(
+      "INSERT OR REPLACE INTO local_files
+         (path, musicbrainz_id, artist, album_artist, album, title, duration, modified)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+      params![
+        path,
+        info.musicbrainz_id,
+        normalize(&info.artist.unwrap_or_default()),
+        normalize(&info.album_artist.unwrap_or_default()),
+        normalize(&info.album.unwrap_or_default()),
+        normalize(&title),
+        info.duration as i64,
+        mtime,
+      ],
+    )?;
+    scanned.indexed += 1;
+  }
+  progress(files.len(), files.len());
+
+  let indexed: Vec<String> = {
+    let db = DB.lock().unwrap();
+    let mut stmt = db.prepare("SELECT path FROM local_files")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+    rows.filter_map(|p| p.ok()).collect()
+  };
+  for path in indexed {
+    let file = PathBuf::from(&path);
+    if file.starts_with(dir) && !file.is_file() {
+      DB.lock()
+        .unwrap()
+        .execute("DELETE FROM local_files WHERE path = ?1", params![path])?;
+      scanned.removed += 1;
+    }
+  }
+  Ok(scanned)
+}
+
+/// A file of a scanned folder that contains this track, if any.
+pub fn find(track: &api::Track) -> Option<PathBuf> {
+  let db = DB.lock().unwrap();
+
+  if let Some(ref mbid) = track.mbid {
+    let mut stmt = db
+      .prepare("SELECT path FROM local_files WHERE musicbrainz_id = ?1")
+      .ok()?;
+    let found = stmt
+      .query_map(params![mbid], |row| row.get::<_, String>(0))
+      .ok()?
+      .filter_map(|p| p.ok())
+      .map(PathBuf::from)
+      .find(|p| p.is_file());
+    if found.is_some() {
+      return found;
+    }
+  }
+
+  let artists = [
+    normalize(&track.artist.name),
+    normalize(&track.album.artist.name),
+  ];
+  let album = normalize(&track.album.title);
+  let mut stmt = db
+    .prepare(
+      "SELECT path, artist, album_artist, album, duration FROM local_files WHERE title = ?1",
+    )
+    .ok()?;
+  let candidates: Vec<(String, String, String, String, i64)> = stmt
+    .query_map(params![normalize(&track.title)], |row| {
+      Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+    })
+    .ok()?
+    .filter_map(|c| c.ok())
+    .collect();
+
+  candidates
+    .into_iter()
+    .filter(|(_, artist, album_artist, _, _)| {
+      artists.contains(artist) || artists.contains(album_artist)
+    })
+    // Untagged albums and unknown durations are given the benefit of the doubt
+    .filter(|(_, _, _, file_album, _)| file_album.is_empty() || *file_album == album)
+    .filter(|(_, _, _, _, duration)| match track.duration {
+      Some(d) if *duration > 0 => (*duration - d as i64).unsigned_abs() <= DURATION_TOLERANCE,
+      _ => true,
+    })
+    .map(|(path, ..)| PathBuf::from(path))
+    .find(|p| p.is_file())
+}
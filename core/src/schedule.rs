@@ -0,0 +1,267 @@
+//! Downloading at the right time.
+//!
+//! Time windows (in the settings) restrict when tracks are downloaded:
+//! outside of them, downloads wait in the `Scheduled` state. Jobs queue a
+//! selection later, or regularly, like "this artist tonight" or "my
+//! favorites every Sunday". Both are checked every minute by `watch`.
+
+use crate::{
+  api,
+  downloads::{self, Download},
+  offline, settings,
+  sync::Source,
+};
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Weekday};
+use serde_derive::*;
+use std::{
+  fs,
+  path::PathBuf,
+  sync::{Mutex, Once},
+  thread,
+  time::Duration,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static WATCH: Once = Once::new();
+
+/// Hours during which downloads can run. Windows that end before they start
+/// go over midnight.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+  #[serde(with = "hour")]
+  pub start: NaiveTime,
+  #[serde(with = "hour")]
+  pub end: NaiveTime,
+}
+
+impl TimeWindow {
+  pub fn contains(&self, time: NaiveTime) -> bool {
+    if self.start <= self.end {
+      time >= self.start && time < self.end
+    } else {
+      time >= self.start || time < self.end
+    }
+  }
+}
+
+/// Times are written like `01:00` in the settings.
+mod hour {
+  use chrono::NaiveTime;
+  use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(time: &NaiveTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&time.format("%H:%M").to_string())
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(d)?;
+    super::parse_hour(&time).ok_or_else(|| D::Error::custom(format!("invalid time: {}", time)))
+  }
+}
+
+/// Parses times like `01:00` or `23:30`.
+pub fn parse_hour(time: &str) -> Option<NaiveTime> {
+  NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+}
+
+fn open_at(windows: &[TimeWindow], time: NaiveTime) -> bool {
+  windows.is_empty() || windows.iter().any(|w| w.contains(time))
+}
+
+/// Whether tracks can be downloaded now.
+pub fn is_open() -> bool {
+  open_at(&settings::get().windows, Local::now().time())
+}
+
+/// The first time after `after` that is `time` of the day.
+fn next_time(after: DateTime<Local>, time: NaiveTime) -> DateTime<Local> {
+  let today = after.date_naive();
+  [today, today + chrono::Duration::days(1)]
+    .iter()
+    .filter_map(|day| Local.from_local_datetime(&day.and_time(time)).earliest())
+    .find(|at| *at > after)
+    .unwrap_or(after)
+}
+
+/// When a download window opens next: now if one is open, or 1 AM if no
+/// window is set.
+pub fn tonight() -> DateTime<Local> {
+  let now = Local::now();
+  let windows = settings::get().windows;
+  if !windows.is_empty() && open_at(&windows, now.time()) {
+    return now;
+  }
+  let starts = if windows.is_empty() {
+    vec![NaiveTime::from_hms_opt(1, 0, 0).unwrap()]
+  } else {
+    windows.iter().map(|w| w.start).collect()
+  };
+  starts
+    .into_iter()
+    .map(|start| next_time(now, start))
+    .min()
+    .unwrap_or(now)
+}
+
+/// Like `tonight`, but on the next `day` (today if it is `day` and the window
+/// didn't open yet).
+pub fn next(day: Weekday) -> DateTime<Local> {
+  let mut at = tonight();
+  while at.weekday() != day {
+    at += chrono::Duration::days(1);
+  }
+  at
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+  Once,
+  Daily,
+  Weekly,
+}
+
+/// Queues a selection at a given time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+  pub id: u32,
+  pub source: Source,
+  /// When it runs next
+  pub next: DateTime<Local>,
+  pub repeat: Repeat,
+}
+
+lazy_static::lazy_static! {
+  /// Only guards the file: the app and the CLI both change it, so it is read
+  /// again every time.
+  static ref JOBS: Mutex<()> = Mutex::new(());
+}
+
+fn jobs_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("schedule.json")
+}
+
+fn read() -> Vec<Job> {
+  fs::read(jobs_file())
+    .ok()
+    .and_then(|f| serde_json::from_slice(&f).ok())
+    .unwrap_or_default()
+}
+
+fn save(jobs: &[Job]) {
+  fs::create_dir_all(jobs_file().parent().unwrap()).ok();
+  if let Ok(json) = serde_json::to_string(jobs) {
+    fs::write(jobs_file(), json).ok();
+  }
+}
+
+/// Changes the jobs as they are on disk, and saves them.
+fn update<T, F: FnOnce(&mut Vec<Job>) -> T>(f: F) -> T {
+  let _lock = JOBS.lock().unwrap();
+  let mut jobs = read();
+  let res = f(&mut jobs);
+  save(&jobs);
+  res
+}
+
+pub fn jobs() -> Vec<Job> {
+  let _lock = JOBS.lock().unwrap();
+  read()
+}
+
+pub fn add(source: Source, next: DateTime<Local>, repeat: Repeat) -> Job {
+  update(|jobs| {
+    let job = Job {
+      id: jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1,
+      source,
+      next,
+      repeat,
+    };
+    jobs.push(job.clone());
+    job
+  })
+}
+
+pub fn remove(id: u32) -> bool {
+  update(|jobs| {
+    let count = jobs.len();
+    jobs.retain(|j| j.id != id);
+    jobs.len() != count
+  })
+}
+
+/// Queues the tracks of the jobs that are due, and plans their next run.
+/// Jobs whose tracks can't be listed are tried again at the next check.
+/// Returns how many tracks were queued.
+///
+/// This is blocking.
+pub fn run_due(client: &api::RequestContext) -> usize {
+  let now = Local::now();
+  let due: Vec<Job> = jobs().into_iter().filter(|j| j.next <= now).collect();
+  let mut queued = 0;
+  for job in due {
+    let tracks = match job.source.tracks(client) {
+      Ok(tracks) => tracks,
+      Err(e) => {
+        println!("Couldn't list the tracks of {}: {}", job.source, e);
+        continue;
+      }
+    };
+    let dls: Vec<Download> = tracks
+      .into_iter()
+      .map(Download::new)
+      .filter(|dl| !downloads::on_disk(dl))
+      .collect();
+    queued += dls.len();
+    downloads::enqueue(dls);
+
+    update(|jobs| {
+      let step = match job.repeat {
+        Repeat::Once => {
+          jobs.retain(|j| j.id != job.id);
+          None
+        }
+        Repeat::Daily => Some(chrono::Duration::days(1)),
+        Repeat::Weekly => Some(chrono::Duration::weeks(1)),
+      };
+      if let (Some(step), Some(j)) = (step, jobs.iter_mut().find(|j| j.id == job.id)) {
+        while j.next <= now {
+          j.next += step;
+        }
+      }
+    });
+  }
+  if queued > 0 {
+    downloads::save();
+  }
+  queued
+}
+
+/// Runs the jobs that are due, and starts the downloads that were waiting
+/// for their window if it is open.
+///
+/// This is blocking.
+pub fn check() {
+  if !offline::is_offline() {
+    let client = api::API.lock().unwrap().clone();
+    if let Some(client) = client {
+      run_due(&client);
+    }
+  }
+  if is_open() {
+    downloads::start_scheduled();
+  }
+}
+
+/// Checks every minute, in the background.
+pub fn watch() {
+  WATCH.call_once(|| {
+    thread::spawn(|| loop {
+      check();
+      thread::sleep(CHECK_INTERVAL);
+    });
+  });
+}
@@ -0,0 +1,317 @@
+//! A D-Bus service, to drive the downloads from scripts and other apps.
+//!
+//! It owns `xyz.gelez.mobydick` on the session bus, with an object at
+//! `/xyz/gelez/mobydick` that implements the `xyz.gelez.mobydick` interface:
+//!
+//! - `Search(s query) -> a(sis)`: artists, albums and tracks, as (kind, ID,
+//!   name). It fails if the instance didn't answer after 20 seconds, before
+//!   the call times out.
+//! - `EnqueueTrack(i id)`, `EnqueueAlbum(i id)` and `EnqueueArtist(i id)`:
+//!   queue what is not downloaded yet. They return right away, and the tracks
+//!   are fetched in the background.
+//! - `Pause()`, `Resume()`, and `Cancel(i track_id) -> b`
+//! - `Status() -> (b paused, a(isss) downloads)`: every download as (track
+//!   ID, status, title, file)
+//!
+//! and sends the `Started(i id, s title)`, `Progress(i id, t written, t size)`
+//! (the size is 0 if it is unknown), `Finished(i id, s file)` and
+//! `Failed(i id, s error)` signals for downloads, and the
+//! `Enqueued(i id, s kind, u queued)` or `EnqueueFailed(i id, s kind, s error)`
+//! signals once what was asked with `Enqueue*` is queued (`kind` is `track`,
+//! `album` or `artist`).
+
+use crate::{
+  api::{self, RequestContext},
+  downloads::{self, DlStatus, Download, Event, DOWNLOADS},
+};
+use dbus::{
+  tree::{Factory, MethodErr},
+  BusType, Connection, Message, NameFlag, RequestNameReply,
+};
+use std::{
+  sync::{mpsc, Arc, Mutex, Once},
+  thread,
+  time::Duration,
+};
+
+pub const NAME: &str = "xyz.gelez.mobydick";
+pub const PATH: &str = "/xyz/gelez/mobydick";
+
+/// How long to wait for method calls before sending the pending signals, in
+/// milliseconds
+const TICK: u32 = 100;
+
+/// How long to wait for search results: D-Bus calls time out after 25 seconds
+/// by default
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// What the worker threads have to send
+enum Outgoing {
+  Event(Event),
+  Message(Message),
+}
+
+type Sender = Arc<Mutex<mpsc::Sender<Outgoing>>>;
+
+static START: Once = Once::new();
+
+/// Serves in the background. If there is no session bus, or if the name is
+/// already taken, it is only reported.
+pub fn start() {
+  START.call_once(|| {
+    thread::spawn(|| {
+      if let Err(e) = serve() {
+        println!("The D-Bus service couldn't start: {}", e);
+      }
+    });
+  });
+}
+
+/// Serves until the bus goes away.
+///
+/// This is blocking.
+pub fn serve() -> Result<(), String> {
+  let conn = Connection::get_private(BusType::Session).map_err(|e| e.to_string())?;
+
+  // Events come from the download threads, and what takes a while is done in
+  // other threads, but everything is sent from here. It all goes through the
+  // same channel, so that signals are sent in order.
+  let (sender, received) = mpsc::channel();
+  let sender: Sender = Arc::new(Mutex::new(sender));
+  let events = sender.clone();
+  downloads::on_event(move |event| {
+    events.lock().unwrap().send(Outgoing::Event(event.clone())).ok();
+  });
+
+  let f = Factory::new_fn::<()>();
+  let (track_sender, album_sender, artist_sender) = (sender.clone(), sender.clone(), sender.clone());
+  let interface = f
+    .interface(NAME, ())
+    .add_m(
+      // Answered from another thread, in `serve_search`
+      f.method("Search", (), |_| Ok(vec![]))
+        .inarg::<&str, _>("query")
+        .outarg::<Vec<(&str, i32, &str)>, _>("results"),
+    )
+    .add_m(
+      f.method("EnqueueTrack", (), move |m| {
+        let id: i32 = m.msg.read1()?;
+        let client = client()?;
+        enqueue_later(&track_sender, id, "track", move || {
+          let track: api::Track = api::send(client.api_get(format!("/tracks/{}/", id)))?.json()?;
+          Ok(vec![Download::new(track)])
+        });
+        Ok(vec![m.msg.method_return()])
+      })
+      .inarg::<i32, _>("id"),
+    )
+    .add_m(
+      f.method("EnqueueAlbum", (), move |m| {
+        let id: i32 = m.msg.read1()?;
+        let client = client()?;
+        enqueue_later(&album_sender, id, "album", move || {
+          let album = client.album(id)?;
+          Ok(downloads::of_album(&client, &album))
+        });
+        Ok(vec![m.msg.method_return()])
+      })
+      .inarg::<i32, _>("id"),
+    )
+    .add_m(
+      f.method("EnqueueArtist", (), move |m| {
+        let id: i32 = m.msg.read1()?;
+        let client = client()?;
+        enqueue_later(&artist_sender, id, "artist", move || {
+          Ok(downloads::of_artist(&client, id, None))
+        });
+        Ok(vec![m.msg.method_return()])
+      })
+      .inarg::<i32, _>("id"),
+    )
+    .add_m(f.method("Pause", (), |m| {
+      downloads::pause();
+      Ok(vec![m.msg.method_return()])
+    }))
+    .add_m(f.method("Resume", (), |m| {
+      downloads::unpause();
+      Ok(vec![m.msg.method_return()])
+    }))
+    .add_m(
+      f.method("Cancel", (), |m| {
+        let id: i32 = m.msg.read1()?;
+        Ok(vec![m.msg.method_return().append1(downloads::cancel(id))])
+      })
+      .inarg::<i32, _>("track_id")
+      .outarg::<bool, _>("cancelled"),
+    )
+    .add_m(
+      f.method("Status", (), |m| {
+        Ok(vec![m.msg.method_return().append2(downloads::is_paused(), status())])
+      })
+      .outarg::<bool, _>("paused")
+      .outarg::<Vec<(i32, &str, &str, &str)>, _>("downloads"),
+    )
+    .add_s(f.signal("Started", ()).sarg::<i32, _>("id").sarg::<&str, _>("title"))
+    .add_s(
+      f.signal("Progress", ())
+        .sarg::<i32, _>("id")
+        .sarg::<u64, _>("written")
+        .sarg::<u64, _>("size"),
+    )
+    .add_s(f.signal("Finished", ()).sarg::<i32, _>("id").sarg::<&str, _>("file"))
+    .add_s(f.signal("Failed", ()).sarg::<i32, _>("id").sarg::<&str, _>("error"))
+    .add_s(
+      f.signal("Enqueued", ())
+        .sarg::<i32, _>("id")
+        .sarg::<&str, _>("kind")
+        .sarg::<u32, _>("queued"),
+    )
+    .add_s(
+      f.signal("EnqueueFailed", ())
+        .sarg::<i32, _>("id")
+        .sarg::<&str, _>("kind")
+        .sarg::<&str, _>("error"),
+    );
+  let tree = f
+    .tree(())
+    .add(f.object_path(PATH, ()).introspectable().add(interface));
+  tree.set_registered(&conn, true).map_err(|e| e.to_string())?;
+  // Only once everything is ready to answer
+  match conn.register_name(NAME, NameFlag::DoNotQueue as u32) {
+    Ok(RequestNameReply::PrimaryOwner) | Ok(RequestNameReply::AlreadyOwner) => {}
+    Ok(_) => return Err(format!("{} is already used by another program", NAME)),
+    Err(e) => return Err(e.to_string()),
+  }
+
+  loop {
+    for msg in conn.incoming(TICK) {
+      let search = msg.interface().is_some_and(|i| &*i == NAME)
+        && msg.member().is_some_and(|m| &*m == "Search");
+      if search {
+        serve_search(msg, sender.clone());
+      } else if let Some(replies) = tree.handle(&msg) {
+        for reply in replies {
+          conn.send(reply).map_err(|_| "The bus went away".to_string())?;
+        }
+      }
+    }
+    for outgoing in received.try_iter() {
+      let msg = match outgoing {
+        Outgoing::Event(event) => signal(&event),
+        Outgoing::Message(msg) => Some(msg),
+      };
+      if let Some(msg) = msg {
+        conn.send(msg).map_err(|_| "The bus went away".to_string())?;
+      }
+    }
+  }
+}
+
+/// Answers a `Search` call from another thread, as the instance may take a
+/// while.
+fn serve_search(call: Message, sender: Sender) {
+  thread::spawn(move || {
+    let res = call
+      .read1::<&str>()
+      .map_err(MethodErr::from)
+      .map(String::from)
+      .and_then(|query| Ok((client()?, query)));
+    let reply = match res {
+      Ok((client, query)) => {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || tx.send(search(&client, &query)).ok());
+        match rx.recv_timeout(SEARCH_TIMEOUT) {
+          Ok(Ok(results)) => call.method_return().append1(results),
+          Ok(Err(e)) => MethodErr::failed(&e).to_message(&call),
+          Err(_) => MethodErr::failed(&api::Error::Timeout).to_message(&call),
+        }
+      }
+      Err(e) => e.to_message(&call),
+    };
+    sender.lock().unwrap().send(Outgoing::Message(reply)).ok();
+  });
+}
+
+/// Fetches what to download in another thread, queues it, and sends
+/// `Enqueued` or `EnqueueFailed`.
+fn enqueue_later<F>(sender: &Sender, id: i32, kind: &'static str, fetch: F)
+where
+  F: FnOnce() -> Result<Vec<Download>, api::Error> + Send + 'static,
+{
+  let sender = sender.clone();
+  thread::spawn(move || {
+    let send = |msg| sender.lock().unwrap().send(Outgoing::Message(msg)).ok();
+    match fetch() {
+      Ok(dls) => {
+        let dls: Vec<Download> = dls.into_iter().filter(|dl| !downloads::on_disk(dl)).collect();
+        // Sent first, so that it comes before the signals of the downloads
+        send(message("Enqueued").append3(id, kind, dls.len() as u32));
+        downloads::enqueue(dls);
+        downloads::save();
+      }
+      Err(e) => {
+        send(message("EnqueueFailed").append3(id, kind, e.to_string()));
+      }
+    }
+  });
+}
+
+fn client() -> Result<RequestContext, MethodErr> {
+  api::API
+    .lock()
+    .unwrap()
+    .clone()
+    .ok_or_else(|| MethodErr::failed(&"Not logged in"))
+}
+
+/// This is blocking.
+fn search(client: &RequestContext, query: &str) -> Result<Vec<(&'static str, i32, String)>, api::Error> {
+  let res: api::SearchResult = api::send(client.api_get("/search").query(&api::SearchQuery {
+    query: query.to_string(),
+  }))?
+  .json()?;
+  Ok(
+    res
+      .artists
+      .into_iter()
+      .map(|a| ("artist", a.id, a.name))
+      .chain(res.albums.into_iter().map(|a| ("album", a.id, a.title)))
+      .chain(res.tracks.into_iter().map(|t| ("track", t.id, t.title)))
+      .collect(),
+  )
+}
+
+fn status() -> Vec<(i32, &'static str, String, String)> {
+  let mut dls: Vec<Download> = DOWNLOADS.lock().unwrap().values().cloned().collect();
+  dls.sort_by_key(|dl| dl.track.id);
+  dls
+    .into_iter()
+    .map(|dl| {
+      let status = match dl.status {
+        DlStatus::Planned => "planned",
+        DlStatus::Scheduled => "scheduled",
+        DlStatus::Started => "started",
+        DlStatus::Done => "done",
+        DlStatus::Cancelled => "cancelled",
+        DlStatus::Failed => "failed",
+      };
+      (dl.track.id, status, dl.track.title, dl.output.display().to_string())
+    })
+    .collect()
+}
+
+fn message(name: &'static str) -> Message {
+  Message::signal(&PATH.into(), &NAME.into(), &name.into())
+}
+
+fn signal(event: &Event) -> Option<Message> {
+  let dl = |id: &i32| DOWNLOADS.lock().unwrap().get(id).cloned();
+  Some(match event {
+    Event::Started(id) => message("Started").append2(*id, dl(id)?.track.title),
+    Event::Progress(id, written, size) => {
+      message("Progress").append3(*id, *written, size.unwrap_or(0))
+    }
+    Event::Finished(id) => message("Finished").append2(*id, dl(id)?.output.display().to_string()),
+    Event::Failed(id, error) => message("Failed").append2(*id, error.as_str()),
+  })
+}
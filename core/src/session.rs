@@ -0,0 +1,93 @@
+//! The account we are logged in with, saved in `data.json` so that every
+//! front-end (and every run) shares it.
+
+use crate::{
+  api::{self, network::SettingsError, nodeinfo, ApiVersion, RequestContext},
+  downloads,
+};
+use std::{fs, path::PathBuf};
+
+fn data_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("data.json")
+}
+
+/// Restores the saved session, if any. Returns `false` if we are not logged in,
+/// and an error if we are but the network settings are invalid.
+pub fn load() -> Result<bool, SettingsError> {
+  let json: serde_json::Value = match fs::read(data_file())
+    .ok()
+    .and_then(|f| serde_json::from_slice(&f).ok())
+  {
+    Some(json) => json,
+    None => return Ok(false),
+  };
+  let (instance, token) = match (json["instance"].as_str(), json["token"].as_str()) {
+    (Some(instance), Some(token)) => (instance.to_string(), token.to_string()),
+    _ => return Ok(false),
+  };
+  let mut ctx = RequestContext::new(instance.clone())?;
+  ctx.auth(token);
+  ctx.set_username(json["username"].as_str().unwrap_or_default().to_string());
+  match serde_json::from_value(json["api_version"].clone()) {
+    Ok(version) => ctx.negotiate(version),
+    Err(_) => detect_version(instance),
+  }
+  *api::API.lock().unwrap() = Some(ctx);
+  Ok(true)
+}
+
+/// For sessions saved before we knew about API versions.
+fn detect_version(instance: String) {
+  std::thread::spawn(move || {
+    if let Ok(info) = nodeinfo::discover(&instance) {
+      if let Some(ref mut client) = *api::API.lock().unwrap() {
+        client.negotiate(ApiVersion::from_version(&info.version));
+      }
+    }
+  });
+}
+
+pub fn save() {
+  let json = match *api::API.lock().unwrap() {
+    Some(ref client) => client.to_json(),
+    None => return,
+  };
+  fs::create_dir_all(data_file().parent().unwrap()).ok();
+  fs::write(data_file(), serde_json::to_string(&json).unwrap()).ok();
+}
+
+/// Logs in and saves the session.
+///
+/// This is blocking.
+pub fn login(instance: &str, username: String, password: String) -> Result<(), String> {
+  let info = nodeinfo::discover(instance).map_err(|e| e.to_string())?;
+  let mut ctx = RequestContext::new(info.url.clone()).map_err(|e| e.to_string())?;
+  ctx.negotiate(ApiVersion::from_version(&info.version));
+
+  let res = api::send(ctx.post("/api/v1/token/").json(&api::LoginData {
+    username: username.clone(),
+    password,
+  }))
+  .map_err(|e| e.to_string())?;
+  let login: api::LoginInfo = res
+    .json()
+    .map_err(|_| "Something went wrong, check your username and password.".to_string())?;
+  ctx.auth(login.token);
+  ctx.set_username(username);
+
+  *api::API.lock().unwrap() = Some(ctx);
+  save();
+  Ok(())
+}
+
+/// Logs out, and forgets the downloads of this account. Files are kept.
+pub fn forget() {
+  fs::remove_file(data_file()).ok();
+  if let Some(client) = api::API.lock().unwrap().take() {
+    client.cache().clear();
+  }
+  downloads::clear();
+}
@@ -0,0 +1,185 @@
+//! User preferences, saved in `settings.toml`.
+//!
+//! Settings take effect as soon as they are changed: `update` saves them and
+//! hands the new values to the modules that use them.
+
+use crate::{
+  api::cache::{self, Cache},
+  downloads, paths,
+  schedule::TimeWindow,
+};
+use serde_derive::*;
+use std::{fs, path::PathBuf, sync::Mutex};
+
+/// Each step brings the settings from a version to the next, the first one
+/// from version 1 to 2. Steps must never be changed once released: add new
+/// ones instead.
+const MIGRATIONS: &[fn(&mut toml::value::Table)] = &[];
+
+/// The version of the settings this build writes
+pub const VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// What the files are converted to by the instance before being downloaded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+  /// As they were uploaded
+  Original,
+  Mp3,
+  Ogg,
+  Opus,
+}
+
+impl Format {
+  pub const ALL: &'static [Format] = &[Format::Original, Format::Mp3, Format::Ogg, Format::Opus];
+
+  /// The `to` parameter of the `listen` endpoint, if files are converted
+  pub fn transcode(self) -> Option<&'static str> {
+    match self {
+      Format::Original => None,
+      Format::Mp3 => Some("mp3"),
+      Format::Ogg => Some("ogg"),
+      Format::Opus => Some("opus"),
+    }
+  }
+
+  /// As saved in the settings
+  pub fn id(self) -> &'static str {
+    self.transcode().unwrap_or("original")
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Format::Original => "Original",
+      Format::Mp3 => "MP3",
+      Format::Ogg => "Ogg Vorbis",
+      Format::Opus => "Opus",
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+  pub version: u32,
+  /// Where tracks are downloaded. The music folder of the system if not set.
+  pub download_dir: Option<PathBuf>,
+  /// See `paths`
+  pub template: String,
+  pub format: Format,
+  /// How many tracks are downloaded at the same time
+  pub concurrency: usize,
+  /// Maximum size of the HTTP cache, in MiB
+  pub cache_size: u64,
+  /// Whether to show a notification when downloads are done
+  pub notifications: bool,
+  /// Run after each download, see `hooks`
+  pub track_hook: String,
+  /// Run once the tracks of an album that were queued together are downloaded
+  pub album_hook: String,
+  /// When tracks can be downloaded. Anytime if there are none.
+  ///
+  /// It must stay last: TOML can't have plain values after a table.
+  pub windows: Vec<TimeWindow>,
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    Settings {
+      version: VERSION,
+      download_dir: None,
+      template: paths::DEFAULT_TEMPLATE.to_string(),
+      format: Format::Original,
+      concurrency: 5,
+      cache_size: cache::DEFAULT_MAX_SIZE / 1024 / 1024,
+      notifications: true,
+      track_hook: String::new(),
+      album_hook: String::new(),
+      windows: vec![],
+    }
+  }
+}
+
+lazy_static::lazy_static! {
+  static ref SETTINGS: Mutex<Settings> = Mutex::new(Settings::default());
+}
+
+fn settings_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("settings.toml")
+}
+
+/// Brings settings written by an older version up to date.
+fn migrate(mut table: toml::value::Table) -> toml::value::Table {
+  let version = table
+    .get("version")
+    .and_then(|v| v.as_integer())
+    .unwrap_or(1)
+    .max(1) as usize;
+  if version > VERSION as usize {
+    println!(
+      "Your settings were saved by a newer version of Mobydick, some of them may be ignored."
+    );
+  }
+  for step in MIGRATIONS.iter().skip(version - 1) {
+    step(&mut table);
+  }
+  table.insert("version".to_string(), toml::Value::Integer(VERSION.into()));
+  table
+}
+
+/// Reads the settings and applies them. Invalid files are reported and
+/// ignored, but not overwritten until the settings are changed.
+pub fn load() {
+  let settings = match fs::read_to_string(settings_file()) {
+    Ok(file) => toml::from_str::<toml::value::Table>(&file)
+      .and_then(|table| toml::Value::Table(migrate(table)).try_into())
+      .unwrap_or_else(|e| {
+        println!("Couldn't read your settings, the default ones are used: {}", e);
+        Settings::default()
+      }),
+    Err(_) => Settings::default(),
+  };
+  apply(&settings);
+  *SETTINGS.lock().unwrap() = settings;
+}
+
+pub fn get() -> Settings {
+  SETTINGS.lock().unwrap().clone()
+}
+
+/// Changes some settings, saves them and applies them right away.
+pub fn update<F: FnOnce(&mut Settings)>(f: F) {
+  let settings = {
+    let mut settings = SETTINGS.lock().unwrap();
+    f(&mut settings);
+    settings.clone()
+  };
+  save(&settings);
+  apply(&settings);
+}
+
+fn save(settings: &Settings) {
+  fs::create_dir_all(settings_file().parent().unwrap()).ok();
+  match toml::to_string_pretty(settings) {
+    Ok(file) => {
+      fs::write(settings_file(), file).ok();
+    }
+    Err(e) => println!("Couldn't save your settings: {}", e),
+  }
+}
+
+fn apply(settings: &Settings) {
+  paths::set_music_dir(settings.download_dir.clone());
+  // It may have been edited by hand
+  let invalid = paths::check_template(&settings.template).is_err();
+  paths::set_template(if settings.template.trim().is_empty() || invalid {
+    paths::DEFAULT_TEMPLATE
+  } else {
+    &settings.template
+  });
+  downloads::set_concurrency(settings.concurrency);
+  Cache::shared().set_max_size(settings.cache_size * 1024 * 1024);
+}
@@ -0,0 +1,429 @@
+//! Mirrors: folders that are kept in sync with a selection of the instance
+//! (artists, albums, playlists, favorites or libraries).
+//!
+//! Each mirror remembers where it saved every track, by ID, so that a track
+//! that was renamed on the instance is moved instead of downloaded again, and
+//! so that tracks that left the selection can be found and deleted.
+
+use crate::{
+  api::{self, RequestContext},
+  db,
+  downloads::{self, DlStatus, Download, Event, DOWNLOADS},
+  paths,
+};
+use serde_derive::*;
+use std::{
+  collections::{HashMap, HashSet},
+  fmt, fs,
+  path::{Path, PathBuf},
+  str::FromStr,
+  sync::{Mutex, Once},
+};
+
+static COPY_WHEN_DOWNLOADED: Once = Once::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Source {
+  Artist(i32),
+  Album(i32),
+  Playlist(i32),
+  Favorites,
+  /// By UUID
+  Library(String),
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Source::Artist(id) => write!(f, "artist:{}", id),
+      Source::Album(id) => write!(f, "album:{}", id),
+      Source::Playlist(id) => write!(f, "playlist:{}", id),
+      Source::Favorites => write!(f, "favorites"),
+      Source::Library(uuid) => write!(f, "library:{}", uuid),
+    }
+  }
+}
+
+/// Parses what `Display` gives, like `album:42` or `favorites`.
+impl FromStr for Source {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Source, String> {
+    let mut parts = s.splitn(2, ':');
+    let kind = parts.next().unwrap_or("");
+    let arg = parts.next().unwrap_or("");
+    let id = || {
+      arg
+        .parse::<i32>()
+        .map_err(|_| format!("`{}` needs an ID, like {}:42", kind, kind))
+    };
+    match kind {
+      "artist" => Ok(Source::Artist(id()?)),
+      "album" => Ok(Source::Album(id()?)),
+      "playlist" => Ok(Source::Playlist(id()?)),
+      "favorites" => Ok(Source::Favorites),
+      "library" if !arg.is_empty() => Ok(Source::Library(arg.to_string())),
+      _ => Err(format!(
+        "Unknown selection `{}`. Use artist:<id>, album:<id>, playlist:<id>, favorites or library:<uuid>.",
+        s
+      )),
+    }
+  }
+}
+
+impl Source {
+  /// The tracks that are currently part of this selection.
+  ///
+  /// This is blocking.
+  pub fn tracks(&self, client: &RequestContext) -> Result<Vec<api::Track>, api::Error> {
+    let query = [("page_size", "100")];
+    Ok(match self {
+      Source::Artist(id) => downloads::of_artist(client, *id, None)
+        .into_iter()
+        .map(|dl| dl.track)
+        .collect(),
+      Source::Album(id) => downloads::of_album(client, &client.album(*id)?)
+        .into_iter()
+        .map(|dl| dl.track)
+        .collect(),
+      Source::Playlist(id) => client
+        .playlist_tracks(*id)?
+        .into_iter()
+        .map(|t| t.track)
+        .collect(),
+      Source::Favorites => client
+        .all_pages::<api::Favorite>(client.api_get("/favorites/tracks/").query(&query))?
+        .into_iter()
+        .map(|f| f.track)
+        .collect(),
+      Source::Library(uuid) => client.all_pages(
+        client
+          .api_get("/tracks/")
+          .query(&[("library", uuid.as_str()), ("page_size", "100")]),
+      )?,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mirror {
+  pub dir: PathBuf,
+  pub sources: Vec<Source>,
+  /// Where each track was saved, by ID, without its real extension (see
+  /// `paths::existing`)
+  #[serde(default)]
+  files: HashMap<i32, PathBuf>,
+}
+
+impl Mirror {
+  pub fn new(dir: PathBuf, sources: Vec<Source>) -> Mirror {
+    Mirror {
+      dir,
+      sources,
+      files: HashMap::new(),
+    }
+  }
+
+  /// Where a track goes in this mirror, with the current template.
+  fn path(&self, track: &api::Track) -> PathBuf {
+    self.dir.join(paths::render(&paths::template(), track))
+  }
+}
+
+/// What a sync did, or would do.
+#[derive(Debug, Default)]
+pub struct Report {
+  /// New tracks, that are queued for download. The ones that were already
+  /// being downloaded somewhere else are copied to the mirror once they are.
+  pub new: Vec<Download>,
+  /// Copied from another folder where the track was already downloaded
+  pub copied: Vec<PathBuf>,
+  /// From, to
+  pub moved: Vec<(PathBuf, PathBuf)>,
+  /// Files of tracks that are not part of the selection anymore. They are only
+  /// deleted if asked to.
+  pub removed: Vec<PathBuf>,
+  pub up_to_date: usize,
+}
+
+lazy_static::lazy_static! {
+  /// Only guards the file: the app and the CLI both change it, so it is read
+  /// again every time.
+  static ref MIRRORS: Mutex<()> = Mutex::new(());
+
+  /// Where to copy tracks once they are downloaded, by ID, see
+  /// `copy_when_downloaded`
+  static ref COPIES: Mutex<HashMap<i32, Vec<PathBuf>>> = Mutex::new(HashMap::new());
+}
+
+fn mirrors_file() -> PathBuf {
+  dirs::config_dir()
+    .unwrap()
+    .join("mobydick")
+    .join("mirrors.json")
+}
+
+fn read() -> Vec<Mirror> {
+  fs::read(mirrors_file())
+    .ok()
+    .and_then(|f| serde_json::from_slice(&f).ok())
+    .unwrap_or_default()
+}
+
+fn save(mirrors: &[Mirror]) {
+  fs::create_dir_all(mirrors_file().parent().unwrap()).ok();
+  if let Ok(json) = serde_json::to_string(mirrors) {
+    fs::write(mirrors_file(), json).ok();
+  }
+}
+
+/// Changes the mirrors as they are on disk, and saves them.
+fn update<T, F: FnOnce(&mut Vec<Mirror>) -> T>(f: F) -> T {
+  let _lock = MIRRORS.lock().unwrap();
+  let mut mirrors = read();
+  let res = f(&mut mirrors);
+  save(&mirrors);
+  res
+}
+
+pub fn list() -> Vec<Mirror> {
+  let _lock = MIRRORS.lock().unwrap();
+  read()
+}
+
+/// Adds a mirror, or adds sources to the mirror of this folder.
+pub fn add(dir: PathBuf, sources: Vec<Source>) {
+  update(|mirrors| match mirrors.iter_mut().find(|m| m.dir == dir) {
+    Some(mirror) => {
+      for source in sources {
+        if !mirror.sources.contains(&source) {
+          mirror.sources.push(source);
+        }
+      }
+    }
+    None => mirrors.push(Mirror::new(dir, sources)),
+  })
+}
+
+/// Stops mirroring a folder. Its files are kept.
+pub fn remove(dir: &Path) -> bool {
+  update(|mirrors| {
+    let count = mirrors.len();
+    mirrors.retain(|m| m.dir != dir);
+    mirrors.len() != count
+  })
+}
+
+/// Brings a mirror up to date: new tracks are downloaded, renamed ones are
+/// moved, and if `delete` is set, the files of tracks that left the selection
+/// are deleted. With `dry_run`, nothing is changed and the report tells what
+/// would be done.
+///
+/// Downloads are only queued: use `downloads::wait` to wait for them. This is
+/// blocking.
+pub fn run(
+  client: &RequestContext,
+  dir: &Path,
+  delete: bool,
+  dry_run: bool,
+) -> Result<Report, String> {
+  let mut mirror = list()
+    .into_iter()
+    .find(|m| m.dir == dir)
+    .ok_or_else(|| format!("{} is not a mirror.", dir.display()))?;
+
+  let mut tracks = vec![];
+  for source in &mirror.sources {
+    tracks.extend(
+      source
+        .tracks(client)
+        .map_err(|e| format!("Couldn't list the tracks of {}. {}", source, e))?,
+    );
+  }
+
+  let mut report = Report::default();
+  let res = sync_files(&mut mirror, tracks, delete, dry_run, &mut report);
+  if !dry_run {
+    // Even if it stopped on an error, what was done until then is remembered
+    downloads::save();
+    downloads::enqueue(report.new.clone());
+    update(|mirrors| {
+      if let Some(m) = mirrors.iter_mut().find(|m| m.dir == mirror.dir) {
+        m.files = mirror.files;
+      }
+    });
+  }
+  res.map(|()| report)
+}
+
+/// Moves, copies and deletes the files of a mirror, and tells which tracks
+/// are new. `mirror.files` follows every change as it is made.
+fn sync_files(
+  mirror: &mut Mirror,
+  tracks: Vec<api::Track>,
+  delete: bool,
+  dry_run: bool,
+  report: &mut Report,
+) -> Result<(), String> {
+  let mut selected = HashSet::new();
+  for track in tracks {
+    let id = track.id;
+    if !selected.insert(id) {
+      continue;
+    }
+    let planned = mirror.path(&track);
+    let current = mirror
+      .files
+      .get(&id)
+      .and_then(|p| paths::existing(p))
+      .or_else(|| paths::existing(&planned));
+
+    match current {
+      Some(ref file) if file.with_extension("") == planned.with_extension("") => {
+        report.up_to_date += 1;
+      }
+      // Renamed on the instance, or the template changed
+      Some(file) => {
+        let to = planned.with_extension(file.extension().unwrap_or_default());
+        if !dry_run {
+          fs::create_dir_all(to.parent().unwrap()).map_err(|e| e.to_string())?;
+          fs::rename(&file, &to).map_err(|e| e.to_string())?;
+          remove_empty_dirs(&file, &mirror.dir);
+          db::moved(&file, &to);
+          if let Some(dl) = DOWNLOADS.lock().unwrap().get_mut(&id) {
+            if dl.output == file {
+              dl.output = to.clone();
+            }
+          }
+        }
+        report.moved.push((file, to));
+      }
+      None => match downloaded_elsewhere(id) {
+        Some(other) => {
+          let to = if dry_run {
+            planned.with_extension(other.extension().unwrap_or_default())
+          } else {
+            copy(&other, &planned, track)?
+          };
+          report.copied.push(to);
+        }
+        None => {
+          if !dry_run && downloading_elsewhere(id, &planned) {
+            copy_when_downloaded(id, planned.clone());
+          }
+          report.new.push(Download::to(planned.clone(), track));
+        }
+      },
+    }
+    mirror.files.insert(id, planned);
+  }
+
+  for (id, planned) in mirror.files.clone() {
+    if selected.contains(&id) {
+      continue;
+    }
+    match paths::existing(&planned) {
+      Some(file) => {
+        if delete && !dry_run {
+          fs::remove_file(&file).map_err(|e| e.to_string())?;
+          remove_empty_dirs(&file, &mirror.dir);
+          db::forget(&file);
+          mirror.files.remove(&id);
+        }
+        report.removed.push(file);
+      }
+      None => {
+        mirror.files.remove(&id);
+      }
+    }
+  }
+  Ok(())
+}
+
+/// A file this track was downloaded to outside of this mirror, if any.
+fn downloaded_elsewhere(track_id: i32) -> Option<PathBuf> {
+  db::file_of(track_id).or_else(|| {
+    DOWNLOADS
+      .lock()
+      .unwrap()
+      .get(&track_id)
+      .filter(|dl| dl.status == DlStatus::Done && dl.output.is_file())
+      .map(|dl| dl.output.clone())
+  })
+}
+
+/// Whether this track is being downloaded somewhere else than `planned`.
+/// Downloads are by track, so it can't be downloaded for the mirror too.
+fn downloading_elsewhere(track_id: i32, planned: &Path) -> bool {
+  DOWNLOADS
+    .lock()
+    .unwrap()
+    .get(&track_id)
+    .filter(|dl| dl.pending())
+    .map(|dl| dl.output.with_extension("") != planned.with_extension(""))
+    .unwrap_or(false)
+}
+
+/// Copies a downloaded track where `planned` says, with the extension of its
+/// file, and records the copy.
+fn copy(from: &Path, planned: &Path, track: api::Track) -> Result<PathBuf, String> {
+  let to = planned.with_extension(from.extension().unwrap_or_default());
+  fs::create_dir_all(to.parent().unwrap()).map_err(|e| e.to_string())?;
+  fs::copy(from, &to).map_err(|e| e.to_string())?;
+  let mut copy = Download::to(to.clone(), track);
+  copy.status = DlStatus::Done;
+  db::record(&copy);
+  Ok(to)
+}
+
+/// Copies a track that is being downloaded where `planned` says once its
+/// download ends. If it fails, or if the copy does, the next sync copies it.
+fn copy_when_downloaded(track_id: i32, planned: PathBuf) {
+  COPY_WHEN_DOWNLOADED.call_once(|| {
+    downloads::on_event(|event| match event {
+      Event::Finished(id) => copy_downloaded(*id),
+      Event::Failed(id, _) => {
+        COPIES.lock().unwrap().remove(id);
+      }
+      _ => {}
+    });
+  });
+  COPIES.lock().unwrap().entry(track_id).or_default().push(planned);
+  // It may have ended in the meantime
+  let pending = DOWNLOADS
+    .lock()
+    .unwrap()
+    .get(&track_id)
+    .map(Download::pending)
+    .unwrap_or(false);
+  if !pending {
+    copy_downloaded(track_id);
+  }
+}
+
+fn copy_downloaded(track_id: i32) {
+  let copies = COPIES.lock().unwrap().remove(&track_id).unwrap_or_default();
+  let done = DOWNLOADS
+    .lock()
+    .unwrap()
+    .get(&track_id)
+    .filter(|dl| dl.status == DlStatus::Done)
+    .cloned();
+  if let Some(done) = done {
+    for planned in copies {
+      copy(&done.output, &planned, done.track.clone()).ok();
+    }
+  }
+}
+
+/// Deletes the folders that were left empty by moving or deleting `file`, up
+/// to the root of the mirror.
+fn remove_empty_dirs(file: &Path, root: &Path) {
+  let mut dir = file.parent();
+  while let Some(d) = dir {
+    if d == root || !d.starts_with(root) || fs::remove_dir(d).is_err() {
+      break;
+    }
+    dir = d.parent();
+  }
+}
@@ -0,0 +1,51 @@
+//! Reading the metadata of audio files on disk.
+
+use lofty::{Accessor, AudioFile, ItemKey, TaggedFileExt};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct FileTags {
+  pub title: Option<String>,
+  pub artist: Option<String>,
+  pub album_artist: Option<String>,
+  pub album: Option<String>,
+  /// MusicBrainz ID of the recording
+  pub musicbrainz_id: Option<String>,
+  /// In seconds
+  pub duration: u64,
+  /// In kbit/s, if it can be known
+  pub bitrate: Option<u32>,
+  /// The extension of the file, in lowercase
+  pub format: String,
+}
+
+/// Reads the tags and the audio properties of a file. Returns `None` if it is
+/// not an audio file we can read.
+pub fn read(path: &Path) -> Option<FileTags> {
+  let file = lofty::read_from_path(path).ok()?;
+  let properties = file.properties();
+  let mut tags = FileTags {
+    duration: properties.duration().as_secs(),
+    bitrate: properties.audio_bitrate(),
+    format: format(path),
+    ..FileTags::default()
+  };
+
+  if let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) {
+    tags.title = tag.title().map(|t| t.to_string());
+    tags.artist = tag.artist().map(|a| a.to_string());
+    tags.album = tag.album().map(|a| a.to_string());
+    tags.album_artist = tag.get_string(&ItemKey::AlbumArtist).map(String::from);
+    tags.musicbrainz_id = tag
+      .get_string(&ItemKey::MusicBrainzRecordingId)
+      .map(String::from);
+  }
+  Some(tags)
+}
+
+pub fn format(path: &Path) -> String {
+  path
+    .extension()
+    .map(|e| e.to_string_lossy().to_lowercase())
+    .unwrap_or_default()
+}
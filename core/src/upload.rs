@@ -0,0 +1,225 @@
+//! Sending local files to a library of the instance.
+//!
+//! Once a file is uploaded, the instance imports it in the background, so we
+//! poll it until the import is finished.
+
+use crate::{api, offline};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+  },
+  thread,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How often we check if an upload was imported
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const AUDIO_EXTENSIONS: [&str; 9] = ["mp3", "ogg", "oga", "opus", "flac", "m4a", "aac", "wav", "aiff"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UploadStatus {
+  Waiting,
+  /// With the fraction of the file that was sent
+  Sending(f64),
+  /// Sent, but not imported yet
+  Pending,
+  Finished,
+  Skipped,
+  Errored(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+  pub id: usize,
+  pub file: PathBuf,
+  library: String,
+  pub status: UploadStatus,
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+type Listener = Box<dyn Fn(Option<Upload>) + Send + Sync>;
+
+lazy_static::lazy_static! {
+  static ref UPLOADS: Mutex<Vec<Upload>> = Mutex::new(Vec::new());
+
+  static ref UP_JOBS: workerpool::Pool<FileUpload> = workerpool::Pool::new(2);
+
+  static ref LISTENERS: Mutex<Vec<Listener>> = Mutex::new(Vec::new());
+}
+
+pub fn list() -> Vec<Upload> {
+  UPLOADS.lock().unwrap().clone()
+}
+
+/// Calls `f` with an upload every time its status changes, or with `None` when
+/// new files are added to the list. It is called from a worker thread.
+pub fn on_change<F: Fn(Option<Upload>) + Send + Sync + 'static>(f: F) {
+  LISTENERS.lock().unwrap().push(Box::new(f));
+}
+
+fn changed(upload: Option<Upload>) {
+  for listener in LISTENERS.lock().unwrap().iter() {
+    listener(upload.clone());
+  }
+}
+
+/// Starts uploading files to a library.
+///
+/// They are sent with the current session, even if the user logs out before
+/// they are imported.
+pub fn enqueue(files: Vec<PathBuf>, library: &str) {
+  let client = api::API.lock().unwrap().clone();
+  for file in files {
+    let mut upload = Upload {
+      id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+      file,
+      library: library.to_string(),
+      status: UploadStatus::Waiting,
+    };
+    match client {
+      Some(ref client) => UP_JOBS.execute((upload.clone(), client.clone())),
+      None => upload.status = UploadStatus::Errored("You are not logged in".to_string()),
+    }
+    UPLOADS.lock().unwrap().push(upload);
+  }
+  changed(None);
+}
+
+/// All the audio files in a folder and its sub-folders.
+pub fn audio_files(dir: &Path) -> Vec<PathBuf> {
+  let mut files = vec![];
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(_) => return files,
+  };
+  for entry in entries.filter_map(|e| e.ok()) {
+    let path = entry.path();
+    if path.is_dir() {
+      files.extend(audio_files(&path));
+    } else if is_audio(&path) {
+      files.push(path);
+    }
+  }
+  files.sort();
+  files
+}
+
+pub fn is_audio(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_ref()))
+    .unwrap_or(false)
+}
+
+fn set_status(id: usize, status: UploadStatus) {
+  let upload = {
+    let mut uploads = UPLOADS.lock().unwrap();
+    match uploads.iter_mut().find(|u| u.id == id) {
+      Some(upload) => {
+        upload.status = status;
+        upload.clone()
+      }
+      None => return,
+    }
+  };
+  changed(Some(upload));
+}
+
+#[derive(Default)]
+struct FileUpload;
+
+impl workerpool::Worker for FileUpload {
+  type Input = (Upload, api::RequestContext);
+  type Output = ();
+
+  fn execute(&mut self, (upload, client): Self::Input) -> Self::Output {
+    let id = upload.id;
+    let size = fs::metadata(&upload.file).map(|m| m.len()).unwrap_or(0).max(1);
+    let last_percent = AtomicUsize::new(0);
+    let part = api::file_part(&upload.file, move |sent| {
+      // Don't wake the UI up for every chunk
+      let percent = (sent * 100 / size) as usize;
+      if last_percent.swap(percent, Ordering::SeqCst) != percent {
+        set_status(id, UploadStatus::Sending(sent as f64 / size as f64));
+      }
+    });
+    let part = match part {
+      Ok(part) => part,
+      Err(e) => {
+        set_status(id, UploadStatus::Errored(e.to_string()));
+        return;
+      }
+    };
+
+    let reference = format!(
+      "mobydick-{}",
+      SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+    );
+    let file_name = upload
+      .file
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let form = reqwest::r#async::multipart::Form::new()
+      .text("library", upload.library.clone())
+      .text("import_reference", reference)
+      .text("source", format!("upload://{}", file_name))
+      .part("audio_file", part);
+
+    set_status(id, UploadStatus::Sending(0.0));
+    let req = client.api_post("/uploads/").multipart(form);
+    let res = api::upload(req)
+      .wait()
+      .unwrap_or(Err(api::Error::Cancelled))
+      .and_then(|res| {
+        if res.status().is_success() {
+          res.json::<api::UploadInfo>().map(Ok)
+        } else {
+          Ok(Err(String::from_utf8_lossy(res.body()).to_string()))
+        }
+      });
+
+    match res {
+      Ok(Ok(info)) => {
+        set_status(id, UploadStatus::Pending);
+        thread::spawn(move || poll(&client, id, info));
+      }
+      Ok(Err(details)) => set_status(id, UploadStatus::Errored(details)),
+      Err(e) => set_status(id, UploadStatus::Errored(e.to_string())),
+    }
+  }
+}
+
+/// Waits for the import of an upload to be done.
+fn poll(client: &api::RequestContext, id: usize, mut info: api::UploadInfo) {
+  loop {
+    match info.import_status.as_ref() {
+      "finished" => return set_status(id, UploadStatus::Finished),
+      "skipped" => return set_status(id, UploadStatus::Skipped),
+      "errored" => {
+        return set_status(
+          id,
+          UploadStatus::Errored(info.error().unwrap_or_default()),
+        )
+      }
+      _ => {}
+    }
+
+    thread::sleep(POLL_INTERVAL);
+    let req = client.api_get(format!("/uploads/{}/", info.uuid)).uncached();
+    match api::send(req).and_then(|r| r.json()) {
+      Ok(new_info) => info = new_info,
+      Err(ref e) if offline::is_network_error(e) => continue,
+      Err(e) => return set_status(id, UploadStatus::Errored(e.to_string())),
+    }
+  }
+}
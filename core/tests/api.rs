@@ -0,0 +1,232 @@
+//! Talking to an instance: building requests, reading responses, errors,
+//! slow answers and cancellation.
+
+mod common;
+
+use mobydick_core::{
+  api::{self, ApiVersion, RequestContext},
+  offline, session,
+};
+use std::time::{Duration, Instant};
+
+#[test]
+fn requests_are_authenticated() {
+  let server = common::server();
+  let client = common::client(server);
+  let res = api::send(client.api_get("/users/users/me/").uncached()).unwrap();
+  assert_eq!(res.status(), 200);
+  let me: api::UserInfo = res.json().unwrap();
+  assert_eq!(me.username, common::USERNAME);
+
+  let req = server.requests_to("/api/v1/users/users/me/").pop().unwrap();
+  assert_eq!(req.header("authorization"), Some("JWT t0k3n"));
+}
+
+#[test]
+fn error_statuses_are_not_errors() {
+  let server = common::server();
+  let client = common::client(server);
+  let res = api::send(client.api_get("/error/").uncached()).unwrap();
+  assert_eq!(res.status(), 500);
+
+  let res = api::send(client.api_get("/nothing-here/").uncached()).unwrap();
+  assert_eq!(res.status(), 404);
+
+  let mut anonymous = RequestContext::new(server.url.clone()).unwrap();
+  anonymous.negotiate(ApiVersion::V1);
+  let res = api::send(anonymous.api_get("/users/users/me/").uncached()).unwrap();
+  assert_eq!(res.status(), 401);
+}
+
+#[test]
+fn unexpected_responses_are_json_errors() {
+  let server = common::server();
+  let client = common::client(server);
+  let res = api::send(client.api_get("/not-json/").uncached()).unwrap();
+  match res.json::<api::SearchResult>() {
+    Err(api::Error::Json(_)) => {}
+    other => panic!("Expected a JSON error, got {:?}", other.map(|_| ())),
+  }
+}
+
+#[test]
+fn unreachable_instances_are_network_errors() {
+  let server = common::server();
+  // Nothing listens on the discard port
+  let client = RequestContext::new("http://127.0.0.1:9".to_string()).unwrap();
+  let err = api::send(client.api_get("/users/users/me/").uncached()).unwrap_err();
+  assert!(offline::is_network_error(&err), "{:?}", err);
+
+  let client = common::client(server);
+  let err = api::send(client.api_get("/not-json/").uncached())
+    .unwrap()
+    .json::<api::UserInfo>()
+    .err()
+    .expect("HTML was read as JSON");
+  assert!(!offline::is_network_error(&err));
+}
+
+#[test]
+fn api_versions_use_their_prefix() {
+  let server = common::server();
+  let mut client = common::client(server);
+  client.negotiate(ApiVersion::V2);
+  let res = api::send(client.api_get("/users/users/me/").uncached()).unwrap();
+  // The mock only knows about v1
+  assert_eq!(res.status(), 404);
+  assert!(!server.requests_to("/api/v2/users/users/me/").is_empty());
+}
+
+#[test]
+fn absolute_urls_are_kept() {
+  let server = common::server();
+  let client = common::client(server);
+  let url = format!("{}/api/v1/users/users/me/", server.url);
+  let res = api::send(client.get(url).uncached()).unwrap();
+  assert_eq!(res.status(), 200);
+}
+
+#[test]
+fn search() {
+  let server = common::server();
+  let client = common::client(server);
+  let res = api::send(client.api_get("/search").query(&api::SearchQuery {
+    query: "intro".to_string(),
+  }))
+  .unwrap();
+  let results: api::SearchResult = res.json().unwrap();
+  assert_eq!(results.artists[0].name, "The Mockers");
+  assert_eq!(results.albums[0].title, "First Album");
+  assert_eq!(results.tracks.len(), 1);
+  assert_eq!(results.tracks[0].listen_url, "/api/v1/listen/ogg/");
+}
+
+#[test]
+fn albums_and_their_tracks() {
+  let server = common::server();
+  let client = common::client(server);
+  let album = client.album(1).unwrap();
+  assert_eq!(album.title, "First Album");
+  // Funkwhale 1.x doesn't embed them, and they span several pages
+  let tracks = album.tracks.unwrap();
+  assert_eq!(tracks.len(), 3);
+  assert_eq!(tracks[0].duration, Some(180));
+}
+
+#[test]
+fn all_albums_of_an_artist() {
+  let server = common::server();
+  let client = common::client(server);
+  let albums = client.artist_albums(1).unwrap();
+  let titles: Vec<_> = albums.iter().map(|a| a.title.as_str()).collect();
+  assert_eq!(titles, vec!["First Album", "Second Album"]);
+}
+
+#[test]
+fn all_pages_follows_next() {
+  let server = common::server();
+  let client = common::client(server);
+  let tracks: Vec<api::Track> = client
+    .all_pages(client.api_get("/tracks/").query(&[("album", "1")]))
+    .unwrap();
+  let titles: Vec<_> = tracks.iter().map(|t| t.title.as_str()).collect();
+  assert_eq!(titles, vec!["Intro", "Song", "Outro"]);
+}
+
+#[test]
+fn first_items_stops_at_the_limit() {
+  let server = common::server();
+  let client = common::client(server);
+  let tracks: Vec<api::Track> = client
+    .first_items(client.api_get("/tracks/").query(&[("album", "1")]), 1)
+    .unwrap();
+  assert_eq!(tracks.len(), 1);
+  assert_eq!(tracks[0].title, "Intro");
+}
+
+#[test]
+fn ranges() {
+  let server = common::server();
+  let client = common::client(server);
+  let res = api::send(
+    client
+      .get("/api/v1/listen/ranged/")
+      .uncached()
+      .header(reqwest::header::RANGE, "bytes=1000-"),
+  )
+  .unwrap();
+  assert_eq!(res.status(), 206);
+  assert_eq!(res.body(), &common::track_content()[1000..]);
+}
+
+#[test]
+fn slow_responses_are_waited_for() {
+  let server = common::server();
+  let client = common::client(server);
+  let start = Instant::now();
+  let res = api::send(client.api_get("/slow/").uncached()).unwrap();
+  assert_eq!(res.status(), 200);
+  assert!(start.elapsed() >= Duration::from_secs(2));
+}
+
+#[test]
+fn calls_can_be_cancelled() {
+  let server = common::server();
+  let client = common::client(server);
+  let start = Instant::now();
+  let call = api::execute(client.api_get("/slow/").uncached());
+  let cancel = call.cancel_handle();
+  cancel.cancel();
+  assert!(cancel.is_cancelled());
+  assert!(call.wait().is_none());
+  assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn background_calls_can_be_cancelled() {
+  let call = api::background(|| {
+    std::thread::sleep(Duration::from_millis(200));
+    42
+  });
+  call.cancel_handle().cancel();
+  assert!(call.wait().is_none());
+
+  let (tx, rx) = std::sync::mpsc::channel();
+  let call = api::background(|| {
+    std::thread::sleep(Duration::from_millis(200));
+    42
+  });
+  let cancel = call.cancel_handle();
+  call.then(move |res| tx.send(res).unwrap());
+  cancel.cancel();
+  assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
+
+#[test]
+fn login() {
+  let server = common::server();
+  let err = session::login(&server.url, common::USERNAME.to_string(), "wrong".to_string());
+  assert!(err.is_err());
+
+  session::login(
+    &server.url,
+    common::USERNAME.to_string(),
+    common::PASSWORD.to_string(),
+  )
+  .unwrap();
+  {
+    let api = api::API.lock().unwrap();
+    let client = api.as_ref().unwrap();
+    assert_eq!(client.username(), common::USERNAME);
+    assert_eq!(client.version(), ApiVersion::V1);
+    assert_eq!(client.to_json()["token"], common::TOKEN);
+  }
+
+  // The session is saved for the next run
+  *api::API.lock().unwrap() = None;
+  assert!(session::load().unwrap());
+  assert_eq!(
+    api::API.lock().unwrap().as_ref().unwrap().instance(),
+    server.url
+  );
+}
@@ -0,0 +1,95 @@
+//! The HTTP cache: what is kept, for how long, and for whom.
+
+mod common;
+
+use mobydick_core::api::{self, cache::Cache};
+use reqwest::header::{HeaderMap, HeaderValue, CACHE_CONTROL, ETAG};
+use std::{thread, time::Duration};
+
+fn cache(name: &str, max_size: u64) -> Cache {
+  Cache::open(common::tmp().join("caches").join(name), max_size)
+}
+
+fn headers(pairs: &[(reqwest::header::HeaderName, &'static str)]) -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  for (name, value) in pairs {
+    headers.insert(name.clone(), HeaderValue::from_static(value));
+  }
+  headers
+}
+
+#[test]
+fn freshness() {
+  let cache = cache("freshness", 1024);
+  cache.store("max-age", &headers(&[(CACHE_CONTROL, "max-age=60")]), b"1");
+  assert!(cache.lookup("max-age").unwrap().fresh);
+
+  // Kept, but revalidated before being used
+  cache.store("no-cache", &headers(&[(CACHE_CONTROL, "no-cache"), (ETAG, "\"1\"")]), b"1");
+  assert!(!cache.lookup("no-cache").unwrap().fresh);
+  cache.store("etag", &headers(&[(ETAG, "\"1\"")]), b"1");
+  assert!(!cache.lookup("etag").unwrap().fresh);
+
+  // Not kept at all
+  cache.store("no-store", &headers(&[(CACHE_CONTROL, "no-store, max-age=60")]), b"1");
+  assert!(cache.lookup("no-store").is_none());
+  cache.store("expired", &headers(&[(CACHE_CONTROL, "max-age=0")]), b"1");
+  assert!(cache.lookup("expired").is_none());
+  cache.store("nothing", &HeaderMap::new(), b"1");
+  assert!(cache.lookup("nothing").is_none());
+}
+
+#[test]
+fn least_recently_used_entries_are_evicted() {
+  let cache = cache("evict", 10);
+  let fresh = headers(&[(CACHE_CONTROL, "max-age=60")]);
+  cache.store("old", &fresh, b"123456");
+  cache.store("unused", &fresh, b"1234");
+  // Times are in seconds
+  thread::sleep(Duration::from_millis(1100));
+  assert!(cache.lookup("old").is_some());
+
+  cache.store("new", &fresh, b"1234");
+  assert!(cache.lookup("unused").is_none());
+  assert_eq!(cache.lookup("old").unwrap().body, b"123456");
+  assert_eq!(cache.lookup("new").unwrap().body, b"1234");
+
+  thread::sleep(Duration::from_millis(1100));
+  assert!(cache.lookup("new").is_some());
+  cache.set_max_size(5);
+  assert!(cache.lookup("old").is_none());
+  assert!(cache.lookup("new").is_some());
+}
+
+#[test]
+fn stale_responses_are_revalidated() {
+  let server = common::server();
+  let client = common::client(server);
+  let first = api::send(client.api_get("/cached/")).unwrap();
+  let second = api::send(client.api_get("/cached/")).unwrap();
+
+  let requests = server.requests_to("/api/v1/cached/");
+  assert_eq!(requests.len(), 2);
+  assert_eq!(requests[1].header("if-none-match"), Some("\"v1\""));
+  // The server answered "304 Not Modified": it is the same response
+  assert_eq!(second.status(), reqwest::StatusCode::OK);
+  assert_eq!(second.body(), first.body());
+}
+
+#[test]
+fn accounts_have_their_own_entries() {
+  let server = common::server();
+  let alice = common::client(server);
+  let mut bob = common::client(server);
+  bob.set_username("bob".to_string());
+
+  let for_alice: serde_json::Value = api::send(alice.api_get("/fresh/")).unwrap().json().unwrap();
+  let for_bob: serde_json::Value = api::send(bob.api_get("/fresh/")).unwrap().json().unwrap();
+  assert_ne!(for_alice, for_bob);
+  assert_eq!(server.requests_to("/api/v1/fresh/").len(), 2);
+
+  // Still fresh for Alice
+  let again: serde_json::Value = api::send(alice.api_get("/fresh/")).unwrap().json().unwrap();
+  assert_eq!(again, for_alice);
+  assert_eq!(server.requests_to("/api/v1/fresh/").len(), 2);
+}
@@ -0,0 +1,492 @@
+//! An in-process mock of a Funkwhale instance, and what every test needs to
+//! run without touching the real configuration.
+//!
+//! The server speaks just enough HTTP/1.1 for reqwest: one request per
+//! connection, answered with `Connection: close`.
+
+#![allow(dead_code)]
+
+use mobydick_core::api::{self, ApiVersion, RequestContext};
+use std::{
+  collections::HashMap,
+  env, fs,
+  io::{BufRead, BufReader, Read, Write},
+  net::{TcpListener, TcpStream},
+  path::PathBuf,
+  sync::{Mutex, Once},
+  thread,
+  time::Duration,
+};
+
+pub const USERNAME: &str = "alice";
+pub const PASSWORD: &str = "secret";
+pub const TOKEN: &str = "t0k3n";
+
+/// Size of the files served by the `listen` endpoints
+pub const TRACK_SIZE: usize = 64 * 1024;
+
+static SETUP: Once = Once::new();
+
+lazy_static::lazy_static! {
+  static ref SERVER: &'static Server = {
+    setup();
+    Server::start()
+  };
+}
+
+/// The mock server shared by the tests of a file.
+pub fn server() -> &'static Server {
+  &SERVER
+}
+
+/// A folder that only lives as long as this test run.
+pub fn tmp() -> PathBuf {
+  env::temp_dir().join(format!("mobydick-tests-{}", std::process::id()))
+}
+
+/// Points the configuration, the cache and the music folder to a temporary
+/// folder. It must be called before anything else in every test (`server`
+/// does it).
+pub fn setup() {
+  SETUP.call_once(|| {
+    let root = tmp();
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir_all(root.join("config")).unwrap();
+    fs::create_dir_all(root.join("cache")).unwrap();
+    env::set_var("HOME", &root);
+    env::set_var("XDG_CONFIG_HOME", root.join("config"));
+    env::set_var("XDG_CACHE_HOME", root.join("cache"));
+    fs::write(
+      root.join("config").join("user-dirs.dirs"),
+      "XDG_MUSIC_DIR=\"$HOME/music\"\n",
+    )
+    .unwrap();
+  });
+}
+
+/// A client for the mock server, logged in.
+pub fn client(server: &Server) -> RequestContext {
+  let mut ctx = RequestContext::new(server.url.clone()).unwrap();
+  ctx.negotiate(ApiVersion::V1);
+  ctx.auth(TOKEN.to_string());
+  ctx.set_username(USERNAME.to_string());
+  ctx
+}
+
+/// Makes the mock server the instance we are logged in to, for the parts of
+/// the core that use `api::API`.
+pub fn log_in(server: &Server) {
+  *api::API.lock().unwrap() = Some(client(server));
+}
+
+/// What the server received
+#[derive(Debug, Clone)]
+pub struct Request {
+  pub method: String,
+  /// With the query string
+  pub path: String,
+  /// Names are in lowercase
+  pub headers: HashMap<String, String>,
+  pub body: Vec<u8>,
+}
+
+impl Request {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.get(name).map(String::as_str)
+  }
+
+  fn query(&self, name: &str) -> Option<String> {
+    let (_, query) = self.path.split_once('?')?;
+    query
+      .split('&')
+      .map(|p| p.splitn(2, '='))
+      .filter_map(|mut p| Some((p.next()?, p.next().unwrap_or(""))))
+      .find(|(k, _)| *k == name)
+      .map(|(_, v)| v.replace('+', " ").replace("%20", " "))
+  }
+
+  fn route(&self) -> &str {
+    self.path.split('?').next().unwrap_or("")
+  }
+
+  fn authenticated(&self) -> bool {
+    self.header("authorization") == Some(&format!("JWT {}", TOKEN))
+  }
+}
+
+pub struct Server {
+  /// Like `http://127.0.0.1:1234`
+  pub url: String,
+  requests: Mutex<Vec<Request>>,
+  /// How many times each path was requested, to make some of them fail first
+  hits: Mutex<HashMap<String, usize>>,
+}
+
+impl Server {
+  /// Starts a server on a free port. It runs until the end of the tests.
+  pub fn start() -> &'static Server {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server: &'static Server = Box::leak(Box::new(Server {
+      url: format!("http://{}", listener.local_addr().unwrap()),
+      requests: Mutex::new(vec![]),
+      hits: Mutex::new(HashMap::new()),
+    }));
+    thread::spawn(move || {
+      for stream in listener.incoming().filter_map(|s| s.ok()) {
+        thread::spawn(move || server.handle(stream));
+      }
+    });
+    server
+  }
+
+  /// The requests made to a path (without the query string) so far.
+  pub fn requests_to(&self, route: &str) -> Vec<Request> {
+    self
+      .requests
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|r| r.route() == route)
+      .cloned()
+      .collect()
+  }
+
+  fn handle(&self, mut stream: TcpStream) {
+    let req = match read_request(&stream) {
+      Some(req) => req,
+      None => return,
+    };
+    self.requests.lock().unwrap().push(req.clone());
+    let hits = {
+      let mut hits = self.hits.lock().unwrap();
+      let count = hits.entry(req.route().to_string()).or_insert(0);
+      *count += 1;
+      *count
+    };
+    self.respond(&req, hits, &mut stream);
+  }
+
+  fn respond(&self, req: &Request, hits: usize, stream: &mut TcpStream) {
+    let route = req.route();
+    match (req.method.as_str(), route) {
+      ("GET", "/.well-known/nodeinfo") => json(
+        stream,
+        200,
+        &format!(
+          r#"{{"links": [{{"rel": "http://nodeinfo.diaspora.software/ns/schema/2.0", "href": "{}/api/v1/instance/nodeinfo/2.0/"}}]}}"#,
+          self.url
+        ),
+      ),
+      ("GET", "/api/v1/instance/nodeinfo/2.0/") => json(stream, 200, NODEINFO),
+      ("POST", "/api/v1/token/") => {
+        let body = String::from_utf8_lossy(&req.body);
+        if body.contains(&format!(r#""password":"{}""#, PASSWORD)) {
+          json(stream, 200, &format!(r#"{{"token": "{}"}}"#, TOKEN))
+        } else {
+          json(
+            stream,
+            400,
+            r#"{"non_field_errors": ["Unable to log in with provided credentials."]}"#,
+          )
+        }
+      }
+      (_, _) if route.starts_with("/api/") && !req.authenticated() => json(
+        stream,
+        401,
+        r#"{"detail": "Authentication credentials were not provided."}"#,
+      ),
+      ("GET", "/api/v1/users/users/me/") => json(
+        stream,
+        200,
+        &format!(r#"{{"username": "{}", "avatar": null}}"#, USERNAME),
+      ),
+      ("GET", "/api/v1/search") => {
+        let query = req.query("query").unwrap_or_default();
+        let tracks = if query.to_lowercase() == "intro" {
+          format!("[{}]", track(1, "Intro", "/api/v1/listen/ogg/"))
+        } else {
+          "[]".to_string()
+        };
+        json(
+          stream,
+          200,
+          &format!(
+            r#"{{"artists": [{{"id": 1, "name": "The Mockers", "albums": null}}], "albums": [{}], "tracks": {}}}"#,
+            ALBUM, tracks
+          ),
+        )
+      }
+      ("GET", "/api/v1/albums/1/") => json(stream, 200, ALBUM),
+      ("GET", "/api/v1/albums/") => match req.query("page").as_deref() {
+        None | Some("1") => json(
+          stream,
+          200,
+          &format!(
+            r#"{{"count": 2, "next": "{}/api/v1/albums/?artist=1&page=2", "results": [{}]}}"#,
+            self.url, ALBUM
+          ),
+        ),
+        Some(_) => json(
+          stream,
+          200,
+          r#"{"count": 2, "next": null, "results": [{"id": 2, "title": "Second Album", "cover": null}]}"#,
+        ),
+      },
+      ("GET", "/api/v1/tracks/") => match req.query("page").as_deref() {
+        // Pagination, with an absolute URL like Funkwhale gives
+        None | Some("1") => json(
+          stream,
+          200,
+          &format!(
+            r#"{{"count": 3, "next": "{}/api/v1/tracks/?album=1&page=2", "results": [{}, {}]}}"#,
+            self.url,
+            track(1, "Intro", "/api/v1/listen/ogg/"),
+            track(2, "Song", "/api/v1/listen/plain/")
+          ),
+        ),
+        Some(_) => json(
+          stream,
+          200,
+          &format!(
+            r#"{{"count": 3, "next": null, "results": [{}]}}"#,
+            track(3, "Outro", "/api/v1/listen/ranged/")
+          ),
+        ),
+      },
+      // Tracks 602 to 604 are plays the instance refuses
+      ("POST", "/api/v1/history/listenings/") => {
+        let body = String::from_utf8_lossy(&req.body);
+        if body.contains(r#""track":602"#) {
+          json(stream, 404, r#"{"detail": "Not found."}"#)
+        } else if body.contains(r#""track":603"#) {
+          json(
+            stream,
+            403,
+            r#"{"detail": "You do not have permission to perform this action."}"#,
+          )
+        } else if body.contains(r#""track":604"#) {
+          json(stream, 500, r#"{"detail": "Server error"}"#)
+        } else {
+          json(stream, 201, &String::from_utf8_lossy(&req.body))
+        }
+      }
+      ("GET", "/api/v1/playlists/1/tracks/") => json(
+        stream,
+        200,
+        &format!(
+          r#"{{"count": 1, "next": null, "results": [{{"index": 0, "track": {}}}]}}"#,
+          track(501, "Slow", "/api/v1/listen/slow/")
+        ),
+      ),
+      ("GET", _) if route.starts_with("/api/v1/tracks/") => {
+        match route["/api/v1/tracks/".len()..].trim_end_matches('/').parse::<i32>() {
+          Ok(id) if id > 0 => json(
+            stream,
+            200,
+            &track(id, &format!("Track {}", id), "/api/v1/listen/plain/"),
+          ),
+          _ => json(stream, 404, r#"{"detail": "Not found."}"#),
+        }
+      }
+      ("GET", "/api/v1/listen/ogg/") => audio(
+        stream,
+        req,
+        &[("Content-Disposition", r#"attachment; filename="Intro.ogg""#)],
+      ),
+      ("GET", "/api/v1/listen/plain/") => audio(stream, req, &[]),
+      ("GET", "/api/v1/listen/inline/") => {
+        audio(stream, req, &[("Content-Disposition", "inline")])
+      }
+      ("GET", "/api/v1/listen/ranged/") => audio(
+        stream,
+        req,
+        &[
+          ("Accept-Ranges", "bytes"),
+          ("Content-Disposition", r#"attachment; filename="Outro.flac""#),
+        ],
+      ),
+      ("GET", "/api/v1/listen/slow/") => slow_audio(stream),
+      ("GET", "/api/v1/listen/flaky/") if hits == 1 => {
+        // Hang up without answering, like an instance that went down
+      }
+      ("GET", "/api/v1/listen/flaky/") => audio(stream, req, &[]),
+      ("GET", "/api/v1/slow/") => {
+        thread::sleep(Duration::from_secs(2));
+        json(stream, 200, r#"{"slow": true}"#)
+      }
+      // Has to be revalidated every time, and changes if it isn't
+      ("GET", "/api/v1/cached/") => match req.header("if-none-match") {
+        Some("\"v1\"") => reply(stream, 304, "application/json", &[("ETag", "\"v1\"")], b""),
+        _ => reply(
+          stream,
+          200,
+          "application/json",
+          &[("ETag", "\"v1\""), ("Cache-Control", "no-cache")],
+          format!(r#"{{"hits": {}}}"#, hits).as_bytes(),
+        ),
+      },
+      ("GET", "/api/v1/fresh/") => reply(
+        stream,
+        200,
+        "application/json",
+        &[("Cache-Control", "max-age=60")],
+        format!(r#"{{"hits": {}}}"#, hits).as_bytes(),
+      ),
+      ("GET", "/api/v1/not-json/") => reply(stream, 200, "text/html", &[], b"<html></html>"),
+      ("GET", "/api/v1/error/") => json(stream, 500, r#"{"detail": "Server error"}"#),
+      ("POST", "/hooks/album/") => json(stream, 200, "{}"),
+      _ => json(stream, 404, r#"{"detail": "Not found."}"#),
+    }
+  }
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+  let mut reader = BufReader::new(stream);
+  let mut line = String::new();
+  reader.read_line(&mut line).ok()?;
+  let mut parts = line.split_whitespace();
+  let method = parts.next()?.to_string();
+  let path = parts.next()?.to_string();
+
+  let mut headers = HashMap::new();
+  loop {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    let mut header = line.splitn(2, ':');
+    headers.insert(
+      header.next()?.trim().to_lowercase(),
+      header.next().unwrap_or("").trim().to_string(),
+    );
+  }
+
+  let len = headers
+    .get("content-length")
+    .and_then(|l| l.parse().ok())
+    .unwrap_or(0);
+  let mut body = vec![0; len];
+  reader.read_exact(&mut body).ok()?;
+  Some(Request {
+    method,
+    path,
+    headers,
+    body,
+  })
+}
+
+fn reply(stream: &mut TcpStream, status: u16, content_type: &str, headers: &[(&str, &str)], body: &[u8]) {
+  let mut head = format!(
+    "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+    status,
+    reason(status),
+    content_type,
+    body.len()
+  );
+  for (name, value) in headers {
+    head.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  head.push_str("\r\n");
+  stream.write_all(head.as_bytes()).ok();
+  stream.write_all(body).ok();
+}
+
+fn reason(status: u16) -> &'static str {
+  match status {
+    200 => "OK",
+    201 => "Created",
+    206 => "Partial Content",
+    304 => "Not Modified",
+    400 => "Bad Request",
+    401 => "Unauthorized",
+    403 => "Forbidden",
+    404 => "Not Found",
+    416 => "Range Not Satisfiable",
+    _ => "Internal Server Error",
+  }
+}
+
+fn json(stream: &mut TcpStream, status: u16, body: &str) {
+  reply(stream, status, "application/json", &[], body.as_bytes());
+}
+
+/// The content of every track
+pub fn track_content() -> Vec<u8> {
+  (0..TRACK_SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+/// Serves a track, honouring `Range` headers.
+fn audio(stream: &mut TcpStream, req: &Request, headers: &[(&str, &str)]) {
+  let content = track_content();
+  let range = req
+    .header("range")
+    .and_then(|r| r.trim_start_matches("bytes=").split('-').next())
+    .and_then(|start| start.parse::<usize>().ok());
+  match range {
+    Some(start) if start >= content.len() => reply(stream, 416, "audio/mpeg", headers, b""),
+    Some(start) => {
+      let content_range = format!("bytes {}-{}/{}", start, content.len() - 1, content.len());
+      let mut headers = headers.to_vec();
+      headers.push(("Content-Range", &content_range));
+      reply(stream, 206, "audio/mpeg", &headers, &content[start..]);
+    }
+    None => reply(stream, 200, "audio/mpeg", headers, &content),
+  }
+}
+
+/// Sends a track slowly, a small chunk at a time, for about ten seconds.
+fn slow_audio(stream: &mut TcpStream) {
+  let content = track_content();
+  let head = format!(
+    "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    content.len()
+  );
+  if stream.write_all(head.as_bytes()).is_err() {
+    return;
+  }
+  for chunk in content.chunks(content.len() / 100) {
+    if stream.write_all(chunk).and_then(|_| stream.flush()).is_err() {
+      return;
+    }
+    thread::sleep(Duration::from_millis(100));
+  }
+}
+
+const NODEINFO: &str = r#"{
+  "version": "2.0",
+  "software": {"name": "funkwhale", "version": "1.2.10"},
+  "openRegistrations": false,
+  "metadata": {"nodeName": "Mock instance", "library": {"anonymousCanListen": false}}
+}"#;
+
+/// Funkwhale 1.x: the cover is an attachment, tracks are not embedded
+pub const ALBUM: &str = r#"{
+  "id": 1,
+  "title": "First Album",
+  "artist": {"id": 1, "name": "The Mockers"},
+  "cover": {"urls": {"original": "/media/1.jpg", "medium_square_crop": "/media/1-m.jpg"}},
+  "tags": ["rock"]
+}"#;
+
+/// A track in the shape of Funkwhale 1.x, whose file is served by `listen`.
+pub fn track(id: i32, title: &str, listen: &str) -> String {
+  format!(
+    r#"{{
+      "id": {},
+      "title": "{}",
+      "artist": {{"id": 1, "name": "The Mockers"}},
+      "album": {},
+      "uploads": [{{"listen_url": "{}", "duration": 180}}],
+      "mbid": "00000000-0000-0000-0000-{:012}",
+      "tags": []
+    }}"#,
+    id, title, ALBUM, listen, id
+  )
+}
+
+/// A track that can be given to the download engine.
+pub fn parse_track(id: i32, title: &str, listen: &str) -> api::Track {
+  serde_json::from_str(&track(id, title, listen)).unwrap()
+}
@@ -0,0 +1,17 @@
+//! The database of downloaded files, when it can't be used.
+
+mod common;
+
+use mobydick_core::db;
+use std::fs;
+
+#[test]
+fn unusable_database_is_reported() {
+  common::setup();
+  // A folder where the database should be
+  let file = dirs::config_dir().unwrap().join("mobydick").join("library.sqlite");
+  fs::create_dir_all(&file).unwrap();
+
+  let problem = db::problem().unwrap();
+  assert!(problem.contains("database"), "{}", problem);
+}
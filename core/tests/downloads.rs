@@ -0,0 +1,145 @@
+//! The download engine, from the queue to the files on disk.
+
+mod common;
+
+use mobydick_core::{
+  db,
+  downloads::{self, DlStatus, Download, DOWNLOADS},
+};
+use std::{
+  fs,
+  path::PathBuf,
+  sync::atomic::{AtomicUsize, Ordering},
+  thread,
+  time::{Duration, Instant},
+};
+
+/// A planned download of a track served by `listen`, to its own folder.
+fn planned(id: i32, title: &str, listen: &str) -> Download {
+  let server = common::server();
+  common::log_in(server);
+  Download::to(
+    common::tmp().join("music").join(id.to_string()).join(format!("{}.mp3", title)),
+    common::parse_track(id, title, listen),
+  )
+}
+
+/// Downloads a track and waits for the engine to be done with it.
+fn download(id: i32, title: &str, listen: &str) -> Download {
+  downloads::enqueue(vec![planned(id, title, listen)]);
+  downloads::wait();
+  DOWNLOADS.lock().unwrap()[&id].clone()
+}
+
+fn files_in(dir: PathBuf) -> Vec<PathBuf> {
+  fs::read_dir(dir)
+    .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+    .unwrap_or_default()
+}
+
+#[test]
+fn extension_from_content_disposition() {
+  let dl = download(101, "Intro", "/api/v1/listen/ogg/");
+  assert_eq!(dl.status, DlStatus::Done);
+  assert_eq!(dl.output.file_name().unwrap(), "Intro.ogg");
+  assert_eq!(fs::read(&dl.output).unwrap(), common::track_content());
+}
+
+#[test]
+fn mp3_by_default() {
+  let dl = download(102, "Song", "/api/v1/listen/plain/");
+  assert_eq!(dl.status, DlStatus::Done);
+  assert_eq!(dl.output.file_name().unwrap(), "Song.mp3");
+
+  // Without a file name
+  let dl = download(103, "Inline", "/api/v1/listen/inline/");
+  assert_eq!(dl.output.file_name().unwrap(), "Inline.mp3");
+}
+
+#[test]
+fn titles_with_dots_keep_them() {
+  let dl = download(104, "Op. 2", "/api/v1/listen/ranged/");
+  assert_eq!(dl.output.file_name().unwrap(), "Op. 2.flac");
+  assert_eq!(
+    fs::metadata(&dl.output).unwrap().len(),
+    common::TRACK_SIZE as u64
+  );
+}
+
+#[test]
+fn error_statuses_are_not_saved() {
+  let dl = download(105, "Missing", "/api/v1/listen/missing/");
+  assert_eq!(dl.status, DlStatus::Failed);
+  assert_eq!(dl.error, Some("The instance answered 404 Not Found".to_string()));
+  assert!(files_in(common::tmp().join("music").join("105")).is_empty());
+  // There is nothing to cancel
+  assert!(!downloads::cancel(105));
+  assert_eq!(DOWNLOADS.lock().unwrap()[&105].status, DlStatus::Failed);
+}
+
+#[test]
+fn failed_downloads_can_be_enqueued_again() {
+  let dl = download(111, "Retried", "/api/v1/listen/missing/");
+  assert_eq!(dl.status, DlStatus::Failed);
+
+  // Once the instance serves it
+  let dl = download(111, "Retried", "/api/v1/listen/plain/");
+  assert_eq!(dl.status, DlStatus::Done);
+  assert_eq!(dl.error, None);
+  assert_eq!(fs::read(&dl.output).unwrap(), common::track_content());
+}
+
+#[test]
+fn downloads_are_recorded() {
+  let dl = download(106, "Recorded", "/api/v1/listen/plain/");
+  assert_eq!(db::file_of(106), Some(dl.output.clone()));
+  let file = db::files().into_iter().find(|f| f.track.id == 106).unwrap();
+  assert_eq!(file.size, common::TRACK_SIZE as u64);
+  assert_eq!(file.account, common::USERNAME);
+  assert_eq!(file.format, "mp3");
+
+  // Wherever it would go now, it is already there
+  let elsewhere = Download::to(PathBuf::from("/nowhere/Recorded.mp3"), dl.track);
+  assert!(downloads::on_disk(&elsewhere));
+  assert!(!downloads::on_disk(&planned(107, "Never downloaded", "/api/v1/listen/plain/")));
+}
+
+#[test]
+fn listeners_are_told() {
+  lazy_static::lazy_static! {
+    static ref CHANGES: AtomicUsize = AtomicUsize::new(0);
+  }
+  downloads::on_change(|| {
+    CHANGES.fetch_add(1, Ordering::SeqCst);
+  });
+  download(108, "Listened", "/api/v1/listen/plain/");
+  // Queued, started and done
+  assert!(CHANGES.load(Ordering::SeqCst) >= 3);
+}
+
+#[test]
+fn cancellation() {
+  downloads::enqueue(vec![planned(109, "Slow", "/api/v1/listen/slow/")]);
+
+  let start = Instant::now();
+  while DOWNLOADS.lock().unwrap()[&109].status != DlStatus::Started {
+    assert!(start.elapsed() < Duration::from_secs(5), "The download didn't start");
+    thread::sleep(Duration::from_millis(50));
+  }
+  // Let it write a bit
+  thread::sleep(Duration::from_millis(300));
+  assert!(downloads::cancel(109));
+  downloads::wait();
+
+  assert_eq!(DOWNLOADS.lock().unwrap()[&109].status, DlStatus::Cancelled);
+  // It stopped well before the end, and the partial file was deleted
+  assert!(start.elapsed() < Duration::from_secs(8));
+  assert!(files_in(common::tmp().join("music").join("109")).is_empty());
+}
+
+#[test]
+fn done_downloads_are_not_cancelled() {
+  download(110, "Done", "/api/v1/listen/plain/");
+  assert!(!downloads::cancel(110));
+  assert_eq!(DOWNLOADS.lock().unwrap()[&110].status, DlStatus::Done);
+}
@@ -0,0 +1,33 @@
+//! Plays of downloaded tracks, sent to the instance.
+
+mod common;
+
+use mobydick_core::{api, history};
+use std::fs;
+
+#[test]
+fn plays_are_only_dropped_when_the_instance_refuses_them() {
+  let server = common::server();
+  common::log_in(server);
+  // A server error: it is kept, but doesn't stop the others
+  history::record(604);
+  history::record(601);
+  // Deleted track
+  history::record(602);
+  // Not about the play: the session may have expired
+  history::record(603);
+  history::flush();
+
+  let sent: Vec<_> = server
+    .requests_to("/api/v1/history/listenings/")
+    .iter()
+    .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["track"].clone())
+    .collect();
+  for id in &[601, 602, 603, 604] {
+    assert!(sent.contains(&(*id).into()), "{} wasn't sent", id);
+  }
+  let file = dirs::config_dir().unwrap().join("mobydick").join("listenings.json");
+  let pending: Vec<api::Listening> = serde_json::from_slice(&fs::read(file).unwrap()).unwrap();
+  let pending: Vec<i32> = pending.iter().map(|l| l.track).collect();
+  assert_eq!(pending, vec![604, 603]);
+}
@@ -0,0 +1,108 @@
+//! Commands and URLs called after downloads.
+
+mod common;
+
+use mobydick_core::{
+  downloads::{self, DlStatus, Download, DOWNLOADS},
+  settings,
+};
+use std::{collections::HashMap, fs, sync::Once};
+
+static HOOKS: Once = Once::new();
+
+/// Writes what the track hook gets next to the file, and fails for tracks
+/// called "Broken". Albums are sent to the mock server.
+fn set_hooks() {
+  let server = common::server();
+  common::log_in(server);
+  HOOKS.call_once(|| {
+    let album_hook = format!("{}/hooks/album/", server.url);
+    settings::update(move |s| {
+      s.track_hook = r#"printf '%s|%s|%s' "$MOBYDICK_TITLE" "$MOBYDICK_ALBUM" "$MOBYDICK_FILE" > "$MOBYDICK_FILE.hook"
+        if [ "$MOBYDICK_TITLE" = Broken ]; then echo 'No space left' >&2; exit 3; fi"#
+        .to_string();
+      s.album_hook = album_hook;
+    });
+  });
+}
+
+fn planned(id: i32, title: &str) -> Download {
+  Download::to(
+    common::tmp().join("music").join(id.to_string()).join(format!("{}.mp3", title)),
+    common::parse_track(id, title, "/api/v1/listen/plain/"),
+  )
+}
+
+#[test]
+fn track_hooks_get_the_track() {
+  set_hooks();
+  downloads::enqueue(vec![planned(301, "Hooked")]);
+  downloads::wait();
+
+  let dl = DOWNLOADS.lock().unwrap()[&301].clone();
+  assert_eq!(dl.status, DlStatus::Done);
+  assert_eq!(dl.hook_error, None);
+  let mut hooked = dl.output.clone().into_os_string();
+  hooked.push(".hook");
+  assert_eq!(
+    fs::read_to_string(hooked).unwrap(),
+    format!("Hooked|First Album|{}", dl.output.display())
+  );
+}
+
+#[test]
+fn failed_hooks_dont_fail_the_download() {
+  set_hooks();
+  downloads::enqueue(vec![planned(302, "Broken")]);
+  downloads::wait();
+
+  let dl = DOWNLOADS.lock().unwrap()[&302].clone();
+  assert_eq!(dl.status, DlStatus::Done);
+  assert_eq!(fs::read(&dl.output).unwrap(), common::track_content());
+  assert_eq!(
+    dl.hook_error,
+    Some("the track hook exited with status 3: No space left".to_string())
+  );
+}
+
+#[test]
+fn album_hooks_run_once_the_album_is_downloaded() {
+  set_hooks();
+  downloads::enqueue(vec![planned(303, "First"), planned(304, "Second")]);
+  downloads::wait();
+
+  let files: Vec<String> = [303, 304]
+    .iter()
+    .map(|id| DOWNLOADS.lock().unwrap()[id].output.display().to_string())
+    .collect();
+  // Other tests only download single tracks of the same album, or episodes
+  let calls: Vec<HashMap<String, String>> = common::server()
+    .requests_to("/hooks/album/")
+    .iter()
+    .map(|req| serde_json::from_slice(&req.body).unwrap())
+    .collect();
+  assert_eq!(calls.len(), 1);
+  assert_eq!(calls[0]["album"], "First Album");
+  let mut sent: Vec<&str> = calls[0]["files"].lines().collect();
+  sent.sort();
+  assert_eq!(sent, files);
+}
+
+#[test]
+fn no_album_hooks_for_episodes() {
+  set_hooks();
+  // Episodes of different podcasts, that all have an album ID of 0
+  let episodes: Vec<Download> = [305, 306]
+    .iter()
+    .map(|id| {
+      let mut dl = planned(*id, "Episode");
+      dl.track.album.id = 0;
+      dl
+    })
+    .collect();
+  downloads::enqueue(episodes);
+  downloads::wait();
+
+  assert_eq!(DOWNLOADS.lock().unwrap()[&305].status, DlStatus::Done);
+  assert!(!DOWNLOADS.lock().unwrap()[&305].with_album);
+}
@@ -0,0 +1,115 @@
+//! Reading the JSON of every Funkwhale version we support, and the downloads
+//! we saved ourselves.
+
+mod common;
+
+use mobydick_core::{
+  api::{self, nodeinfo::Version, ApiVersion},
+  downloads::{DlStatus, Download},
+};
+use std::path::PathBuf;
+
+#[test]
+fn legacy_album_with_embedded_tracks() {
+  let album: api::Album = serde_json::from_str(
+    r#"{
+      "id": 4,
+      "title": "Old Times",
+      "artist": {"id": 2, "name": "Oldies"},
+      "cover": {"original": "/media/4.jpg", "medium_square_crop": "/media/4-m.jpg"},
+      "tracks": [
+        {"id": 10, "title": "One", "artist": {"name": "Oldies"}, "listen_url": "/api/v1/listen/10/"}
+      ]
+    }"#,
+  )
+  .unwrap();
+  assert_eq!(album.artist.name, "Oldies");
+  assert_eq!(album.cover.medium_square_crop.as_ref().unwrap(), "/media/4-m.jpg");
+  assert!(album.tags.is_empty());
+  let tracks = album.tracks.clone().unwrap();
+  assert_eq!(tracks[0].listen_url, "/api/v1/listen/10/");
+  assert_eq!(tracks[0].duration, None);
+
+  let track = tracks[0].clone().into_full(&album);
+  assert_eq!(track.album.title, "Old Times");
+  assert!(track.album.tracks.is_none());
+}
+
+#[test]
+fn v1_track_with_uploads() {
+  let track = common::parse_track(7, "Intro", "/api/v1/listen/ogg/");
+  assert_eq!(track.artist.name, "The Mockers");
+  assert_eq!(track.listen_url, "/api/v1/listen/ogg/");
+  assert_eq!(track.duration, Some(180));
+  assert_eq!(track.mbid.as_ref().unwrap(), "00000000-0000-0000-0000-000000000007");
+  // Attachments
+  assert_eq!(track.album.cover.original.as_ref().unwrap(), "/media/1.jpg");
+}
+
+#[test]
+fn v2_artist_credits() {
+  let track: api::Track = serde_json::from_str(
+    r#"{
+      "id": 12,
+      "title": "Duet",
+      "artist_credit": [
+        {"credit": "Alice", "joinphrase": " & "},
+        {"credit": "Bob"}
+      ],
+      "album": {
+        "id": 5,
+        "title": "Together",
+        "artist_credit": [{"credit": "Alice", "joinphrase": ""}],
+        "cover": {"urls": {"large_square_crop": "/media/5-l.jpg"}}
+      },
+      "uploads": []
+    }"#,
+  )
+  .unwrap();
+  assert_eq!(track.artist.name, "Alice & Bob");
+  assert_eq!(track.album.artist.name, "Alice");
+  // The large crop stands in for the missing ones
+  assert_eq!(track.album.cover.medium_square_crop.as_ref().unwrap(), "/media/5-l.jpg");
+  assert_eq!(track.listen_url, "");
+  assert_eq!(track.duration, None);
+}
+
+#[test]
+fn pages() {
+  let page: api::Page<api::ArtistAlbum> = serde_json::from_str(
+    r#"{"count": 1, "next": null, "previous": null, "results": [{"id": 1, "title": "First Album", "cover": null}]}"#,
+  )
+  .unwrap();
+  assert_eq!(page.count, 1);
+  assert!(page.next.is_none());
+  assert_eq!(page.results[0].tracks_count, 0);
+}
+
+#[test]
+fn saved_downloads_can_be_read_again() {
+  let mut dl = Download::to(
+    PathBuf::from("/music/The Mockers/First Album/Intro.mp3"),
+    common::parse_track(7, "Intro", "/api/v1/listen/ogg/"),
+  );
+  dl.status = DlStatus::Done;
+  let json = serde_json::to_string(&dl).unwrap();
+  let read: Download = serde_json::from_str(&json).unwrap();
+  assert_eq!(read.status, DlStatus::Done);
+  assert_eq!(read.output, dl.output);
+  assert_eq!(read.url, "/api/v1/listen/ogg/");
+  assert_eq!(read.track.listen_url, dl.track.listen_url);
+  assert_eq!(read.track.duration, Some(180));
+  assert_eq!(read.track.mbid, dl.track.mbid);
+}
+
+#[test]
+fn versions() {
+  let v = Version::parse("1.0-rc1").unwrap();
+  assert_eq!((v.major, v.minor, v.patch), (1, 0, 0));
+  assert_eq!(ApiVersion::from_version(&v), ApiVersion::V1);
+  let v = Version::parse("0.19.0+git.1a2b3c").unwrap();
+  assert_eq!(ApiVersion::from_version(&v), ApiVersion::Legacy);
+  assert!(ApiVersion::Legacy.embeds_album_tracks());
+  assert_eq!(ApiVersion::V2.prefix(), "/api/v2");
+  assert!(Version::parse("nightly").is_none());
+}
@@ -0,0 +1,29 @@
+//! Network settings the user set up, and what happens when they are invalid.
+
+mod common;
+
+use mobydick_core::{api::RequestContext, session};
+use std::fs;
+
+#[test]
+fn invalid_settings_are_reported() {
+  common::setup();
+  let config = dirs::config_dir().unwrap().join("mobydick");
+  fs::create_dir_all(&config).unwrap();
+  let missing = common::tmp().join("missing.pem");
+  fs::write(
+    config.join("network.json"),
+    serde_json::json!({ "ca_bundle": missing }).to_string(),
+  )
+  .unwrap();
+  fs::write(
+    config.join("data.json"),
+    serde_json::json!({ "instance": "http://127.0.0.1:9", "token": common::TOKEN }).to_string(),
+  )
+  .unwrap();
+
+  let err = RequestContext::new("http://127.0.0.1:9".to_string()).err().unwrap();
+  assert!(err.to_string().contains("missing.pem"), "{}", err);
+  // A saved session can't be restored either, instead of using other settings
+  assert!(session::load().is_err());
+}
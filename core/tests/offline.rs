@@ -0,0 +1,51 @@
+//! Downloads that fail because the instance went away are retried once it is
+//! back.
+//!
+//! This waits for the offline check, and takes about 30 seconds.
+
+mod common;
+
+use mobydick_core::{
+  downloads::{self, DlStatus, Download, DOWNLOADS},
+  offline,
+};
+use std::{
+  fs, thread,
+  time::{Duration, Instant},
+};
+
+fn status(id: i32) -> DlStatus {
+  DOWNLOADS.lock().unwrap()[&id].status.clone()
+}
+
+#[test]
+fn downloads_are_retried_when_back_online() {
+  let server = common::server();
+  common::log_in(server);
+  let output = common::tmp().join("music").join("Flaky.mp3");
+  downloads::enqueue(vec![Download::to(
+    output.clone(),
+    common::parse_track(201, "Flaky", "/api/v1/listen/flaky/"),
+  )]);
+
+  // The first try fails without an answer: we are offline, and it waits
+  let start = Instant::now();
+  while !offline::is_offline() {
+    assert!(start.elapsed() < Duration::from_secs(10), "Never went offline");
+    thread::sleep(Duration::from_millis(50));
+  }
+  assert_eq!(status(201), DlStatus::Planned);
+  assert!(!output.exists());
+
+  // The instance answers again, so the download is tried another time
+  while status(201) != DlStatus::Done {
+    assert!(start.elapsed() < Duration::from_secs(90), "Never retried");
+    thread::sleep(Duration::from_millis(200));
+  }
+  assert!(!offline::is_offline());
+  assert_eq!(server.requests_to("/api/v1/listen/flaky/").len(), 2);
+  assert_eq!(
+    fs::read(&DOWNLOADS.lock().unwrap()[&201].output).unwrap(),
+    common::track_content()
+  );
+}
@@ -0,0 +1,109 @@
+//! Where downloads are saved, and how files are named.
+
+mod common;
+
+use mobydick_core::{api, paths};
+use std::{fs, path::PathBuf};
+
+fn track(title: &str) -> api::Track {
+  let mut track = common::parse_track(1, title, "/api/v1/listen/plain/");
+  track.artist.name = "Guest".to_string();
+  track
+}
+
+#[test]
+fn default_template() {
+  assert_eq!(
+    paths::render(paths::DEFAULT_TEMPLATE, &track("Intro")),
+    PathBuf::from("Guest/First Album/Intro.mp3")
+  );
+}
+
+#[test]
+fn custom_templates() {
+  let track = track("Intro");
+  assert_eq!(
+    paths::render("{artist} - {title}", &track),
+    PathBuf::from("Guest - Intro.mp3")
+  );
+  // Empty parts don't make empty folders
+  assert_eq!(
+    paths::render("/{album}//{title}/", &track),
+    PathBuf::from("First Album/Intro.mp3")
+  );
+}
+
+#[test]
+fn invalid_templates() {
+  assert!(paths::check_template("").is_ok());
+  assert!(paths::check_template("{album_artist}/{album}/{artist} - {title}").is_ok());
+  assert!(paths::check_template("{artist}/{titel}").is_err());
+  assert!(paths::check_template("{artist}/{title").is_err());
+  // Every track of an album would go to the same file
+  assert!(paths::check_template("{artist}/{album}").is_err());
+  assert!(paths::check_template("{title}/").is_ok());
+}
+
+#[test]
+fn names_are_cleaned() {
+  assert_eq!(
+    paths::render("{album}/{title}", &track("AC/DC")),
+    PathBuf::from("First Album/AC-DC.mp3")
+  );
+  assert_eq!(
+    paths::render("{title}/{title}", &track("..")),
+    PathBuf::from("_/_.mp3")
+  );
+  assert_eq!(
+    paths::render("{title}", &track("  ")),
+    PathBuf::from("_.mp3")
+  );
+}
+
+#[test]
+fn dots_in_titles_are_kept() {
+  let path = paths::render("{title}", &track("Op. 2 No.1"));
+  assert_eq!(path, PathBuf::from("Op. 2 No.1.mp3"));
+  assert_eq!(path.file_stem().unwrap(), "Op. 2 No.1");
+}
+
+#[test]
+fn tracks_go_to_the_music_folder() {
+  common::setup();
+  let music = common::tmp().join("music");
+  assert_eq!(paths::music_dir(), music);
+  assert_eq!(
+    paths::track(&track("Intro")),
+    music.join("Guest/First Album/Intro.mp3")
+  );
+}
+
+#[test]
+fn episodes() {
+  common::setup();
+  let channel: api::Channel = serde_json::from_str(
+    r#"{"uuid": "abc", "artist": {"id": 3, "name": "Talk/Show", "content_category": "podcast"}}"#,
+  )
+  .unwrap();
+  let episode: api::Episode = serde_json::from_str(
+    r#"{"id": 30, "title": "Episode 1.5", "listen_url": "/api/v1/listen/30/", "creation_date": "2020-05-17T10:00:00Z"}"#,
+  )
+  .unwrap();
+  assert_eq!(
+    paths::episode(&channel, &episode),
+    common::tmp().join("music/Podcasts/Talk-Show/2020-05-17 - Episode 1.5.mp3")
+  );
+}
+
+#[test]
+fn existing_files_have_any_extension() {
+  common::setup();
+  let dir = common::tmp().join("existing");
+  fs::create_dir_all(&dir).unwrap();
+  let planned = dir.join("Op. 2.mp3");
+  assert_eq!(paths::existing(&planned), None);
+
+  fs::write(dir.join("Op. 2.flac"), b"").unwrap();
+  fs::write(dir.join("Op. 3.mp3"), b"").unwrap();
+  assert_eq!(paths::existing(&planned), Some(dir.join("Op. 2.flac")));
+}
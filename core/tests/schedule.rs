@@ -0,0 +1,35 @@
+//! Scheduled jobs, shared between the app and the CLI through their file.
+
+mod common;
+
+use chrono::{Duration, Local};
+use mobydick_core::{
+  schedule::{self, Repeat},
+  sync::Source,
+};
+use std::fs;
+
+#[test]
+fn jobs_added_by_another_process_are_kept() {
+  common::setup();
+  let first = schedule::add(Source::Album(1), schedule::tonight(), Repeat::Once);
+
+  // As the CLI would add one while the app is running
+  let file = dirs::config_dir().unwrap().join("mobydick").join("schedule.json");
+  let mut jobs: Vec<serde_json::Value> = serde_json::from_slice(&fs::read(&file).unwrap()).unwrap();
+  let mut other = jobs[0].clone();
+  other["id"] = (first.id + 1).into();
+  other["source"] = serde_json::to_value(Source::Favorites).unwrap();
+  other["next"] = serde_json::to_value(Local::now() + Duration::days(1)).unwrap();
+  jobs.push(other);
+  fs::write(&file, serde_json::to_vec(&jobs).unwrap()).unwrap();
+
+  let jobs = schedule::jobs();
+  assert_eq!(jobs.len(), 2);
+  assert_eq!(jobs[1].source, Source::Favorites);
+  let third = schedule::add(Source::Artist(1), schedule::tonight(), Repeat::Daily);
+  assert_eq!(third.id, first.id + 2);
+  assert!(schedule::remove(first.id));
+  let ids: Vec<u32> = schedule::jobs().iter().map(|j| j.id).collect();
+  assert_eq!(ids, vec![first.id + 1, third.id]);
+}
@@ -0,0 +1,168 @@
+//! The D-Bus service, on a private bus.
+//!
+//! This needs `dbus-daemon`, so it is ignored by default: run it with
+//! `cargo test --test service -- --ignored`.
+
+mod common;
+
+use dbus::{BusType, Connection, Message};
+use mobydick_core::service;
+use std::{
+  env,
+  io::{BufRead, BufReader},
+  process::{Child, Command, Stdio},
+  thread,
+  time::{Duration, Instant},
+};
+
+type Status = (bool, Vec<(i32, String, String, String)>);
+
+/// Starts a bus that only lives as long as this test, and makes it the
+/// session bus.
+fn private_bus() -> Option<Child> {
+  let mut daemon = Command::new("dbus-daemon")
+    .args(["--session", "--nofork", "--print-address"])
+    .stdout(Stdio::piped())
+    .spawn()
+    .ok()?;
+  let mut address = String::new();
+  BufReader::new(daemon.stdout.as_mut()?)
+    .read_line(&mut address)
+    .ok()?;
+  env::set_var("DBUS_SESSION_BUS_ADDRESS", address.trim());
+  Some(daemon)
+}
+
+fn method(name: &str) -> Message {
+  Message::new_method_call(service::NAME, service::PATH, service::NAME, name).unwrap()
+}
+
+fn call(conn: &Connection, msg: Message) -> Message {
+  conn.send_with_reply_and_block(msg, 5000).unwrap()
+}
+
+fn status(conn: &Connection, id: i32) -> Option<String> {
+  let (_, dls): Status = call(conn, method("Status")).read2().unwrap();
+  dls.into_iter().find(|dl| dl.0 == id).map(|dl| dl.1)
+}
+
+fn is_served(conn: &Connection) -> bool {
+  Message::new_method_call(
+    "org.freedesktop.DBus",
+    "/",
+    "org.freedesktop.DBus",
+    "NameHasOwner",
+  )
+  .ok()
+  .map(|msg| msg.append1(service::NAME))
+  .and_then(|msg| conn.send_with_reply_and_block(msg, 500).ok())
+  .and_then(|reply| reply.get1())
+  .unwrap_or(false)
+}
+
+/// Waits for the end of an `Enqueue*` call: how many tracks were queued, or
+/// the error.
+fn queued(conn: &Connection, id: i32) -> Result<u32, String> {
+  let start = Instant::now();
+  loop {
+    assert!(start.elapsed() < Duration::from_secs(10), "{} was never queued", id);
+    for msg in conn.incoming(100) {
+      let name = msg.member().map(|name| (*name).to_string());
+      match (name.as_deref(), msg.get1::<i32>()) {
+        (Some("Enqueued"), Some(i)) if i == id => return Ok(msg.read3::<i32, &str, u32>().unwrap().2),
+        (Some("EnqueueFailed"), Some(i)) if i == id => {
+          return Err(msg.read3::<i32, &str, &str>().unwrap().2.to_string())
+        }
+        _ => {}
+      }
+    }
+  }
+}
+
+/// The signals received until `id` is finished, as (name, track ID).
+fn signals_until_finished(conn: &Connection, id: i32) -> Vec<(String, i32)> {
+  let start = Instant::now();
+  let mut signals = vec![];
+  loop {
+    assert!(start.elapsed() < Duration::from_secs(10), "{} never finished", id);
+    for msg in conn.incoming(100) {
+      let name = match msg.member() {
+        Some(name) => (*name).to_string(),
+        None => continue,
+      };
+      let track_id: i32 = msg.get1().unwrap_or_default();
+      signals.push((name.clone(), track_id));
+      if name == "Finished" && track_id == id {
+        return signals;
+      }
+    }
+  }
+}
+
+#[test]
+#[ignore = "needs dbus-daemon"]
+fn scripts_can_drive_downloads() {
+  let mut daemon = private_bus().expect("dbus-daemon couldn't be started");
+  common::log_in(common::server());
+  service::start();
+
+  let conn = Connection::get_private(BusType::Session).unwrap();
+  conn
+    .add_match(&format!("type='signal',interface='{}'", service::NAME))
+    .unwrap();
+  let start = Instant::now();
+  while !is_served(&conn) {
+    assert!(start.elapsed() < Duration::from_secs(5), "The service didn't start");
+    thread::sleep(Duration::from_millis(50));
+  }
+
+  let results: Vec<(String, i32, String)> =
+    call(&conn, method("Search").append1("intro")).read1().unwrap();
+  assert!(results.contains(&("artist".to_string(), 1, "The Mockers".to_string())));
+  assert!(results.contains(&("album".to_string(), 1, "First Album".to_string())));
+  assert!(results.contains(&("track".to_string(), 1, "Intro".to_string())));
+
+  // Downloads are followed with signals
+  call(&conn, method("EnqueueTrack").append1(401));
+  assert_eq!(queued(&conn, 401), Ok(1));
+  let signals = signals_until_finished(&conn, 401);
+  assert!(signals.contains(&("Started".to_string(), 401)));
+  assert_eq!(status(&conn, 401), Some("done".to_string()));
+  // It is not queued again
+  call(&conn, method("EnqueueTrack").append1(401));
+  assert_eq!(queued(&conn, 401), Ok(0));
+
+  // Paused downloads wait
+  call(&conn, method("Pause"));
+  call(&conn, method("EnqueueTrack").append1(402));
+  assert_eq!(queued(&conn, 402), Ok(1));
+  let (paused, _): Status = call(&conn, method("Status")).read2().unwrap();
+  assert!(paused);
+  thread::sleep(Duration::from_millis(300));
+  assert_eq!(status(&conn, 402), Some("scheduled".to_string()));
+  call(&conn, method("Resume"));
+  signals_until_finished(&conn, 402);
+  assert_eq!(status(&conn, 402), Some("done".to_string()));
+
+  // Only what is not downloaded can be cancelled
+  let cancelled: bool = call(&conn, method("Cancel").append1(402)).read1().unwrap();
+  assert!(!cancelled);
+  call(&conn, method("Pause"));
+  call(&conn, method("EnqueueTrack").append1(403));
+  assert_eq!(queued(&conn, 403), Ok(1));
+  let cancelled: bool = call(&conn, method("Cancel").append1(403)).read1().unwrap();
+  assert!(cancelled);
+  assert_eq!(status(&conn, 403), Some("cancelled".to_string()));
+  call(&conn, method("Resume"));
+
+  // It can't be cancelled twice
+  let cancelled: bool = call(&conn, method("Cancel").append1(403)).read1().unwrap();
+  assert!(!cancelled);
+
+  // Errors come as signals (there is no track 0)
+  call(&conn, method("EnqueueTrack").append1(0));
+  assert!(queued(&conn, 0).is_err());
+
+  daemon.kill().ok();
+  daemon.wait().ok();
+}
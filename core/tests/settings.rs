@@ -0,0 +1,34 @@
+//! Saving the settings and reading them back.
+
+mod common;
+
+use mobydick_core::{
+  schedule::{self, TimeWindow},
+  settings,
+};
+use std::fs;
+
+#[test]
+fn settings_are_read_back() {
+  common::setup();
+  let window = TimeWindow {
+    start: schedule::parse_hour("23:00").unwrap(),
+    end: schedule::parse_hour("06:30").unwrap(),
+  };
+  settings::update(|s| {
+    s.concurrency = 2;
+    s.windows = vec![window.clone()];
+    s.track_hook = "beet import \"$MOBYDICK_FILE\"".to_string();
+    s.album_hook = "http://localhost:32400/scan".to_string();
+  });
+  let file = dirs::config_dir().unwrap().join("mobydick").join("settings.toml");
+  assert!(fs::read_to_string(file).unwrap().contains("23:00"));
+
+  // Invalid files would be read as the default settings
+  settings::load();
+  let read = settings::get();
+  assert_eq!(read.concurrency, 2);
+  assert_eq!(read.windows, vec![window]);
+  assert_eq!(read.track_hook, "beet import \"$MOBYDICK_FILE\"");
+  assert_eq!(read.album_hook, "http://localhost:32400/scan");
+}
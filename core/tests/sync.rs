@@ -0,0 +1,74 @@
+//! Mirrors of a selection, kept in sync.
+
+mod common;
+
+use mobydick_core::{
+  api::RequestContext,
+  downloads::{self, DlStatus, Download, DOWNLOADS},
+  settings,
+  sync::{self, Source},
+};
+use std::{fs, path::PathBuf, sync::Once};
+
+static TEMPLATE: Once = Once::new();
+
+/// A mirror of a selection of the mock server, where each track has its own
+/// folder.
+fn mirror(name: &str, source: Source) -> PathBuf {
+  common::log_in(common::server());
+  TEMPLATE.call_once(|| settings::update(|s| s.template = "{title}/{title}".to_string()));
+  let dir = common::tmp().join("mirrors").join(name);
+  fs::create_dir_all(&dir).unwrap();
+  sync::add(dir.clone(), vec![source]);
+  dir
+}
+
+fn client() -> RequestContext {
+  common::client(common::server())
+}
+
+#[test]
+fn what_was_done_before_an_error_is_kept() {
+  let dir = mirror("broken", Source::Album(1));
+  // "Song" was downloaded somewhere else, but can't be copied: a file is
+  // where its folder should be
+  let elsewhere = common::tmp().join("elsewhere").join("Song.mp3");
+  fs::create_dir_all(elsewhere.parent().unwrap()).unwrap();
+  fs::write(&elsewhere, common::track_content()).unwrap();
+  let mut song = Download::to(elsewhere, common::parse_track(2, "Song", "/api/v1/listen/plain/"));
+  song.status = DlStatus::Done;
+  DOWNLOADS.lock().unwrap().insert(2, song);
+  fs::write(dir.join("Song"), "").unwrap();
+
+  assert!(sync::run(&client(), &dir, false, false).is_err());
+  // "Intro" came before, and is downloaded anyway
+  downloads::wait();
+  let intro = DOWNLOADS.lock().unwrap()[&1].clone();
+  assert_eq!(intro.status, DlStatus::Done);
+  assert!(intro.output.starts_with(&dir));
+
+  // And it is known to be there
+  fs::remove_file(dir.join("Song")).unwrap();
+  let report = sync::run(&client(), &dir, false, false).unwrap();
+  assert_eq!(report.up_to_date, 1);
+  assert_eq!(report.copied, vec![dir.join("Song").join("Song.mp3")]);
+}
+
+#[test]
+fn tracks_downloaded_elsewhere_are_copied_once_they_are() {
+  let dir = mirror("copied", Source::Playlist(1));
+  let elsewhere = common::tmp().join("elsewhere").join("Slow.mp3");
+  downloads::enqueue(vec![Download::to(
+    elsewhere.clone(),
+    common::parse_track(501, "Slow", "/api/v1/listen/slow/"),
+  )]);
+
+  let report = sync::run(&client(), &dir, false, false).unwrap();
+  assert_eq!(report.new.len(), 1);
+  downloads::wait();
+  let dl = DOWNLOADS.lock().unwrap()[&501].clone();
+  assert_eq!(dl.status, DlStatus::Done);
+  assert_eq!(dl.output, elsewhere);
+  let copy = dir.join("Slow").join("Slow.mp3");
+  assert_eq!(fs::read(copy).unwrap(), common::track_content());
+}
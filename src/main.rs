@@ -1,12 +1,10 @@
 use gtk::{self, prelude::*, *};
-use std::{
-  cell::RefCell,
-  collections::HashMap,
-  fs,
-  path::PathBuf,
-  rc::Rc,
-  sync::{Arc, Mutex},
+use mobydick_core::{
+  api, channels, db,
+  downloads::{self, enqueue, on_disk, DlStatus, Download, DOWNLOADS},
+  export, history, paths, scan, schedule, service, session, settings, sync, upload,
 };
+use std::{cell::RefCell, rc::Rc};
 
 macro_rules! clone {
     (@param _) => ( _ );
@@ -34,27 +32,19 @@ macro_rules! rc {
     )
 }
 
+/// Runs `$then` on the main thread once the result of a `Call` is there.
+///
+/// With `for $widget,` the call is cancelled when the widget is destroyed.
 macro_rules! wait {
-  ($exp:expr => | const $res:ident | $then:block) => {
-    let rx = $exp;
-    gtk::idle_add(move || match rx.try_recv() {
-      Err(_) => glib::Continue(true),
-      Ok($res) => {
-        $then;
-        glib::Continue(false)
-      }
-    })
-  };
-  ($exp:expr => | $res:ident | $then:block) => {
-    let rx = $exp;
-    gtk::idle_add(move || match rx.try_recv() {
-      Err(_) => glib::Continue(true),
-      Ok(mut $res) => {
-        $then;
-        glib::Continue(false)
-      }
-    })
+  (for $widget:expr, $exp:expr => | $res:ident | $then:block) => {
+    crate::ui::on_result_for(&$widget, $exp, move |$res| $then)
   };
+  ($exp:expr => | const $res:ident | $then:block) => {{
+    crate::ui::on_result($exp, move |$res| $then);
+  }};
+  ($exp:expr => | $res:ident | $then:block) => {{
+    crate::ui::on_result($exp, move |$res| $then);
+  }};
 }
 
 macro_rules! client {
@@ -63,7 +53,7 @@ macro_rules! client {
   };
 }
 
-mod api;
+mod offline;
 mod ui;
 
 #[derive(Debug)]
@@ -76,78 +66,6 @@ pub struct AppState {
 
 pub type State = Rc<RefCell<AppState>>;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum DlStatus {
-  Planned,
-  Started,
-  Done,
-  Cancelled,
-}
-
-#[derive(Debug, Clone)]
-pub struct Download {
-  url: String,
-  status: DlStatus,
-  output: PathBuf,
-  track: api::Track,
-}
-
-impl Download {
-  pub fn ended(&mut self, out: PathBuf) {
-    self.status = DlStatus::Done;
-    self.output = out;
-  }
-}
-
-lazy_static::lazy_static! {
-    static ref DOWNLOADS: Arc<Mutex<HashMap<i32, Download>>> = Arc::new(Mutex::new(HashMap::new()));
-
-    static ref DL_JOBS: workerpool::Pool<TrackDl> = workerpool::Pool::new(5);
-}
-
-#[derive(Default)]
-struct TrackDl;
-
-impl workerpool::Worker for TrackDl {
-  type Input = Download;
-  type Output = ();
-
-  fn execute(&mut self, dl: Self::Input) -> Self::Output {
-    if dl.status == DlStatus::Cancelled {
-      return;
-    }
-
-    {
-      let mut dls = DOWNLOADS.lock().unwrap();
-      let dl = dls.get_mut(&dl.track.id).unwrap();
-      dl.status = DlStatus::Started;
-    }
-
-    let mut res = client!().get(&dl.url).send().unwrap();
-
-    let ext = res
-      .headers()
-      .get(reqwest::header::CONTENT_DISPOSITION)
-      .and_then(|h| h.to_str().ok())
-      .unwrap_or(".mp3")
-      .rsplitn(2, '.')
-      .next()
-      .unwrap_or("mp3");
-
-    fs::create_dir_all(dl.output.parent().unwrap()).unwrap();
-    let mut out = dl.output.clone();
-    out.set_extension(ext);
-    let mut file = fs::File::create(out.clone()).unwrap();
-
-    res.copy_to(&mut file).unwrap();
-
-    let mut dls = DOWNLOADS.lock().unwrap();
-    if let Some(dl) = dls.get_mut(&dl.track.id) {
-      dl.ended(out);
-    }
-  }
-}
-
 fn main() {
   if gtk::init().is_err() {
     println!("Failed to initialize GTK.");
@@ -162,42 +80,38 @@ fn main() {
   window.connect_delete_event(move |_, _| {
     gtk::main_quit();
 
-    fs::create_dir_all(dirs::config_dir().unwrap().join("mobydick")).unwrap();
-    fs::write(
-      dirs::config_dir()
-        .unwrap()
-        .join("mobydick")
-        .join("data.json"),
-      serde_json::to_string(&client!().to_json()).unwrap(),
-    )
-    .unwrap();
+    downloads::save();
+    session::save();
 
     Inhibit(false)
   });
 
+  settings::load();
+  listen();
   init(Rc::new(RefCell::new(window)));
+  history::mpris::watch();
 
   gtk::main();
 }
 
-fn init(window: Rc<RefCell<Window>>) {
-  let connected = fs::read(
-    dirs::config_dir()
-      .unwrap()
-      .join("mobydick")
-      .join("data.json"),
-  )
-  .ok()
-  .and_then(|f| {
-    let json: serde_json::Value = serde_json::from_slice(&f).ok()?;
-    let mut api_ctx = crate::api::API.lock().ok()?;
-    let mut ctx = api::RequestContext::new(json["instance"].as_str()?.to_string());
-    ctx.auth(json["token"].as_str()?.to_string());
-    *api_ctx = Some(ctx);
+/// Updates the UI when something changes in the background.
+fn listen() {
+  offline::init();
+  downloads::on_change(ui::dl_list::refresh);
+  upload::on_change(|upload| match upload {
+    Some(upload) => ui::libraries::update_upload(upload),
+    None => ui::libraries::refresh_uploads(),
+  });
+}
 
-    Some(())
-  })
-  .is_some();
+fn init(window: Rc<RefCell<Window>>) {
+  // With invalid network settings, the login page lets the user fix them
+  let loaded = session::load();
+  let connected = *loaded.as_ref().unwrap_or(&false);
+  if connected {
+    downloads::load();
+    downloads::resume();
+  }
 
   let state = Rc::new(RefCell::new(AppState {
     window: window.clone(),
@@ -240,25 +154,55 @@ fn init(window: Rc<RefCell<Window>>) {
   window.borrow().show_all();
 
   if connected {
-    let main_page =
-      ui::main_page::render(state.borrow().window.clone(), &state.borrow().header, &{
-        let s = StackSwitcher::new();
-        s.set_stack(&state.borrow().stack);
-        s
-      });
-    state
-      .borrow()
-      .stack
-      .add_titled(&main_page, "main", "Search Music");
-    state
-      .borrow()
-      .stack
-      .add_titled(&*ui::dl_list::render().borrow(), "downloads", "Downloads");
-    state.borrow().stack.set_visible_child_name("main");
+    add_main_pages(&state.borrow());
   } else {
     let login_page = ui::login_page::render(state.clone());
     state.borrow().stack.add_named(&login_page, "login");
   }
+  let mut errors: Vec<String> = db::problem().into_iter().collect();
+  if let Err(e) = loaded {
+    errors.push(e.to_string());
+  }
+  if !errors.is_empty() {
+    show_error(state, &errors.join("\n"));
+  }
+}
+
+/// Adds the pages that are shown once logged in.
+pub fn add_main_pages(state: &AppState) {
+  let main_page = ui::main_page::render(state.window.clone(), &state.header, &{
+    let s = StackSwitcher::new();
+    s.set_stack(&state.stack);
+    s
+  });
+  state.stack.add_titled(&main_page, "main", "Search Music");
+  state
+    .stack
+    .add_titled(&ui::favorites::render(), "favorites", "Favorites");
+  state
+    .stack
+    .add_titled(&ui::playlists::render(), "playlists", "Playlists");
+  state
+    .stack
+    .add_titled(&ui::libraries::render(), "libraries", "Libraries");
+  state
+    .stack
+    .add_titled(&ui::channels::render(), "channels", "Channels");
+  state
+    .stack
+    .add_titled(&ui::radios::render(), "radios", "Radios");
+  state
+    .stack
+    .add_titled(&ui::tags::render(&state.stack), "tags", "Tags");
+  state
+    .stack
+    .add_titled(&*ui::dl_list::render().borrow(), "downloads", "Downloads");
+  state.stack.set_visible_child_name("main");
+
+  channels::schedule();
+  schedule::watch();
+  service::start();
+  std::thread::spawn(history::flush);
 }
 
 fn show_error(state: State, msg: &str) {
@@ -278,15 +222,7 @@ fn show_error(state: State, msg: &str) {
 }
 
 fn logout(window: Rc<RefCell<Window>>) {
-  fs::remove_file(
-    dirs::config_dir()
-      .unwrap()
-      .join("mobydick")
-      .join("data.json"),
-  )
-  .ok();
-  *api::API.lock().unwrap() = None;
-  *DOWNLOADS.lock().unwrap() = HashMap::new();
+  session::forget();
   {
     let window = window.borrow();
     for ch in window.get_children() {
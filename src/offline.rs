@@ -0,0 +1,37 @@
+//! Brings the changes of the offline mode (see `mobydick_core::offline`) to
+//! the main thread, where widgets can be updated.
+
+pub use mobydick_core::offline::{is_network_error, is_offline, went_offline};
+use std::cell::RefCell;
+
+type Listener = Box<dyn Fn(bool)>;
+
+thread_local! {
+  static LISTENERS: RefCell<Vec<Listener>> = RefCell::new(Vec::new());
+}
+
+/// Forwards the changes noticed by the core to the main thread. Called once,
+/// on start.
+pub fn init() {
+  mobydick_core::offline::on_change(|offline| {
+    glib::idle_add(move || {
+      notify(offline);
+      glib::Continue(false)
+    });
+  });
+}
+
+/// Calls `f` on the main thread every time we go offline (`true`) or come back
+/// online (`false`).
+pub fn subscribe<F: Fn(bool) + 'static>(f: F) {
+  f(is_offline());
+  LISTENERS.with(|l| l.borrow_mut().push(Box::new(f)));
+}
+
+fn notify(offline: bool) {
+  LISTENERS.with(|l| {
+    for listener in l.borrow().iter() {
+      listener(offline);
+    }
+  });
+}
@@ -1,6 +1,16 @@
-use crate::{api, ui::network_image::NetworkImage, DlStatus, Download};
+use crate::{
+  api, channels, downloads, export, scan,
+  schedule::{self, Repeat},
+  sync::Source,
+  ui::{self, favorites, network_image::NetworkImage, playlist_editor},
+  DlStatus, Download,
+};
 use gtk::*;
-use std::{cell::RefCell, rc::Rc, sync::mpsc::channel, thread};
+use std::{
+  cell::{Cell, RefCell},
+  path::{Path, PathBuf},
+  rc::Rc,
+};
 
 pub fn render<T>(model: T) -> Rc<RefCell<Grid>>
 where
@@ -28,6 +38,62 @@ where
   sub_text.set_hexpand(true);
   sub_text.set_halign(Align::Start);
 
+  if let Some(track_id) = model.track_id() {
+    let fav_bt = ToggleButton::new();
+    fav_bt.set_image(&Image::new_from_icon_name(
+      "emblem-favorite-symbolic",
+      IconSize::Button.into(),
+    ));
+    fav_bt.set_valign(Align::Center);
+    fav_bt.set_relief(ReliefStyle::None);
+    fav_bt.set_active(favorites::is_favorite(track_id));
+    fav_bt.set_tooltip_text("Favorite");
+    // Set while the button is put back as it was, after an error
+    let reverting = Rc::new(Cell::new(false));
+    fav_bt.connect_toggled(move |bt| {
+      if reverting.replace(false) {
+        return;
+      }
+      let favorite = bt.get_active();
+      clone!(bt, reverting);
+      favorites::set_favorite(track_id, favorite, move |e| {
+        reverting.set(true);
+        bt.set_active(!favorite);
+        bt.set_tooltip_text(format!("Couldn't update your favorites. {}", e).as_str());
+      });
+    });
+    card.attach(&fav_bt, 4, 0, 1, 2);
+  }
+
+  if let Some(menu) = model.menu() {
+    let menu_bt = MenuButton::new();
+    menu_bt.set_image(&Image::new_from_icon_name(
+      "view-more-symbolic",
+      IconSize::Button.into(),
+    ));
+    menu_bt.set_valign(Align::Center);
+    menu_bt.set_relief(ReliefStyle::None);
+    menu_bt.set_popup(&menu);
+    card.attach(&menu_bt, 5, 0, 1, 2);
+  }
+
+  if let Some(source) = model.source() {
+    let schedule_bt = Button::new();
+    schedule_bt.set_image(&Image::new_from_icon_name(
+      "alarm-symbolic",
+      IconSize::Button.into(),
+    ));
+    schedule_bt.set_valign(Align::Center);
+    schedule_bt.set_relief(ReliefStyle::None);
+    schedule_bt.set_tooltip_text("Download tonight");
+    schedule_bt.connect_clicked(move |bt| {
+      let job = schedule::add(source.clone(), schedule::tonight(), Repeat::Once);
+      bt.set_sensitive(false);
+      bt.set_tooltip_text(format!("Will be downloaded at {}", job.next.format("%H:%M")).as_ref());
+    });
+    card.attach(&schedule_bt, 6, 0, 1, 2);
+  }
+
   rc!(card);
   if let Some(dl) = model.download_status() {
     match dl.status {
@@ -40,20 +106,26 @@ where
         }
 
         let out = dl.output.clone();
-        open_bt.connect_clicked(move |_| {
-          open::that(out.clone()).unwrap();
-          println!("opened file");
+        let track_id = dl.track.id;
+        open_bt.connect_clicked(move |bt| {
+          if open(bt, &out) {
+            crate::history::record(track_id);
+          }
         });
         card.borrow().attach(&open_bt, 3, 0, 1, 2);
 
+        if let Some(ref error) = dl.hook_error {
+          sub_text.set_text(format!("{} — Downloaded, but a hook failed", model.subtext()).as_ref());
+          sub_text.set_tooltip_text(format!("After the download, {}", error).as_ref());
+        }
+
         let open_bt = Button::new_with_label("View File");
         open_bt.set_valign(Align::Center);
         open_bt.set_vexpand(true);
 
         let out = dl.output;
-        open_bt.connect_clicked(move |_| {
-          open::that(&(*out.parent().unwrap())).unwrap();
-          println!("opened folder");
+        open_bt.connect_clicked(move |bt| {
+          open(bt, out.parent().unwrap());
         });
         card.borrow().attach(&open_bt, 2, 0, 1, 2);
       }
@@ -67,10 +139,7 @@ where
 
         let track_id = dl.track.id;
         cancel_bt.connect_clicked(move |_| {
-          let mut dls = crate::DOWNLOADS.lock().unwrap();
-          let dl = dls.get_mut(&track_id).unwrap();
-          dl.status = DlStatus::Cancelled;
-          println!("Cancelled");
+          downloads::cancel(track_id);
         });
         card.borrow().attach(&cancel_bt, 3, 0, 1, 2);
 
@@ -80,10 +149,50 @@ where
           sub_text.set_text(format!("{} — Download in progress", model.subtext()).as_ref());
         }
       }
+      DlStatus::Scheduled => {
+        let cancel_bt = Button::new_with_label("Cancel");
+        cancel_bt.set_valign(Align::Center);
+        cancel_bt.set_vexpand(true);
+
+        let track_id = dl.track.id;
+        cancel_bt.connect_clicked(move |_| {
+          downloads::cancel(track_id);
+        });
+        card.borrow().attach(&cancel_bt, 3, 0, 1, 2);
+        if downloads::is_paused() {
+          sub_text.set_text(format!("{} — Paused", model.subtext()).as_ref());
+        } else {
+          sub_text.set_text(format!("{} — Waiting for the download window", model.subtext()).as_ref());
+        }
+      }
       DlStatus::Cancelled => {
         sub_text.set_text(format!("{} — Cancelled", model.subtext()).as_ref());
       }
+      DlStatus::Failed => {
+        let retry_bt = Button::new_with_label("Retry");
+        retry_bt.set_valign(Align::Center);
+        retry_bt.set_vexpand(true);
+
+        let retried = Download::to(dl.output.clone(), dl.track.clone());
+        retry_bt.connect_clicked(move |_| {
+          downloads::enqueue(vec![retried.clone()]);
+        });
+        card.borrow().attach(&retry_bt, 3, 0, 1, 2);
+        sub_text.set_text(format!("{} — Download failed", model.subtext()).as_ref());
+        if let Some(ref error) = dl.error {
+          sub_text.set_tooltip_text(error.as_ref());
+        }
+      }
     }
+  } else if let Some(file) = model.local_file() {
+    let open_bt = Button::new_with_label("Play");
+    open_bt.set_valign(Align::Center);
+    open_bt.set_vexpand(true);
+    open_bt.connect_clicked(move |bt| {
+      open(bt, &file);
+    });
+    card.borrow().attach(&open_bt, 3, 0, 1, 2);
+    sub_text.set_text(format!("{} — Already in your library", model.subtext()).as_ref());
   } else {
     let dl_bt = Button::new_with_label("Download");
     dl_bt.set_valign(Align::Center);
@@ -94,32 +203,33 @@ where
 
     rc!(dl_bt);
     {
-      clone!(dl_bt, card);
-      wait!({ // Fetch the list of files to download
-          let (tx, rx) = channel();
-          thread::spawn(move || {
-              let dl_list = model.downloads();
-              tx.send(dl_list).unwrap();
-          });
-          rx
-      } => | const dl_list | {
+      clone!(dl_bt, card, model);
+      let dl_bt_widget = dl_bt.borrow().clone();
+      let fetched = model.clone();
+      let list = move || {
+        let all = fetched.downloads();
+        let missing: Vec<_> = all.iter().filter(|dl| !downloads::on_disk(dl)).cloned().collect();
+        (all, missing)
+      };
+      wait!(for dl_bt_widget, api::background(list) => |fetched| { // Fetch the list of files to download
+          let (all, dl_list) = fetched;
+          let count = all.len();
           let dl_bt = dl_bt.borrow();
-          if dl_list.is_empty() {	// Nothing to download
+          if count == 0 {	// Nothing to download
               dl_bt.set_label("Not available");
               dl_bt.set_sensitive(false);
+          } else if dl_list.is_empty() { // Everything is already on disk
+              dl_bt.set_label("Already in your library");
+              dl_bt.set_sensitive(false);
           } else {
               clone!(dl_list);
               dl_bt.connect_clicked(move |_| {
-                  for dl in dl_list.clone() {
-                      let mut dls = crate::DOWNLOADS.lock().unwrap();
-                      dls.insert(dl.track.id, dl.clone());
-
-                      crate::DL_JOBS.execute(dl);
-                  }
+                  model.downloading(&all);
+                  crate::enqueue(dl_list.clone());
               });
           }
 
-          if dl_list.len() > 1 { // Not only one song
+          if count > 1 { // Not only one song
               let more_bt = Button::new_with_label("Details");
               more_bt.set_valign(Align::Center);
               more_bt.set_vexpand(true);
@@ -135,11 +245,27 @@ where
     let card = card.borrow();
     card.attach(&main_text, 1, 0, 1, 1);
     card.attach(&sub_text, 1, 1, 1, 1);
+    let tags = model.tags();
+    if !tags.is_empty() {
+      card.attach(&ui::tags::chips(&tags), 1, 2, 1, 1);
+    }
   }
 
   card
 }
 
+/// Opens a file or a folder with the default application. If it can't, the
+/// reason is shown on `bt`.
+fn open(bt: &Button, path: &Path) -> bool {
+  let error = match open::that(path) {
+    Ok(status) if status.success() => return true,
+    Ok(status) => format!("No application could open it ({})", status),
+    Err(e) => e.to_string(),
+  };
+  bt.set_tooltip_text(format!("Couldn't open {}. {}", path.display(), error).as_str());
+  false
+}
+
 pub trait CardModel: Clone + Send + Sync {
   fn text(&self) -> String;
   fn subtext(&self) -> String {
@@ -154,6 +280,34 @@ pub trait CardModel: Clone + Send + Sync {
   fn download_status(&self) -> Option<Download> {
     None
   }
+
+  /// A file that already contains this track, found in a scanned music folder
+  fn local_file(&self) -> Option<PathBuf> {
+    None
+  }
+
+  /// If this card represents a single track, its ID
+  fn track_id(&self) -> Option<i32> {
+    None
+  }
+
+  /// Called when the user starts downloading, with all the downloads of this
+  /// card (including the ones that are already on disk), in order
+  fn downloading(&self, _dls: &[Download]) {}
+
+  /// Other actions on this card
+  fn menu(&self) -> Option<Menu> {
+    None
+  }
+
+  /// The selection this card represents, if its download can be scheduled
+  fn source(&self) -> Option<Source> {
+    None
+  }
+
+  fn tags(&self) -> Vec<String> {
+    vec![]
+  }
 }
 
 impl CardModel for api::Artist {
@@ -162,7 +316,10 @@ impl CardModel for api::Artist {
   }
 
   fn subtext(&self) -> String {
-    format!("{} albums", self.albums.clone().unwrap().len())
+    match self.albums {
+      Some(ref albums) => format!("{} albums", albums.len()),
+      None => String::new(),
+    }
   }
 
   fn image_url(&self) -> Option<String> {
@@ -175,29 +332,20 @@ impl CardModel for api::Artist {
   }
 
   fn downloads(&self) -> Vec<Download> {
-    let mut dls = vec![];
-    for album in self.albums.clone().unwrap_or_default() {
-      let album: api::Album = client!()
-        .get(&format!("/api/v1/albums/{}/", album.id))
-        .send()
-        .unwrap()
-        .json()
-        .unwrap();
-
-      for track in album.clone().tracks.unwrap_or_default() {
-        dls.push(Download {
-          url: track.listen_url.clone(),
-          output: dirs::audio_dir()
-            .unwrap()
-            .join(self.name.clone())
-            .join(album.title.clone())
-            .join(format!("{}.mp3", track.title.clone())),
-          status: DlStatus::Planned,
-          track: track.clone().into_full(&album),
-        });
-      }
-    }
-    dls
+    let client = client!().clone();
+    downloads::of_artist(&client, self.id, self.albums.clone())
+  }
+
+  fn menu(&self) -> Option<Menu> {
+    Some(ui::radios::artist_menu(self.id, self.name.clone()))
+  }
+
+  fn source(&self) -> Option<Source> {
+    Some(Source::Artist(self.id))
+  }
+
+  fn tags(&self) -> Vec<String> {
+    self.tags.clone()
   }
 }
 
@@ -219,22 +367,23 @@ impl CardModel for api::Album {
   }
 
   fn downloads(&self) -> Vec<Download> {
-    self
-      .tracks
-      .clone()
-      .unwrap_or_default()
-      .iter()
-      .map(|track| Download {
-        url: track.listen_url.clone(),
-        output: dirs::audio_dir()
-          .unwrap()
-          .join(self.artist.name.clone())
-          .join(self.title.clone())
-          .join(format!("{}.mp3", track.title.clone())),
-        status: DlStatus::Planned,
-        track: track.clone().into_full(&self),
-      })
-      .collect()
+    let client = client!().clone();
+    downloads::of_album(&client, self)
+  }
+
+  fn menu(&self) -> Option<Menu> {
+    let album = self.clone();
+    Some(playlist_editor::add_menu(move || {
+      album.downloads().iter().map(|dl| dl.track.id).collect()
+    }))
+  }
+
+  fn source(&self) -> Option<Source> {
+    Some(Source::Album(self.id))
+  }
+
+  fn tags(&self) -> Vec<String> {
+    self.tags.clone()
   }
 }
 
@@ -252,19 +401,163 @@ impl CardModel for api::Track {
   }
 
   fn downloads(&self) -> Vec<Download> {
-    vec![Download {
-      url: self.listen_url.clone(),
-      output: dirs::audio_dir()
-        .unwrap()
-        .join(self.artist.name.clone())
-        .join(self.album.title.clone())
-        .join(format!("{}.mp3", self.title.clone())),
-      status: DlStatus::Planned,
-      track: self.clone(),
-    }]
+    vec![Download::new(self.clone())]
   }
 
   fn download_status(&self) -> Option<Download> {
     crate::DOWNLOADS.lock().ok()?.get(&self.id).cloned()
   }
+
+  fn local_file(&self) -> Option<PathBuf> {
+    scan::find(self)
+  }
+
+  fn track_id(&self) -> Option<i32> {
+    Some(self.id)
+  }
+
+  fn menu(&self) -> Option<Menu> {
+    let id = self.id;
+    Some(playlist_editor::add_menu(move || vec![id]))
+  }
+
+  fn tags(&self) -> Vec<String> {
+    self.tags.clone()
+  }
+}
+
+impl CardModel for api::Playlist {
+  fn text(&self) -> String {
+    self.name.clone()
+  }
+
+  fn subtext(&self) -> String {
+    format!("{} tracks, by {}", self.tracks_count, self.user.username)
+  }
+
+  fn image_url(&self) -> Option<String> {
+    self.album_covers.first().cloned()
+  }
+
+  fn downloads(&self) -> Vec<Download> {
+    let client = client!().clone();
+    client
+      .playlist_tracks(self.id)
+      .unwrap_or_default()
+      .into_iter()
+      .flat_map(|t| t.track.downloads())
+      .collect()
+  }
+
+  fn downloading(&self, dls: &[Download]) {
+    export::watch(export::Export {
+      playlist_id: self.id,
+      name: self.name.clone(),
+      modification_date: self.modification_date.clone(),
+      tracks: dls.iter().map(|dl| dl.track.clone()).collect(),
+    });
+  }
+
+  fn source(&self) -> Option<Source> {
+    Some(Source::Playlist(self.id))
+  }
+}
+
+/// A playlist of the user, that they can edit
+#[derive(Clone)]
+pub struct OwnPlaylist(pub api::Playlist);
+
+impl CardModel for OwnPlaylist {
+  fn text(&self) -> String {
+    self.0.text()
+  }
+
+  fn subtext(&self) -> String {
+    self.0.subtext()
+  }
+
+  fn image_url(&self) -> Option<String> {
+    self.0.image_url()
+  }
+
+  fn downloads(&self) -> Vec<Download> {
+    self.0.downloads()
+  }
+
+  fn downloading(&self, dls: &[Download]) {
+    self.0.downloading(dls)
+  }
+
+  fn menu(&self) -> Option<Menu> {
+    Some(playlist_editor::edit_menu(self.0.clone()))
+  }
+
+  fn source(&self) -> Option<Source> {
+    self.0.source()
+  }
+}
+
+impl CardModel for api::Channel {
+  fn text(&self) -> String {
+    self.artist.name.clone()
+  }
+
+  fn subtext(&self) -> String {
+    format!(
+      "{}, {} subscribers",
+      if self.is_podcast() { "Podcast" } else { "Channel" },
+      self.subscriptions_count
+    )
+  }
+
+  fn image_url(&self) -> Option<String> {
+    self.artist.cover.medium_square_crop.clone()
+  }
+
+  fn downloads(&self) -> Vec<Download> {
+    let client = client!().clone();
+    channels::episodes(&client, self, 500)
+      .unwrap_or_default()
+      .into_iter()
+      .map(|episode| channels::download(self, episode))
+      .collect()
+  }
+
+  fn menu(&self) -> Option<Menu> {
+    Some(ui::channels::menu(self.clone()))
+  }
+}
+
+/// An episode, with the channel it was published in
+#[derive(Clone)]
+pub struct ChannelEpisode(pub api::Channel, pub api::Episode);
+
+impl CardModel for ChannelEpisode {
+  fn text(&self) -> String {
+    self.1.title.clone()
+  }
+
+  fn subtext(&self) -> String {
+    format!(
+      "Published on {}",
+      self.1.creation_date.get(..10).unwrap_or_default()
+    )
+  }
+
+  fn image_url(&self) -> Option<String> {
+    self
+      .1
+      .cover
+      .medium_square_crop
+      .clone()
+      .or_else(|| self.0.artist.cover.medium_square_crop.clone())
+  }
+
+  fn downloads(&self) -> Vec<Download> {
+    vec![channels::download(&self.0, self.1.clone())]
+  }
+
+  fn download_status(&self) -> Option<Download> {
+    crate::DOWNLOADS.lock().ok()?.get(&self.1.id).cloned()
+  }
 }
@@ -0,0 +1,196 @@
+use crate::{
+  api::{self, background, execute, ApiVersion},
+  channels,
+  ui::{card, title},
+};
+use gtk::*;
+use std::{collections::HashSet, sync::Mutex};
+
+lazy_static::lazy_static! {
+  /// UUIDs of the channels the user subscribed to
+  static ref SUBSCRIPTIONS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+pub fn is_subscribed(uuid: &str) -> bool {
+  SUBSCRIPTIONS.lock().unwrap().contains(uuid)
+}
+
+pub fn render() -> gtk::Box {
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+  cont.set_valign(Align::Start);
+  cont.set_margin_top(48);
+  cont.set_margin_bottom(48);
+  cont.set_margin_start(96);
+  cont.set_margin_end(96);
+
+  if client!().version() == ApiVersion::Legacy {
+    cont.add(&Label::new("Channels and podcasts need Funkwhale 1.0 or newer."));
+    cont.show_all();
+    return cont;
+  }
+
+  let sub_title = title("Subscriptions");
+  sub_title.set_halign(Align::Start);
+  cont.add(&sub_title);
+  let subscriptions = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&subscriptions);
+
+  let search_title = title("Find channels and podcasts");
+  search_title.set_halign(Align::Start);
+  cont.add(&search_title);
+  let search = SearchEntry::new();
+  search.set_placeholder_text("Search channels");
+  cont.add(&search);
+  let results = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&results);
+
+  search.connect_activate(move |s| {
+    let req = client!().api_get("/channels/").query(&[
+      ("q", s.get_text().unwrap_or_default()),
+      ("page_size", "50".to_string()),
+    ]);
+    show_channels(&results, req, "No channel found. Try something else.");
+  });
+
+  let ids_req = client!().api_get("/subscriptions/all/").uncached();
+  wait!(execute(ids_req) => |res| {
+    if let Ok(ids) = res.and_then(|r| r.json::<api::SubscriptionIds>()) {
+      SUBSCRIPTIONS
+        .lock()
+        .unwrap()
+        .extend(ids.results.into_iter().map(|s| s.channel));
+    }
+    let req = client!()
+      .api_get("/channels/")
+      .query(&[("subscribed", "true"), ("page_size", "100")])
+      .uncached();
+    show_channels(&subscriptions, req, "You are not subscribed to any channel yet.");
+  });
+
+  cont.show_all();
+  cont
+}
+
+fn show_channels(list: &gtk::Box, req: api::Request, empty: &'static str) {
+  for ch in list.get_children() {
+    list.remove(&ch);
+  }
+
+  let widget = list.clone();
+  let list = list.clone();
+  wait!(for widget, execute(req) => |res| {
+    match res.and_then(|r| r.json::<api::Page<api::Channel>>()) {
+      Ok(page) => {
+        if page.results.is_empty() {
+          list.add(&Label::new(empty));
+        }
+        for channel in page.results {
+          list.add(&*card::render(channel).borrow());
+        }
+      }
+      Err(e) => {
+        list.add(&Label::new(format!("Couldn't load the channels. {}", e).as_ref()));
+      }
+    }
+    list.show_all();
+  });
+}
+
+/// Subscription and episodes of a channel.
+pub fn menu(channel: api::Channel) -> Menu {
+  let menu = Menu::new();
+
+  let subscribed = CheckMenuItem::new_with_label("Subscribed");
+  subscribed.set_active(is_subscribed(&channel.uuid));
+  menu.append(&subscribed);
+
+  let auto = CheckMenuItem::new_with_label("Download new episodes automatically");
+  auto.set_active(channels::is_auto(&channel.uuid));
+  auto.set_sensitive(is_subscribed(&channel.uuid));
+  auto.connect_toggled(clone!(channel => move |item| {
+      channels::set_auto(&channel, item.get_active());
+  }));
+  if channel.is_podcast() {
+    menu.append(&auto);
+  }
+
+  subscribed.connect_toggled(clone!(channel, auto => move |item| {
+      let subscribe = item.get_active();
+      let req = client!().api_post(format!(
+          "/channels/{}/{}/",
+          channel.uuid,
+          if subscribe { "subscribe" } else { "unsubscribe" }
+      ));
+      clone!(channel, auto);
+      wait!(execute(req) => |res| {
+          if res.map(|r| r.status().is_success()).unwrap_or(false) {
+              let mut subs = SUBSCRIPTIONS.lock().unwrap();
+              if subscribe {
+                  subs.insert(channel.uuid.clone());
+              } else {
+                  subs.remove(&channel.uuid);
+              }
+              auto.set_sensitive(subscribe);
+              if !subscribe {
+                  auto.set_active(false);
+              }
+          }
+      });
+  }));
+
+  let show = MenuItem::new_with_label("Show episodes…");
+  show.connect_activate(move |_| {
+    episodes_dialog(channel.clone());
+  });
+  menu.append(&show);
+
+  menu.show_all();
+  menu
+}
+
+/// Lists the latest episodes of a channel.
+fn episodes_dialog(channel: api::Channel) {
+  let dialog = Dialog::new_with_buttons(
+    Some(channel.artist.name.as_str()),
+    None::<&Window>,
+    DialogFlags::MODAL,
+    &[("Close", ResponseType::Close.into())],
+  );
+  dialog.set_default_size(720, 540);
+
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  list.set_margin_top(12);
+  list.set_margin_bottom(12);
+  list.set_margin_start(12);
+  list.set_margin_end(12);
+  let scrolled = ScrolledWindow::new(None, None);
+  scrolled.set_vexpand(true);
+  scrolled.add(&list);
+  dialog.get_content_area().add(&scrolled);
+
+  let widget = list.clone();
+  clone!(list);
+  let client = client!().clone();
+  wait!(for widget, background(move || {
+    channels::episodes(&client, &channel, 50).map(|eps| (channel, eps))
+  }) => |res| {
+    match res {
+      Ok((channel, episodes)) => {
+        if episodes.is_empty() {
+          list.add(&Label::new("Nothing was published in this channel yet."));
+        }
+        for episode in episodes {
+          list.add(&*card::render(card::ChannelEpisode(channel.clone(), episode)).borrow());
+        }
+      }
+      Err(e) => {
+        list.add(&Label::new(format!("Couldn't load the episodes. {}", e).as_ref()));
+      }
+    }
+    list.show_all();
+  });
+
+  dialog.show_all();
+  dialog.run();
+  dialog.destroy();
+}
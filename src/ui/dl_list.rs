@@ -1,6 +1,11 @@
 use crate::ui::card;
 use gtk::{prelude::*, *};
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+thread_local! {
+  static LIST: RefCell<Option<Rc<RefCell<gtk::Box>>>> = const { RefCell::new(None) };
+  static SCAN: RefCell<Option<(Button, Label)>> = const { RefCell::new(None) };
+}
 
 pub fn render() -> Rc<RefCell<gtk::Box>> {
   let cont = gtk::Box::new(Orientation::Vertical, 12);
@@ -10,28 +15,114 @@ pub fn render() -> Rc<RefCell<gtk::Box>> {
   cont.set_margin_start(96);
   cont.set_margin_end(96);
 
-  let active = crate::DL_JOBS.active_count();
-  rc!(cont, active);
-  gtk::idle_add(clone!(cont => move || {
-      let active_now = crate::DL_JOBS.active_count();
-      if active_now != *active.borrow() {
-          *active.borrow_mut() = active_now;
-
-          let cont = cont.borrow();
-          for ch in cont.get_children() {
-              cont.remove(&ch);
-          }
-
-          let dl_list = {
-              crate::DOWNLOADS.lock().unwrap().clone()
-          };
-          for (_, dl) in dl_list {
-              cont.add(&*card::render(dl.track).borrow());
-          }
-          cont.show_all();
-      }
-      glib::Continue(true)
-  }));
-  cont.borrow().show_all();
+  let header = gtk::Box::new(Orientation::Horizontal, 12);
+  let status = Label::new(None);
+  if let Some(c) = status.get_style_context() {
+    c.add_class("dim-label")
+  }
+  status.set_hexpand(true);
+  status.set_halign(Align::Start);
+  let scan_bt = Button::new_with_label("Scan a music folder…");
+  scan_bt.set_tooltip_text("Tracks that are already in this folder won't be downloaded again");
+  header.pack_start(&status, true, true, 0);
+  header.pack_end(&scan_bt, false, false, 0);
+  scan_bt.connect_clicked(move |bt| {
+    if let Some(dir) = choose_folder() {
+      scan(dir, bt, &status);
+    }
+  });
+  cont.add(&header);
+
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&list);
+
+  rc!(list);
+  LIST.with(|l| *l.borrow_mut() = Some(list));
+  update();
+  rc!(cont);
   cont
 }
+
+fn choose_folder() -> Option<PathBuf> {
+  let dialog = FileChooserDialog::new(
+    Some("Choose your music folder"),
+    None::<&Window>,
+    FileChooserAction::SelectFolder,
+  );
+  dialog.add_buttons(&[
+    ("Cancel", ResponseType::Cancel.into()),
+    ("Scan", ResponseType::Accept.into()),
+  ]);
+  let answer = dialog.run();
+  let dir = dialog.get_filename();
+  dialog.destroy();
+
+  if ResponseType::from(answer) == ResponseType::Accept {
+    dir
+  } else {
+    None
+  }
+}
+
+/// Indexes a folder in the background, and shows how it goes in `status`.
+fn scan(dir: PathBuf, bt: &Button, status: &Label) {
+  bt.set_sensitive(false);
+  status.set_text("Scanning…");
+  SCAN.with(|s| *s.borrow_mut() = Some((bt.clone(), status.clone())));
+  std::thread::spawn(move || {
+    let res = crate::scan::scan(&dir, |done, total| {
+      if done % 20 == 0 {
+        show_scan(format!("Scanning… ({}/{})", done, total), false);
+      }
+    });
+    match res {
+      Ok(scanned) => show_scan(
+        format!("{} files found in your library", scanned.indexed + scanned.unchanged),
+        true,
+      ),
+      Err(e) => show_scan(format!("Couldn't scan this folder: {}", e), true),
+    }
+  });
+}
+
+/// Can be called from any thread.
+fn show_scan(text: String, done: bool) {
+  glib::idle_add(move || {
+    SCAN.with(|s| {
+      if let Some((ref bt, ref status)) = *s.borrow() {
+        status.set_text(&text);
+        bt.set_sensitive(done);
+      }
+    });
+    if done {
+      // Some downloads may now be known to be already there
+      update();
+    }
+    glib::Continue(false)
+  });
+}
+
+/// Asks for the list to be updated. Can be called from any thread.
+pub fn refresh() {
+  glib::idle_add(|| {
+    update();
+    glib::Continue(false)
+  });
+}
+
+fn update() {
+  LIST.with(|list| {
+    if let Some(ref cont) = *list.borrow() {
+      let cont = cont.borrow();
+      for ch in cont.get_children() {
+        cont.remove(&ch);
+      }
+
+      let dl_list = { crate::DOWNLOADS.lock().unwrap().clone() };
+      for (_, dl) in dl_list {
+        cont.add(&*card::render(dl.track).borrow());
+      }
+      cont.show_all();
+    }
+  });
+}
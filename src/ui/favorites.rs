@@ -0,0 +1,168 @@
+use crate::{
+  api::{self, execute},
+  ui::{card, card::CardModel, title},
+};
+use gtk::*;
+use std::{
+  cell::RefCell,
+  collections::HashSet,
+  rc::Rc,
+  sync::Mutex,
+};
+
+lazy_static::lazy_static! {
+  /// IDs of the tracks the user favorited
+  static ref FAVORITES: Mutex<HashSet<i32>> = Mutex::new(HashSet::new());
+}
+
+pub fn is_favorite(track_id: i32) -> bool {
+  FAVORITES.lock().unwrap().contains(&track_id)
+}
+
+/// Adds or removes a track from the favorites of the user. `on_error` is called
+/// with the reason if the instance didn't do it.
+pub fn set_favorite<F: FnOnce(String) + 'static>(track_id: i32, favorite: bool, on_error: F) {
+  let req = if favorite {
+    client!().api_post("/favorites/tracks/")
+  } else {
+    client!().api_post("/favorites/tracks/remove/")
+  };
+  wait!(execute(req.json(&api::TrackRef { track: track_id })) => |res| {
+    match res {
+      Ok(ref r) if r.status().is_success() => {
+        let mut favs = FAVORITES.lock().unwrap();
+        if favorite {
+          favs.insert(track_id);
+        } else {
+          favs.remove(&track_id);
+        }
+      }
+      Ok(r) => on_error(format!("The instance answered {}", r.status())),
+      Err(e) => on_error(e.to_string()),
+    }
+  });
+}
+
+pub fn render() -> gtk::Box {
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+  cont.set_valign(Align::Start);
+  cont.set_margin_top(48);
+  cont.set_margin_bottom(48);
+  cont.set_margin_start(96);
+  cont.set_margin_end(96);
+
+  let header = gtk::Box::new(Orientation::Horizontal, 12);
+  let title = title("Favorites");
+  title.set_hexpand(true);
+  title.set_halign(Align::Start);
+  header.add(&title);
+
+  let dl_all = Button::new_with_label("Download all favorites");
+  if let Some(c) = dl_all.get_style_context() {
+    c.add_class("suggested-action")
+  }
+  dl_all.connect_clicked(|bt| {
+    bt.set_sensitive(false);
+    bt.set_label("Looking for new favorites…");
+    let bt = bt.clone();
+    wait!(api::background(missing_favorites) => |const new| {
+      let new = match new {
+        Ok(new) => new,
+        Err(e) => {
+          bt.set_label("Download all favorites");
+          bt.set_tooltip_text(format!("Couldn't list your favorites. {}", e).as_str());
+          bt.set_sensitive(true);
+          return;
+        }
+      };
+      bt.set_tooltip_text(None);
+      if new.is_empty() {
+        bt.set_label("All your favorites are downloaded");
+      } else {
+        bt.set_label(&format!("{} new tracks to download", new.len()));
+        crate::enqueue(new);
+      }
+      bt.set_sensitive(true);
+    });
+  });
+  header.add(&dl_all);
+  cont.add(&header);
+
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&list);
+
+  let more_bt = Button::new_with_label("Load more");
+  more_bt.set_halign(Align::Center);
+  more_bt.set_no_show_all(true);
+  cont.add(&more_bt);
+
+  let next = Rc::new(RefCell::new(None));
+  load_page(&list, &more_bt, next.clone());
+  more_bt.connect_clicked(clone!(list, next => move |bt| {
+      load_page(&list, bt, next.clone());
+  }));
+
+  wait!(execute(client!().api_get("/favorites/tracks/all/").uncached()) => |res| {
+    if let Ok(ids) = res.and_then(|r| r.json::<api::FavoriteIds>()) {
+      let mut favs = FAVORITES.lock().unwrap();
+      favs.extend(ids.results.into_iter().map(|f| f.track));
+    }
+  });
+
+  cont.show_all();
+  cont
+}
+
+/// Loads the next page of favorites. `next` is the URL of this page, or `None`
+/// for the first one, and it is updated once the page is loaded.
+fn load_page(list: &gtk::Box, more_bt: &Button, next: Rc<RefCell<Option<String>>>) {
+  let req = match *next.borrow() {
+    Some(ref url) => client!().get(url),
+    None => client!().api_get("/favorites/tracks/").query(&[("ordering", "-creation_date")]),
+  };
+  more_bt.set_sensitive(false);
+
+  let widget = list.clone();
+  clone!(list, more_bt);
+  wait!(for widget, execute(req.uncached()) => |res| {
+    match res.and_then(|r| r.json::<api::Page<api::Favorite>>()) {
+      Ok(page) => {
+        if page.results.is_empty() && next.borrow().is_none() {
+          list.add(&Label::new("You don't have any favorite yet. Click on the heart of a song to add it here."));
+        }
+        for fav in page.results {
+          FAVORITES.lock().unwrap().insert(fav.track.id);
+          list.add(&*card::render(fav.track).borrow());
+        }
+        more_bt.set_visible(page.next.is_some());
+        more_bt.set_sensitive(true);
+        *next.borrow_mut() = page.next;
+      }
+      Err(e) => {
+        list.add(&Label::new(format!("Couldn't load your favorites. {}", e).as_ref()));
+        more_bt.set_sensitive(true);
+      }
+    }
+    list.show_all();
+  });
+}
+
+/// Lists every favorite, going through all the pages, and keeps the ones that
+/// are not on disk yet.
+///
+/// This is blocking.
+fn missing_favorites() -> Result<Vec<crate::Download>, api::Error> {
+  let client = client!().clone();
+  let favs: Vec<api::Favorite> = client.all_pages(
+    client
+      .api_get("/favorites/tracks/")
+      .query(&[("page_size", "100")]),
+  )?;
+  Ok(
+    favs
+      .into_iter()
+      .flat_map(|fav| fav.track.downloads())
+      .filter(|dl| !crate::on_disk(dl))
+      .collect(),
+  )
+}
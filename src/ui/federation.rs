@@ -0,0 +1,248 @@
+//! Libraries of other users, that can be followed to access their content,
+//! even if they are on another instance.
+
+use crate::{
+  api::{self, background, execute, send},
+  ui::{card, title},
+};
+use gtk::*;
+
+pub fn render() -> gtk::Box {
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+
+  let follows_title = title("Followed libraries");
+  follows_title.set_halign(Align::Start);
+  cont.add(&follows_title);
+
+  let form = gtk::Box::new(Orientation::Horizontal, 12);
+  let url = Entry::new();
+  url.set_placeholder_text("Library URL, like https://instance.tld/federation/music/libraries/…");
+  url.set_hexpand(true);
+  form.add(&url);
+  let follow_bt = Button::new_with_label("Follow");
+  form.add(&follow_bt);
+  cont.add(&form);
+
+  let status = Label::new("");
+  status.set_halign(Align::Start);
+  status.set_line_wrap(true);
+  cont.add(&status);
+
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&list);
+  load_follows(&list);
+
+  follow_bt.connect_clicked(clone!(url, list => move |bt| {
+      let fid = url.get_text().unwrap_or_default().trim().to_string();
+      if fid.is_empty() {
+          return;
+      }
+      bt.set_sensitive(false);
+      status.set_text("Looking for this library…");
+      clone!(bt, url, status, list);
+      wait!(background(move || follow(fid)) => |const res| {
+          match res {
+              Ok(follow) => {
+                  url.set_text("");
+                  status.set_text(&format!(
+                      "You asked to follow {}. You will be able to browse it once its owner approves it.",
+                      follow.target.name
+                  ));
+                  load_follows(&list);
+              }
+              Err(e) => status.set_text(&format!("Couldn't follow this library. {}", e)),
+          }
+          bt.set_sensitive(true);
+      });
+  }));
+  url.connect_activate(move |_| follow_bt.clicked());
+
+  cont
+}
+
+fn load_follows(list: &gtk::Box) {
+  for ch in list.get_children() {
+    list.remove(&ch);
+  }
+
+  let widget = list.clone();
+  let list = list.clone();
+  let req = client!()
+    .api_get("/federation/follows/library/")
+    .query(&[("page_size", "100")])
+    .uncached();
+  wait!(for widget, execute(req) => |res| {
+    match res.and_then(|r| r.json::<api::Page<api::LibraryFollow>>()) {
+      Ok(page) => {
+        if page.results.is_empty() {
+          list.add(&Label::new("You don't follow any library yet. Paste the URL of a library above to follow it."));
+        }
+        for follow in page.results {
+          list.add(&follow_row(follow, &list));
+        }
+      }
+      Err(e) => {
+        list.add(&Label::new(format!("Couldn't load the libraries you follow. {}", e).as_ref()));
+      }
+    }
+    list.show_all();
+  });
+}
+
+fn follow_row(follow: api::LibraryFollow, list: &gtk::Box) -> Grid {
+  let row = Grid::new();
+  row.set_column_spacing(12);
+
+  let name = Label::new(follow.target.name.as_ref());
+  if let Some(c) = name.get_style_context() {
+    c.add_class("h3")
+  }
+  name.set_hexpand(true);
+  name.set_halign(Align::Start);
+  row.attach(&name, 0, 0, 1, 1);
+
+  let details = Label::new(
+    format!(
+      "By {}, {} tracks — {}",
+      follow.target.actor.full_username,
+      follow.target.uploads_count,
+      match follow.approved {
+        None => "Waiting for approval",
+        Some(true) => "Approved",
+        Some(false) => "Refused",
+      }
+    )
+    .as_ref(),
+  );
+  if let Some(c) = details.get_style_context() {
+    c.add_class("dim-label")
+  }
+  details.set_halign(Align::Start);
+  details.set_line_wrap(true);
+  row.attach(&details, 0, 1, 1, 1);
+
+  if follow.approved == Some(true) {
+    let browse_bt = Button::new_with_label("Browse");
+    browse_bt.set_valign(Align::Center);
+    browse_bt.connect_clicked(clone!(follow => move |_| {
+        browse(follow.target.clone());
+    }));
+    row.attach(&browse_bt, 1, 0, 1, 2);
+
+    let scan_bt = Button::new_with_label("Check for new content");
+    scan_bt.set_valign(Align::Center);
+    let uuid = follow.target.uuid.clone();
+    scan_bt.connect_clicked(clone!(details => move |bt| {
+        let req = client!().api_post(format!("/federation/libraries/{}/scan/", uuid));
+        bt.set_sensitive(false);
+        clone!(details);
+        wait!(execute(req) => |res| {
+            if res.map(|r| r.status().is_success()).unwrap_or(false) {
+                details.set_text("Your instance is fetching the new content of this library.");
+            } else {
+                details.set_text("Couldn't check for new content.");
+            }
+        });
+    }));
+    row.attach(&scan_bt, 2, 0, 1, 2);
+  }
+
+  let unfollow_bt = Button::new_with_label("Unfollow");
+  unfollow_bt.set_valign(Align::Center);
+  if let Some(c) = unfollow_bt.get_style_context() {
+    c.add_class("destructive-action")
+  }
+  let uuid = follow.uuid;
+  clone!(list);
+  unfollow_bt.connect_clicked(move |_| {
+    let req = client!().api_delete(format!("/federation/follows/library/{}/", uuid));
+    clone!(list);
+    wait!(execute(req) => |res| {
+        if res.map(|r| r.status().is_success()).unwrap_or(false) {
+            load_follows(&list);
+        }
+    });
+  });
+  row.attach(&unfollow_bt, 3, 0, 1, 2);
+
+  row
+}
+
+/// Fetches a library from its ActivityPub ID, and asks to follow it.
+///
+/// This is blocking.
+fn follow(fid: String) -> Result<api::LibraryFollow, String> {
+  let client = client!().clone();
+  let res = send(client.api_post("/federation/libraries/fetch/").json(&api::FetchRequest { fid }))
+    .map_err(|e| e.to_string())?;
+  if !res.status().is_success() {
+    return Err(String::from_utf8_lossy(res.body()).to_string());
+  }
+  let library = res
+    .json::<api::Page<api::RemoteLibrary>>()
+    .map_err(|e| e.to_string())?
+    .results
+    .into_iter()
+    .next()
+    .ok_or_else(|| "There is no library at this address.".to_string())?;
+
+  let res = send(
+    client
+      .api_post("/federation/follows/library/")
+      .json(&api::FollowRequest {
+        target: library.uuid,
+      }),
+  )
+  .map_err(|e| e.to_string())?;
+  if !res.status().is_success() {
+    return Err(String::from_utf8_lossy(res.body()).to_string());
+  }
+  res.json().map_err(|e| e.to_string())
+}
+
+/// Shows the albums of a library, to download them.
+fn browse(library: api::RemoteLibrary) {
+  let dialog = Dialog::new_with_buttons(
+    Some(library.name.as_str()),
+    None::<&Window>,
+    DialogFlags::MODAL,
+    &[("Close", ResponseType::Close.into())],
+  );
+  dialog.set_default_size(720, 540);
+
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  list.set_margin_top(12);
+  list.set_margin_bottom(12);
+  list.set_margin_start(12);
+  list.set_margin_end(12);
+  let scrolled = ScrolledWindow::new(None, None);
+  scrolled.set_vexpand(true);
+  scrolled.add(&list);
+  dialog.get_content_area().add(&scrolled);
+
+  let widget = list.clone();
+  clone!(list);
+  let req = client!()
+    .api_get("/albums/")
+    .query(&[("library", library.uuid.as_str()), ("page_size", "100")]);
+  wait!(for widget, execute(req) => |res| {
+    match res.and_then(|r| r.json::<api::Page<api::Album>>()) {
+      Ok(page) => {
+        if page.results.is_empty() {
+          list.add(&Label::new("Your instance didn't fetch anything from this library yet. Check for new content, and come back later."));
+        }
+        for album in page.results {
+          list.add(&*card::render(album).borrow());
+        }
+      }
+      Err(e) => {
+        list.add(&Label::new(format!("Couldn't load this library. {}", e).as_ref()));
+      }
+    }
+    list.show_all();
+  });
+
+  dialog.show_all();
+  dialog.run();
+  dialog.destroy();
+}
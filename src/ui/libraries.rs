@@ -0,0 +1,241 @@
+use crate::{
+  api::{self, background, execute},
+  ui::{federation, title},
+  upload::{self, Upload, UploadStatus},
+};
+use gtk::*;
+use std::{cell::RefCell, collections::HashMap};
+
+thread_local! {
+  static UPLOAD_LIST: RefCell<Option<gtk::Box>> = const { RefCell::new(None) };
+  /// The progress bar and status of each upload, to update them in place
+  static ROWS: RefCell<HashMap<usize, (ProgressBar, Label)>> = RefCell::new(HashMap::new());
+}
+
+pub fn render() -> gtk::Box {
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+  cont.set_valign(Align::Start);
+  cont.set_margin_top(48);
+  cont.set_margin_bottom(48);
+  cont.set_margin_start(96);
+  cont.set_margin_end(96);
+
+  let lib_title = title("Your libraries");
+  lib_title.set_halign(Align::Start);
+  cont.add(&lib_title);
+
+  let libraries = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&libraries);
+
+  cont.add(&federation::render());
+
+  let up_title = title("Uploads");
+  up_title.set_halign(Align::Start);
+  cont.add(&up_title);
+
+  let uploads = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&uploads);
+  UPLOAD_LIST.with(|list| *list.borrow_mut() = Some(uploads));
+  update_uploads();
+
+  let widget = libraries.clone();
+  let req = client!()
+    .api_get("/libraries/")
+    .query(&[("page_size", "100")])
+    .uncached();
+  wait!(for widget, execute(req) => |res| {
+    match res.and_then(|r| r.json::<api::Page<api::Library>>()) {
+      Ok(page) => {
+        if page.results.is_empty() {
+          libraries.add(&Label::new("You don't have any library yet. Create one on your instance to upload music."));
+        }
+        for library in page.results {
+          libraries.add(&library_row(library));
+        }
+      }
+      Err(e) => {
+        libraries.add(&Label::new(format!("Couldn't load your libraries. {}", e).as_ref()));
+      }
+    }
+    libraries.show_all();
+  });
+
+  cont.show_all();
+  cont
+}
+
+fn library_row(library: api::Library) -> Grid {
+  let row = Grid::new();
+  row.set_column_spacing(12);
+
+  let name = Label::new(library.name.as_ref());
+  if let Some(c) = name.get_style_context() {
+    c.add_class("h3")
+  }
+  name.set_hexpand(true);
+  name.set_halign(Align::Start);
+  row.attach(&name, 0, 0, 1, 1);
+
+  let details = Label::new(
+    format!(
+      "{} uploads, visible to {}",
+      library.uploads_count,
+      match library.privacy_level.as_ref() {
+        "me" => "you only",
+        "instance" => "this instance",
+        _ => "everyone",
+      }
+    )
+    .as_ref(),
+  );
+  if let Some(c) = details.get_style_context() {
+    c.add_class("dim-label")
+  }
+  details.set_halign(Align::Start);
+  row.attach(&details, 0, 1, 1, 1);
+
+  let files_bt = Button::new_with_label("Upload files…");
+  files_bt.set_valign(Align::Center);
+  let uuid = library.uuid.clone();
+  files_bt.connect_clicked(move |_| {
+    let files = choose(FileChooserAction::Open);
+    if !files.is_empty() {
+      upload::enqueue(files, &uuid);
+    }
+  });
+  row.attach(&files_bt, 1, 0, 1, 2);
+
+  let folder_bt = Button::new_with_label("Upload a folder…");
+  folder_bt.set_valign(Align::Center);
+  let uuid = library.uuid;
+  folder_bt.connect_clicked(move |_| {
+    if let Some(dir) = choose(FileChooserAction::SelectFolder).pop() {
+      let uuid = uuid.clone();
+      wait!(background(move || upload::audio_files(&dir)) => |const files| {
+        upload::enqueue(files, &uuid);
+      });
+    }
+  });
+  row.attach(&folder_bt, 2, 0, 1, 2);
+
+  row
+}
+
+fn choose(action: FileChooserAction) -> Vec<std::path::PathBuf> {
+  let title = if action == FileChooserAction::SelectFolder {
+    "Choose a folder to upload"
+  } else {
+    "Choose files to upload"
+  };
+  let dialog = FileChooserDialog::new(Some(title), None::<&Window>, action);
+  dialog.add_buttons(&[
+    ("Cancel", ResponseType::Cancel.into()),
+    ("Upload", ResponseType::Accept.into()),
+  ]);
+  if action == FileChooserAction::Open {
+    dialog.set_select_multiple(true);
+    let filter = FileFilter::new();
+    FileFilterExt::set_name(&filter, "Audio files");
+    filter.add_mime_type("audio/*");
+    dialog.add_filter(&filter);
+  }
+
+  let answer = dialog.run();
+  let files = dialog.get_filenames();
+  dialog.destroy();
+
+  if ResponseType::from(answer) == ResponseType::Accept {
+    files
+  } else {
+    vec![]
+  }
+}
+
+/// Shows the new list of uploads. Can be called from any thread.
+pub fn refresh_uploads() {
+  glib::idle_add(|| {
+    update_uploads();
+    glib::Continue(false)
+  });
+}
+
+/// Shows the new status of an upload. Can be called from any thread.
+pub fn update_upload(upload: Upload) {
+  glib::idle_add(move || {
+    let found = ROWS.with(|rows| match rows.borrow().get(&upload.id) {
+      Some((progress, status)) => {
+        show_status(&upload, progress, status);
+        true
+      }
+      None => false,
+    });
+    if !found {
+      update_uploads();
+    }
+    glib::Continue(false)
+  });
+}
+
+fn update_uploads() {
+  UPLOAD_LIST.with(|list| {
+    if let Some(ref cont) = *list.borrow() {
+      for ch in cont.get_children() {
+        cont.remove(&ch);
+      }
+
+      let uploads = upload::list();
+      if uploads.is_empty() {
+        cont.add(&Label::new("Files you upload will appear here."));
+      }
+      ROWS.with(|rows| {
+        let mut rows = rows.borrow_mut();
+        rows.clear();
+        for upload in uploads {
+          let row = Grid::new();
+          row.set_column_spacing(12);
+          let name = Label::new(
+            upload
+              .file
+              .file_name()
+              .map(|n| n.to_string_lossy().to_string())
+              .unwrap_or_default()
+              .as_ref(),
+          );
+          name.set_hexpand(true);
+          name.set_halign(Align::Start);
+          row.attach(&name, 0, 0, 1, 1);
+
+          let status = Label::new("");
+          status.set_halign(Align::Start);
+          status.set_line_wrap(true);
+          if let Some(c) = status.get_style_context() {
+            c.add_class("dim-label")
+          }
+          row.attach(&status, 0, 1, 1, 1);
+
+          let progress = ProgressBar::new();
+          progress.set_valign(Align::Center);
+          row.attach(&progress, 1, 0, 1, 2);
+
+          show_status(&upload, &progress, &status);
+          cont.add(&row);
+          rows.insert(upload.id, (progress, status));
+        }
+      });
+      cont.show_all();
+    }
+  });
+}
+
+fn show_status(upload: &Upload, progress: &ProgressBar, status: &Label) {
+  let (fraction, text) = match upload.status {
+    UploadStatus::Waiting => (0.0, "Waiting to upload".to_string()),
+    UploadStatus::Sending(f) => (f, "Uploading".to_string()),
+    UploadStatus::Pending => (1.0, "Uploaded, waiting for the instance to import it".to_string()),
+    UploadStatus::Finished => (1.0, "Imported".to_string()),
+    UploadStatus::Skipped => (1.0, "Skipped, this track is already in your library".to_string()),
+    UploadStatus::Errored(ref e) => (1.0, format!("Failed: {}", e)),
+  };
+  progress.set_fraction(fraction);
+  status.set_text(&text);
+}
@@ -1,6 +1,17 @@
-use crate::{api::*, ui::title, State};
+use crate::{
+  api::{
+    network::{self, NetworkSettings},
+    nodeinfo::{self, DiscoveryError, InstanceInfo},
+    *,
+  },
+  ui::title,
+  State,
+};
 use gtk::*;
-use std::{cell::RefCell, rc::Rc};
+use std::{
+  cell::{Cell, RefCell},
+  rc::Rc,
+};
 
 pub fn render(state: State) -> gtk::Box {
   let cont = gtk::Box::new(Orientation::Vertical, 24);
@@ -18,47 +29,50 @@ pub fn render(state: State) -> gtk::Box {
     c.add_class("suggested-action")
   }
   login_bt.set_margin_bottom(48);
-  let widgets = Rc::new(RefCell::new((instance, username, password)));
-  login_bt.connect_clicked(clone!(state, widgets => move |_| {
-		let mut api_ctx = crate::api::API.lock().unwrap();
-		let mut instance_url = widgets.borrow().0.get_text().unwrap().trim_end_matches('/').to_string();
-		if !(instance_url.starts_with("http://") || instance_url.starts_with("https://")) {
-			instance_url = format!("https://{}", instance_url)
-		}
-		*api_ctx = Some(RequestContext::new(
-			instance_url
-		));
+  let instance_info = Label::new(None);
+  instance_info.set_halign(Align::Start);
+  instance_info.set_line_wrap(true);
+  if let Some(c) = instance_info.get_style_context() {
+    c.add_class("dim-label")
+  }
 
+  let (network_expander, network) = network_settings();
+  let network = Rc::new(network);
+
+  let widgets = Rc::new(RefCell::new((instance, username, password)));
+  // Set when the login is retried after trusting a certificate, to only retry once
+  let retried = Rc::new(Cell::new(false));
+  login_bt.connect_clicked(clone!(state, widgets, instance_info, network, retried => move |bt| {
 		let state = state.clone();
-		wait!(execute(api_ctx.as_ref().unwrap().post("/api/v1/token/").json(&LoginData {
-			username: widgets.borrow().1.get_text().unwrap(),
-			password: widgets.borrow().2.get_text().unwrap(),
-		})) => |res| {
-			let res: Result<LoginInfo, _> = res.json();
-
-			match res {
-				Err(_) => crate::show_error(state.clone(), "Somehting went wrong, check your username and password, and the URL of your instance."),
-				Ok(res) => {
-					if let Some(ref mut client) = *crate::api::API.lock().unwrap() {
-						client.auth(res.token);
+		if let Err(e) = network.save() {
+			crate::show_error(state, &e.to_string());
+			return;
+		}
+		let bt = bt.clone();
+		let retrying = retried.replace(false);
+		clone!(widgets, instance_info, retried);
+		let url = widgets.borrow().0.get_text().unwrap_or_default();
+		wait!(check_instance(url) => |const info| {
+			match info {
+				Err(DiscoveryError::Tls(host, port, e)) => {
+					instance_info.set_text("");
+					if retrying {
+						crate::show_error(state.clone(), &DiscoveryError::Tls(host, port, e).to_string());
+						return;
 					}
-
-					let state = state.borrow();
-					state.error.set_revealed(false);
-					state.stack.add_titled(&crate::ui::main_page::render(
-						state.window.clone(),
-						&state.header,
-						&{
-							let s = StackSwitcher::new();
-							s.set_stack(&state.stack);
-							s
-						}
-					),
-					"main", "Search Music");
-					state.stack.set_visible_child_name("main");
-			        state.stack.add_titled(&*crate::ui::dl_list::render().borrow(), "downloads", "Downloads");
-					state.stack.remove(&state.stack.get_child_by_name("login").unwrap()); // To avoid having a "Login" tab in the header
-					state.stack.show_all();
+					clone!(bt, retried);
+					trust_certificate(state.clone(), host, port, e, move || {
+						retried.set(true);
+						bt.clicked();
+					});
+				},
+				Err(e) => {
+					instance_info.set_text("");
+					crate::show_error(state.clone(), &e.to_string());
+				},
+				Ok(info) => {
+					instance_info.set_text(&info.summary());
+					login(state.clone(), &info, widgets.borrow().1.get_text().unwrap_or_default(), widgets.borrow().2.get_text().unwrap_or_default());
 				}
 			}
 		});
@@ -67,12 +81,34 @@ pub fn render(state: State) -> gtk::Box {
   {
     let (ref instance, ref username, ref password) = *widgets.borrow();
     cont.add(&title);
-    cont.add(&instance.render());
+    let instance_box = instance.render();
+    instance_box.add(&instance_info);
+    cont.add(&instance_box);
     cont.add(&username.render());
     cont.add(&password.render());
+    cont.add(&network_expander);
     cont.add(&login_bt);
   }
 
+  // Give early feedback about the instance, before the user types their password
+  widgets
+    .borrow()
+    .0
+    .entry
+    .connect_focus_out_event(clone!(instance_info => move |entry, _| {
+        let url = entry.get_text().unwrap_or_default();
+        if !url.trim().is_empty() {
+            clone!(instance_info);
+            wait!(check_instance(url) => |const info| {
+                match info {
+                    Ok(info) => instance_info.set_text(&info.summary()),
+                    Err(e) => instance_info.set_text(&e.to_string()),
+                }
+            });
+        }
+        Inhibit(false)
+    }));
+
   widgets
     .borrow()
     .0
@@ -95,24 +131,165 @@ pub fn render(state: State) -> gtk::Box {
   cont
 }
 
+struct NetworkWidgets {
+  proxy: Input<'static>,
+  ca_bundle: FileChooserButton,
+  client_cert: FileChooserButton,
+  client_cert_password: Input<'static>,
+}
+
+impl NetworkWidgets {
+  fn save(&self) -> Result<(), network::SettingsError> {
+    let mut settings = NetworkSettings::load();
+    settings.proxy = self.proxy.get_text().filter(|p| !p.trim().is_empty());
+    settings.ca_bundle = self.ca_bundle.get_filename();
+    settings.client_cert = self.client_cert.get_filename();
+    settings.client_cert_password = self.client_cert_password.get_text().unwrap_or_default();
+    settings.save()
+  }
+}
+
+fn network_settings() -> (Expander, NetworkWidgets) {
+  let settings = NetworkSettings::load();
+  let expander = Expander::new("Network settings");
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+  cont.set_margin_top(12);
+
+  let proxy = Input::new("Proxy").with_placeholder("socks5://localhost:1080");
+  if let Some(ref p) = settings.proxy {
+    proxy.entry.set_text(p);
+  }
+
+  let file_input = |label: &str, path: &Option<std::path::PathBuf>| {
+    let chooser = FileChooserButton::new(label, FileChooserAction::Open);
+    if let Some(ref path) = *path {
+      chooser.set_filename(path);
+    }
+    let lbl = Label::new(label);
+    lbl.set_halign(Align::Start);
+    let b = gtk::Box::new(Orientation::Vertical, 6);
+    b.add(&lbl);
+    b.add(&chooser);
+    (b, chooser)
+  };
+  let (ca_box, ca_bundle) = file_input("Additional certificate authorities (PEM)", &settings.ca_bundle);
+  let (cert_box, client_cert) = file_input("Client certificate (PKCS#12)", &settings.client_cert);
+  let client_cert_password = Input::new_password("Client certificate password");
+  client_cert_password.entry.set_text(&settings.client_cert_password);
+
+  cont.add(&proxy.render());
+  cont.add(&ca_box);
+  cont.add(&cert_box);
+  cont.add(&client_cert_password.render());
+  expander.add(&cont);
+
+  (
+    expander,
+    NetworkWidgets {
+      proxy,
+      ca_bundle,
+      client_cert,
+      client_cert_password,
+    },
+  )
+}
+
+/// Asks the user if they want to trust the certificate of their instance, when
+/// it is not signed by a known authority. If they do, its fingerprint is pinned
+/// and `retry` is called: it should only be done once, as the certificate may
+/// still be rejected (for instance if it is for another host).
+fn trust_certificate<F: Fn() + 'static>(state: State, host: String, port: u16, err: String, retry: F) {
+  let fetch_host = host.clone();
+  wait!(background(move || network::peer_certificate(&fetch_host, port)) => |const cert| {
+    let der = match cert {
+      Ok(der) => der,
+      Err(_) => {
+        crate::show_error(state.clone(), &DiscoveryError::Tls(host.clone(), port, err.clone()).to_string());
+        return;
+      }
+    };
+
+    let dialog = MessageDialog::new(
+      Some(&*state.borrow().window.borrow()),
+      DialogFlags::MODAL,
+      MessageType::Warning,
+      ButtonsType::YesNo,
+      &format!(
+        "The certificate of {} couldn't be verified ({}).\n\nIf you know this instance uses a self-signed certificate, check that its SHA-256 fingerprint is:\n\n{}\n\nDo you want to trust it?",
+        host, err, network::fingerprint(&der)
+      ),
+    );
+    let answer = dialog.run();
+    dialog.destroy();
+
+    if ResponseType::from(answer) == ResponseType::Yes {
+      let mut settings = NetworkSettings::load();
+      match settings.pin(&host, &der) {
+        Ok(()) => retry(),
+        Err(e) => crate::show_error(state.clone(), &format!("Couldn't save the certificate: {}", e)),
+      }
+    }
+  });
+}
+
+fn check_instance(url: String) -> Call<Result<InstanceInfo, DiscoveryError>> {
+  background(move || nodeinfo::discover(&url))
+}
+
+fn login(state: State, instance: &InstanceInfo, username: String, password: String) {
+  let mut api_ctx = crate::api::API.lock().unwrap();
+  let mut ctx = match RequestContext::new(instance.url.clone()) {
+    Ok(ctx) => ctx,
+    Err(e) => {
+      crate::show_error(state, &e.to_string());
+      return;
+    }
+  };
+  ctx.negotiate(ApiVersion::from_version(&instance.version));
+  *api_ctx = Some(ctx);
+
+  wait!(execute(api_ctx.as_ref().unwrap().post("/api/v1/token/").json(&LoginData {
+    username: username.clone(),
+    password,
+  })) => |res| {
+    let res: Result<LoginInfo, _> = res.and_then(|r| r.json());
+
+    match res {
+      Err(_) => crate::show_error(state.clone(), "Something went wrong, check your username and password."),
+      Ok(res) => {
+        if let Some(ref mut client) = *crate::api::API.lock().unwrap() {
+          client.auth(res.token);
+          client.set_username(username);
+        }
+
+        let state = state.borrow();
+        state.error.set_revealed(false);
+        crate::add_main_pages(&state);
+        state.stack.remove(&state.stack.get_child_by_name("login").unwrap()); // To avoid having a "Login" tab in the header
+        state.stack.show_all();
+      }
+    }
+  });
+}
+
 struct Input<'a> {
   label: &'a str,
   entry: gtk::Entry,
 }
 
 impl<'a> Input<'a> {
-  fn new(text: &'a str) -> Input {
+  fn new(text: &'a str) -> Input<'a> {
     let entry = gtk::Entry::new();
     Input { label: text, entry }
   }
 
-  fn new_password(text: &'a str) -> Input {
+  fn new_password(text: &'a str) -> Input<'a> {
     let input = Input::new(text);
     input.entry.set_visibility(false);
     input
   }
 
-  fn with_placeholder(self, ph: &'a str) -> Input {
+  fn with_placeholder(self, ph: &'a str) -> Input<'a> {
     self.entry.set_placeholder_text(ph);
     self
   }
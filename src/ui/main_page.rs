@@ -64,44 +64,100 @@ pub fn render(
       crate::logout(window.clone());
   }));
   header.pack_end(&logout_bt);
+
+  let prefs_bt = Button::new_from_icon_name("preferences-system-symbolic", IconSize::LargeToolbar.into());
+  prefs_bt.set_tooltip_text("Preferences");
+  prefs_bt.connect_clicked(clone!(window => move |_| {
+      super::preferences::show(&window.borrow());
+  }));
+  header.pack_end(&prefs_bt);
+
+  let offline_lbl = Label::new("Offline");
+  if let Some(c) = offline_lbl.get_style_context() {
+    c.add_class("dim-label")
+  }
+  offline_lbl.set_tooltip_text(
+    "Your instance can't be reached. You can still play your downloads, and new downloads will start once you are back online.",
+  );
+  offline_lbl.set_no_show_all(true);
+  header.pack_end(&offline_lbl);
   header.show_all();
 
   let search = SearchEntry::new();
   search.set_placeholder_text("Search");
   cont.add(&search);
 
+  crate::offline::subscribe(clone!(offline_lbl, search => move |offline| {
+      offline_lbl.set_visible(offline);
+      search.set_sensitive(!offline);
+      if offline {
+          search.set_placeholder_text("Search is not available offline");
+      } else {
+          search.set_placeholder_text("Search");
+      }
+  }));
+
   let results = gtk::Box::new(Orientation::Vertical, 12);
   results.set_valign(Align::Start);
   cont.add(&results);
 
   rc!(avatar, results);
   clone!(avatar, results, avatar_path);
-  wait!(execute(client!().get("/api/v1/users/users/me")) => |res| {
-      let res: Result<api::UserInfo, _> = res.json();
+  wait!(execute(client!().api_get("/users/users/me")) => |res| {
+      // Only these mean our session is not valid anymore
+      if let Ok(ref r) = res {
+          if r.status().as_u16() == 401 || r.status().as_u16() == 403 {
+              crate::logout(window.clone());
+              return;
+          }
+      }
+      let res: Result<api::UserInfo, _> = res.and_then(|r| r.json());
       match res {
           Ok(res) => {
               avatar.borrow().set_tooltip_text(format!("Connected as {}.", res.username).as_ref());
+              // Sessions saved by older versions don't know it
+              if let Some(ref mut client) = *api::API.lock().unwrap() {
+                  client.set_username(res.username.clone());
+              }
 
               clone!(avatar_path, avatar);
               wait!(execute(client!().get(&res.avatar.medium_square_crop.unwrap_or_default())) => |avatar_dl| {
-                  fs::create_dir_all(avatar_path.parent().unwrap()).unwrap();
-                  let mut avatar_file = fs::File::create(avatar_path.clone()).unwrap();
-                  avatar_dl.copy_to(&mut avatar_file).unwrap();
-                  avatar.borrow().queue_draw();
+                  if let Ok(avatar_dl) = avatar_dl {
+                      fs::create_dir_all(avatar_path.parent().unwrap()).unwrap();
+                      let mut avatar_file = fs::File::create(avatar_path.clone()).unwrap();
+                      avatar_dl.copy_to(&mut avatar_file).unwrap();
+                      avatar.borrow().queue_draw();
+                  }
               });
           },
-          Err(_) => {
-              crate::logout(window.clone());
+          Err(ref e) if crate::offline::is_network_error(e) => {
+              crate::offline::went_offline();
+          }
+          Err(e) => {
+              avatar.borrow().set_tooltip_text(format!("Couldn't load your profile. {}", e).as_ref());
           }
       }
   });
 
   search.connect_activate(move |s| {
     let results = results.clone();
-    wait!(execute(client!().get("/api/v1/search").query(&api::SearchQuery {
+    wait!(execute(client!().api_get("/search").query(&api::SearchQuery {
         query: s.get_text().unwrap_or_default()
     })) => |res| {
-        update_results(res.json().unwrap(), &results.borrow());
+        match res.and_then(|r| r.json()) {
+            Ok(res) => update_results(res, &results.borrow()),
+            Err(e) => {
+                if crate::offline::is_network_error(&e) {
+                    crate::offline::went_offline();
+                }
+                let results = results.borrow();
+                for ch in results.get_children() {
+                    results.remove(&ch);
+                }
+                results.add(&Label::new(format!("The search failed. {}", e).as_ref()));
+                results.show_all();
+            }
+        }
     });
   });
 
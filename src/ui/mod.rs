@@ -1,10 +1,27 @@
+use crate::api::Call;
 use gtk::prelude::*;
+use std::{
+  any::Any,
+  cell::RefCell,
+  collections::HashMap,
+  rc::Rc,
+  sync::atomic::{AtomicUsize, Ordering},
+};
 
 pub mod card;
+pub mod channels;
 pub mod dl_list;
+pub mod favorites;
+pub mod federation;
+pub mod libraries;
 pub mod login_page;
 pub mod main_page;
 pub mod network_image;
+pub mod playlist_editor;
+pub mod playlists;
+pub mod preferences;
+pub mod radios;
+pub mod tags;
 
 fn title(text: &str) -> gtk::Label {
   let lbl = gtk::Label::new(text);
@@ -13,3 +30,72 @@ fn title(text: &str) -> gtk::Label {
   }
   lbl
 }
+
+type Callback = Box<dyn FnOnce(Box<dyn Any>)>;
+
+static NEXT_CALLBACK: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+  /// Callbacks waiting for the result of a `Call`, they can only live on the main thread
+  static CALLBACKS: RefCell<HashMap<usize, Callback>> = RefCell::new(HashMap::new());
+}
+
+/// Calls `f` on the main thread when the result of `call` is available.
+///
+/// The main loop is only woken up once, when the result arrives.
+pub fn on_result<T, F>(call: Call<T>, f: F) -> usize
+where
+  T: Send + 'static,
+  F: FnOnce(T) + 'static,
+{
+  let id = NEXT_CALLBACK.fetch_add(1, Ordering::SeqCst);
+  CALLBACKS.with(|cbs| {
+    cbs.borrow_mut().insert(
+      id,
+      Box::new(move |res: Box<dyn Any>| {
+        if let Ok(res) = res.downcast::<T>() {
+          f(*res);
+        }
+      }),
+    )
+  });
+
+  call.then(move |res| {
+    let mut res = Some(res);
+    glib::idle_add(move || {
+      // The callback may register new ones, so don't keep CALLBACKS borrowed
+      let cb = CALLBACKS.with(|cbs| cbs.borrow_mut().remove(&id));
+      if let (Some(cb), Some(res)) = (cb, res.take()) {
+        cb(Box::new(res));
+      }
+      glib::Continue(false)
+    });
+  });
+  id
+}
+
+/// Like `on_result`, but the call is cancelled if `widget` is destroyed first.
+pub fn on_result_for<W, T, F>(widget: &W, call: Call<T>, f: F)
+where
+  W: IsA<gtk::Widget> + IsA<glib::Object> + WidgetExt,
+  T: Send + 'static,
+  F: FnOnce(T) + 'static,
+{
+  let cancel = call.cancel_handle();
+  // Disconnected once the result is there, not to pile up on widgets that stay
+  let handler = Rc::new(RefCell::new(None));
+  let id = on_result(call, {
+    let widget = widget.upcast_ref::<gtk::Widget>().clone();
+    let handler = handler.clone();
+    move |res| {
+      if let Some(handler) = handler.borrow_mut().take() {
+        widget.disconnect(handler);
+      }
+      f(res)
+    }
+  });
+  *handler.borrow_mut() = Some(widget.connect_destroy(move |_| {
+    cancel.cancel();
+    CALLBACKS.with(|cbs| cbs.borrow_mut().remove(&id));
+  }));
+}
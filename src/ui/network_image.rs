@@ -11,7 +11,11 @@ impl NetworkImage {
     let image = Image::new_from_icon_name("image-loading", 4);
     rc!(image);
 
-    let dest_file = url.split("/media/").last().unwrap().replace('/', "-");
+    let dest_file = url
+      .split("/media/")
+      .last()
+      .unwrap()
+      .replace(|c: char| !c.is_alphanumeric() && c != '.', "-");
     let dest = dirs::cache_dir()
       .unwrap()
       .join(env!("CARGO_PKG_NAME"))
@@ -21,8 +25,13 @@ impl NetworkImage {
       let pb = gdk_pixbuf::Pixbuf::new_from_file_at_scale(dest, 64, 64, true).unwrap();
       image.borrow().set_from_pixbuf(&pb);
     } else {
+      let widget = image.borrow().clone();
       clone!(image);
-      wait!(execute(client!().get(&url)) => |res| {
+      wait!(for widget, execute(client!().get(&url)) => |res| {
+          let res = match res {
+              Ok(res) => res,
+              Err(_) => return,
+          };
           fs::create_dir_all(dest.parent().unwrap()).unwrap();
           let mut file = fs::File::create(dest.clone()).unwrap();
           res.copy_to(&mut file).unwrap();
@@ -0,0 +1,343 @@
+//! Creating playlists and changing their content.
+
+use crate::{
+  api::{self, background, send},
+  ui::playlists,
+};
+use gtk::*;
+
+const PRIVACY_LEVELS: [(&str, &str); 3] = [
+  ("me", "Only me"),
+  ("instance", "Users of this instance"),
+  ("everyone", "Everyone"),
+];
+
+/// A menu to add tracks to one of the playlists of the user.
+///
+/// `tracks` gives the IDs of the tracks to add. It is called on another thread,
+/// so it can make requests.
+pub fn add_menu<F>(tracks: F) -> Menu
+where
+  F: Fn() -> Vec<i32> + Clone + Send + 'static,
+{
+  let menu = Menu::new();
+  // The list of playlists is only fetched when needed, not for every card
+  menu.connect_show(move |menu| {
+    for ch in menu.get_children() {
+      menu.remove(&ch);
+    }
+
+    let new = MenuItem::new_with_label("Add to a new playlist…");
+    new.connect_activate(clone!(tracks => move |_| {
+        if let Some(data) = playlist_dialog("New playlist", None) {
+            clone!(tracks);
+            wait!(background(move || create(data, tracks())) => |res| {
+                report(res, "Couldn't create the playlist");
+            });
+        }
+    }));
+    menu.append(&new);
+    menu.append(&SeparatorMenuItem::new());
+
+    let loading = MenuItem::new_with_label("Loading your playlists…");
+    loading.set_sensitive(false);
+    menu.append(&loading);
+    menu.show_all();
+
+    let widget = menu.clone();
+    let menu = menu.clone();
+    clone!(tracks);
+    wait!(for widget, background(playlists::own_playlists) => |res| {
+        menu.remove(&loading);
+        for playlist in res.unwrap_or_default() {
+            let item = MenuItem::new_with_label(&playlist.name);
+            item.connect_activate(clone!(tracks => move |_| {
+                clone!(tracks);
+                let id = playlist.id;
+                wait!(background(move || add(id, tracks())) => |res| {
+                    report(res, "Couldn't add to the playlist");
+                });
+            }));
+            menu.append(&item);
+        }
+        menu.show_all();
+    });
+  });
+  menu
+}
+
+/// A menu to manage a playlist the user owns.
+pub fn edit_menu(playlist: api::Playlist) -> Menu {
+  let menu = Menu::new();
+
+  let edit = MenuItem::new_with_label("Edit…");
+  edit.connect_activate(clone!(playlist => move |_| {
+      edit_dialog(playlist.clone());
+  }));
+  menu.append(&edit);
+
+  let delete = MenuItem::new_with_label("Delete");
+  delete.connect_activate(move |_| {
+    let dialog = MessageDialog::new(
+      None::<&Window>,
+      DialogFlags::MODAL,
+      MessageType::Question,
+      ButtonsType::YesNo,
+      &format!("Delete the playlist \"{}\"? This can't be undone.", playlist.name),
+    );
+    let answer = dialog.run();
+    dialog.destroy();
+
+    if ResponseType::from(answer) == ResponseType::Yes {
+      let req = client!().api_delete(format!("/playlists/{}/", playlist.id));
+      wait!(background(move || checked(send(req))) => |res| {
+          report(res, "Couldn't delete the playlist");
+      });
+    }
+  });
+  menu.append(&delete);
+
+  menu.show_all();
+  menu
+}
+
+/// Asks for the name and visibility of a playlist.
+fn playlist_dialog(title: &str, current: Option<&api::Playlist>) -> Option<api::PlaylistData> {
+  let dialog = Dialog::new_with_buttons(
+    Some(title),
+    None::<&Window>,
+    DialogFlags::MODAL,
+    &[
+      ("Cancel", ResponseType::Cancel.into()),
+      ("Save", ResponseType::Ok.into()),
+    ],
+  );
+  dialog.set_default_response(ResponseType::Ok.into());
+
+  let content = dialog.get_content_area();
+  content.set_spacing(12);
+  content.set_margin_top(12);
+  content.set_margin_bottom(12);
+  content.set_margin_start(12);
+  content.set_margin_end(12);
+
+  let name = Entry::new();
+  name.set_placeholder_text("Name");
+  name.set_activates_default(true);
+  let privacy = ComboBoxText::new();
+  for (id, label) in PRIVACY_LEVELS.iter() {
+    privacy.append(Some(*id), label);
+  }
+  match current {
+    Some(playlist) => {
+      name.set_text(&playlist.name);
+      privacy.set_active_id(Some(playlist.privacy_level.as_ref()));
+    }
+    None => {
+      privacy.set_active_id(Some("me"));
+    }
+  }
+  content.add(&name);
+  content.add(&Label::new("Who can see this playlist?"));
+  content.add(&privacy);
+  dialog.show_all();
+
+  let answer = dialog.run();
+  let data = api::PlaylistData {
+    name: name.get_text().unwrap_or_default().trim().to_string(),
+    privacy_level: privacy.get_active_id().unwrap_or_else(|| "me".to_string()),
+  };
+  dialog.destroy();
+
+  if ResponseType::from(answer) == ResponseType::Ok && !data.name.is_empty() {
+    Some(data)
+  } else {
+    None
+  }
+}
+
+/// Renames a playlist, and lets the user reorder or remove its tracks.
+fn edit_dialog(playlist: api::Playlist) {
+  let dialog = Dialog::new_with_buttons(
+    Some("Edit playlist"),
+    None::<&Window>,
+    DialogFlags::MODAL,
+    &[("Close", ResponseType::Close.into())],
+  );
+  dialog.set_default_size(480, 540);
+
+  let content = dialog.get_content_area();
+  content.set_spacing(12);
+  content.set_margin_top(12);
+  content.set_margin_bottom(12);
+  content.set_margin_start(12);
+  content.set_margin_end(12);
+
+  let header = gtk::Box::new(Orientation::Horizontal, 12);
+  let name = Label::new(playlist.name.as_ref());
+  if let Some(c) = name.get_style_context() {
+    c.add_class("h3")
+  }
+  name.set_hexpand(true);
+  name.set_halign(Align::Start);
+  header.add(&name);
+  let rename = Button::new_with_label("Rename…");
+  header.add(&rename);
+  content.add(&header);
+
+  let list = ListBox::new();
+  list.set_selection_mode(SelectionMode::None);
+  let scrolled = ScrolledWindow::new(None, None);
+  scrolled.set_vexpand(true);
+  scrolled.add(&list);
+  content.add(&scrolled);
+
+  let id = playlist.id;
+  rename.connect_clicked(clone!(name => move |_| {
+      if let Some(data) = playlist_dialog("Rename playlist", Some(&playlist)) {
+          let req = client!().api_patch(format!("/playlists/{}/", id)).json(&data);
+          clone!(name);
+          wait!(background(move || checked(send(req))) => |res| {
+              if res.is_ok() {
+                  name.set_text(&data.name);
+              }
+              report(res, "Couldn't rename the playlist");
+          });
+      }
+  }));
+
+  load_tracks(&list, id);
+  dialog.show_all();
+  dialog.run();
+  dialog.destroy();
+}
+
+fn load_tracks(list: &ListBox, playlist_id: i32) {
+  for ch in list.get_children() {
+    list.remove(&ch);
+  }
+
+  let widget = list.clone();
+  let list = list.clone();
+  let client = client!().clone();
+  wait!(for widget, background(move || client.playlist_tracks(playlist_id)) => |res| {
+      let tracks = match res {
+          Ok(tracks) => tracks,
+          Err(e) => {
+              list.add(&Label::new(format!("Couldn't load the tracks. {}", e).as_ref()));
+              list.show_all();
+              return;
+          }
+      };
+      let count = tracks.len() as i32;
+      for plt in tracks {
+          let row = gtk::Box::new(Orientation::Horizontal, 6);
+          let lbl = Label::new(format!("{} — {}", plt.track.artist.name, plt.track.title).as_ref());
+          lbl.set_hexpand(true);
+          lbl.set_halign(Align::Start);
+          row.add(&lbl);
+
+          let index = plt.index;
+          let up = Button::new_from_icon_name("go-up-symbolic", IconSize::Button.into());
+          up.set_sensitive(index > 0);
+          up.connect_clicked(clone!(list => move |_| {
+              let req = client!()
+                  .api_post(format!("/playlists/{}/move/", playlist_id))
+                  .json(&api::PlaylistMove { from: index, to: index - 1 });
+              update(&list, playlist_id, req);
+          }));
+          row.add(&up);
+
+          let down = Button::new_from_icon_name("go-down-symbolic", IconSize::Button.into());
+          down.set_sensitive(index < count - 1);
+          down.connect_clicked(clone!(list => move |_| {
+              let req = client!()
+                  .api_post(format!("/playlists/{}/move/", playlist_id))
+                  .json(&api::PlaylistMove { from: index, to: index + 1 });
+              update(&list, playlist_id, req);
+          }));
+          row.add(&down);
+
+          let remove = Button::new_from_icon_name("list-remove-symbolic", IconSize::Button.into());
+          remove.set_tooltip_text("Remove from the playlist");
+          remove.connect_clicked(clone!(list => move |_| {
+              let req = client!()
+                  .api_post(format!("/playlists/{}/remove/", playlist_id))
+                  .json(&api::PlaylistRemove { index });
+              update(&list, playlist_id, req);
+          }));
+          row.add(&remove);
+
+          list.add(&row);
+      }
+      list.show_all();
+  });
+}
+
+/// Sends a change to a playlist, and shows its new content.
+fn update(list: &ListBox, playlist_id: i32, req: api::Request) {
+  list.set_sensitive(false);
+  clone!(list);
+  wait!(background(move || checked(send(req))) => |res| {
+      list.set_sensitive(true);
+      load_tracks(&list, playlist_id);
+      report(res, "Couldn't update the playlist");
+  });
+}
+
+/// Turns error statuses into errors.
+fn checked(res: Result<api::Response, api::Error>) -> Result<api::Response, String> {
+  match res {
+    Ok(r) => {
+      if r.status().is_success() {
+        Ok(r)
+      } else {
+        Err(format!(
+          "The server answered: {}",
+          String::from_utf8_lossy(r.body())
+        ))
+      }
+    }
+    Err(e) => Err(e.to_string()),
+  }
+}
+
+/// Creates a playlist with some tracks.
+///
+/// This is blocking.
+fn create(data: api::PlaylistData, tracks: Vec<i32>) -> Result<api::Response, String> {
+  let client = client!().clone();
+  let playlist: api::Playlist = checked(send(client.api_post("/playlists/").json(&data)))?
+    .json()
+    .map_err(|e| e.to_string())?;
+  add(playlist.id, tracks)
+}
+
+/// This is blocking.
+fn add(playlist_id: i32, tracks: Vec<i32>) -> Result<api::Response, String> {
+  let req = client!()
+    .api_post(format!("/playlists/{}/add/", playlist_id))
+    .json(&api::PlaylistAdd {
+      tracks,
+      allow_duplicates: false,
+    });
+  checked(send(req))
+}
+
+/// Shows what went wrong, or updates the list of playlists.
+fn report<T>(res: Result<T, String>, msg: &str) {
+  match res {
+    Ok(_) => playlists::refresh(),
+    Err(e) => {
+      let dialog = MessageDialog::new(
+        None::<&Window>,
+        DialogFlags::MODAL,
+        MessageType::Error,
+        ButtonsType::Close,
+        &format!("{}. {}", msg, e),
+      );
+      dialog.run();
+      dialog.destroy();
+    }
+  }
+}
@@ -0,0 +1,148 @@
+use crate::{
+  api,
+  export,
+  ui::{card, title},
+};
+use gtk::*;
+use std::cell::RefCell;
+
+thread_local! {
+  /// The lists of playlists of the user, and of the other users
+  static LISTS: RefCell<Option<(gtk::Box, gtk::Box)>> = const { RefCell::new(None) };
+}
+
+pub fn render() -> gtk::Box {
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+  cont.set_valign(Align::Start);
+  cont.set_margin_top(48);
+  cont.set_margin_bottom(48);
+  cont.set_margin_start(96);
+  cont.set_margin_end(96);
+
+  let header = gtk::Box::new(Orientation::Horizontal, 12);
+  let own_title = title("Your playlists");
+  own_title.set_hexpand(true);
+  own_title.set_halign(Align::Start);
+  header.add(&own_title);
+
+  let xspf = CheckButton::new_with_label("Also export as XSPF");
+  xspf.set_tooltip_text(
+    "Downloaded playlists are saved as M3U files in the Playlists folder of your music.",
+  );
+  xspf.set_active(export::xspf_enabled());
+  xspf.connect_toggled(|bt| {
+    let enabled = bt.get_active();
+    std::thread::spawn(move || export::set_xspf(enabled));
+  });
+  header.add(&xspf);
+  cont.add(&header);
+
+  let own = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&own);
+
+  let public_title = title("Public playlists");
+  public_title.set_halign(Align::Start);
+  cont.add(&public_title);
+  let public = gtk::Box::new(Orientation::Vertical, 12);
+  cont.add(&public);
+
+  LISTS.with(|lists| *lists.borrow_mut() = Some((own, public)));
+  refresh();
+
+  cont.show_all();
+  cont
+}
+
+/// Loads the playlists again, after they were changed.
+pub fn refresh() {
+  let (own, public) = match LISTS.with(|lists| lists.borrow().clone()) {
+    Some(lists) => lists,
+    None => return,
+  };
+
+  let widget = own.clone();
+  wait!(for widget, api::background(load) => |res| {
+    for list in [&own, &public].iter() {
+      for ch in list.get_children() {
+        list.remove(&ch);
+      }
+    }
+
+    match res {
+      Ok((username, playlists)) => {
+        let (mine, others): (Vec<_>, Vec<_>) = playlists
+          .into_iter()
+          .partition(|p| p.user.username == username);
+        if mine.is_empty() {
+          own.add(&Label::new("You don't have any playlist yet. Add tracks to a new playlist from their menu."));
+        }
+        for playlist in mine {
+          own.add(&*card::render(card::OwnPlaylist(playlist)).borrow());
+        }
+        if others.is_empty() {
+          public.add(&Label::new("There are no public playlists on this instance."));
+        }
+        for playlist in others {
+          public.add(&*card::render(playlist).borrow());
+        }
+      }
+      Err(e) => {
+        own.add(&Label::new(format!("Couldn't load the playlists. {}", e).as_ref()));
+      }
+    }
+    own.show_all();
+    public.show_all();
+  });
+}
+
+/// The playlists the user can add tracks to.
+///
+/// This is blocking.
+pub fn own_playlists() -> Result<Vec<api::Playlist>, api::Error> {
+  let client = client!().clone();
+  let (username, playlists) = all_playlists(&client)?;
+  Ok(
+    playlists
+      .into_iter()
+      .filter(|p| p.user.username == username)
+      .collect(),
+  )
+}
+
+/// Fetches the name of the user and every playlist they can see.
+///
+/// This is blocking.
+fn all_playlists(client: &api::RequestContext) -> Result<(String, Vec<api::Playlist>), api::Error> {
+  let me: api::UserInfo = api::send(client.api_get("/users/users/me"))?.json()?;
+
+  let playlists = client.all_pages(
+    client
+      .api_get("/playlists/")
+      .query(&[("page_size", "100"), ("ordering", "-modification_date")]),
+  )?;
+  Ok((me.username, playlists))
+}
+
+/// Lists the playlists, and updates the exported ones that changed since they
+/// were downloaded.
+///
+/// This is blocking.
+fn load() -> Result<(String, Vec<api::Playlist>), api::Error> {
+  let client = client!().clone();
+  let (username, playlists) = all_playlists(&client)?;
+
+  for playlist in playlists.iter() {
+    if export::is_outdated(playlist.id, &playlist.modification_date) {
+      if let Ok(tracks) = client.playlist_tracks(playlist.id) {
+        export::watch(export::Export {
+          playlist_id: playlist.id,
+          name: playlist.name.clone(),
+          modification_date: playlist.modification_date.clone(),
+          tracks: tracks.into_iter().map(|t| t.track).collect(),
+        });
+      }
+    }
+  }
+
+  Ok((username, playlists))
+}
@@ -0,0 +1,227 @@
+use crate::{
+  paths,
+  schedule::{self, TimeWindow},
+  settings::{self, Format},
+};
+use gtk::*;
+use std::rc::Rc;
+
+/// What to do with the text of an entry once it is edited
+type Apply = Rc<dyn Fn(&Entry)>;
+
+/// Shows the Preferences dialog. Changes are applied right away, or when
+/// leaving the field for text.
+pub fn show(parent: &Window) {
+  let dialog = Dialog::new_with_buttons(
+    Some("Preferences"),
+    Some(parent),
+    DialogFlags::MODAL,
+    &[("Close", ResponseType::Close.into())],
+  );
+  dialog.set_default_size(540, -1);
+
+  let current = settings::get();
+  // Applied again when the dialog is closed, if they still have the focus
+  let mut edited: Vec<(Entry, Apply)> = vec![];
+  let grid = Grid::new();
+  grid.set_row_spacing(12);
+  grid.set_column_spacing(12);
+  grid.set_margin_top(18);
+  grid.set_margin_bottom(18);
+  grid.set_margin_start(18);
+  grid.set_margin_end(18);
+
+  let dir_bt = FileChooserButton::new("Download location", FileChooserAction::SelectFolder);
+  dir_bt.set_hexpand(true);
+  dir_bt.set_filename(paths::music_dir());
+  dir_bt.connect_file_set(|bt| {
+    let dir = bt.get_filename();
+    settings::update(move |s| s.download_dir = dir);
+  });
+  attach(&grid, 0, "Download location", &dir_bt);
+
+  let template = Entry::new();
+  template.set_text(&current.template);
+  template.set_placeholder_text(paths::DEFAULT_TEMPLATE);
+  edited.push(on_edited(&template, |entry| {
+    let text = text(entry);
+    match paths::check_template(&text) {
+      Ok(()) => {
+        invalid(entry, None);
+        settings::update(move |s| s.template = text);
+      }
+      Err(e) => invalid(entry, Some(&e)),
+    }
+  }));
+  attach(&grid, 1, "File names", &template);
+  let help = Label::new(
+    "{artist}, {album_artist}, {album} and {title} are replaced by the metadata of each track. Every / starts a new folder.",
+  );
+  help.set_line_wrap(true);
+  help.set_xalign(0.0);
+  if let Some(c) = help.get_style_context() {
+    c.add_class("dim-label")
+  }
+  grid.attach(&help, 1, 2, 1, 1);
+
+  let format = ComboBoxText::new();
+  for f in Format::ALL {
+    format.append(Some(f.id()), f.name());
+  }
+  format.set_active_id(Some(current.format.id()));
+  format.connect_changed(|combo| {
+    let id = combo.get_active_id().map(|id| id.to_string());
+    if let Some(&f) = Format::ALL.iter().find(|f| Some(f.id().to_string()) == id) {
+      settings::update(move |s| s.format = f);
+    }
+  });
+  attach(&grid, 3, "Format", &format);
+
+  let concurrency = SpinButton::new_with_range(1.0, 10.0, 1.0);
+  concurrency.set_value(current.concurrency as f64);
+  concurrency.connect_value_changed(|spin| {
+    let jobs = spin.get_value_as_int() as usize;
+    settings::update(move |s| s.concurrency = jobs);
+  });
+  attach(&grid, 4, "Simultaneous downloads", &concurrency);
+
+  let cache = SpinButton::new_with_range(10.0, 10_000.0, 10.0);
+  cache.set_value(current.cache_size as f64);
+  cache.connect_value_changed(|spin| {
+    let size = spin.get_value_as_int() as u64;
+    settings::update(move |s| s.cache_size = size);
+  });
+  attach(&grid, 5, "Cache size (MiB)", &cache);
+
+  let notifications = Switch::new();
+  notifications.set_halign(Align::Start);
+  notifications.set_active(current.notifications);
+  notifications.connect_state_set(|_, on| {
+    settings::update(move |s| s.notifications = on);
+    Inhibit(false)
+  });
+  attach(&grid, 6, "Notify when downloads are done", &notifications);
+
+  // Only the first window can be edited here, the others are kept
+  let window = current.windows.first().cloned();
+  let hours = Box::new(Orientation::Horizontal, 6);
+  let limit = CheckButton::new_with_label("Only between");
+  limit.set_active(window.is_some());
+  let start = Entry::new();
+  let end = Entry::new();
+  for (entry, default) in &[(&start, "01:00"), (&end, "07:00")] {
+    entry.set_width_chars(5);
+    entry.set_placeholder_text(*default);
+    entry.set_text(default);
+  }
+  if let Some(ref w) = window {
+    start.set_text(&w.start.format("%H:%M").to_string());
+    end.set_text(&w.end.format("%H:%M").to_string());
+  }
+  hours.add(&limit);
+  hours.add(&start);
+  hours.add(&Label::new("and"));
+  hours.add(&end);
+  let save_window = clone!(limit, start, end => move || {
+    let on = limit.get_active();
+    start.set_sensitive(on);
+    end.set_sensitive(on);
+    if !on {
+      settings::update(|s| s.windows.clear());
+      return;
+    }
+    let (start_hour, end_hour) = (schedule::parse_hour(&text(&start)), schedule::parse_hour(&text(&end)));
+    invalid(&start, start_hour.map_or(Some("Use the HH:MM format"), |_| None));
+    invalid(&end, end_hour.map_or(Some("Use the HH:MM format"), |_| None));
+    let window = match (start_hour, end_hour) {
+      (Some(start), Some(end)) => TimeWindow { start, end },
+      _ => return,
+    };
+    settings::update(move |s| {
+      if s.windows.is_empty() {
+        s.windows.push(window);
+      } else {
+        s.windows[0] = window;
+      }
+    });
+  });
+  start.set_sensitive(window.is_some());
+  end.set_sensitive(window.is_some());
+  limit.connect_toggled(clone!(save_window => move |_| save_window()));
+  edited.push(on_edited(&start, clone!(save_window => move |_| save_window())));
+  edited.push(on_edited(&end, move |_| save_window()));
+  attach(&grid, 7, "Download", &hours);
+
+  let track_hook = hook_entry(&current.track_hook);
+  edited.push(on_edited(&track_hook, |entry| {
+    let hook = text(entry);
+    settings::update(move |s| s.track_hook = hook);
+  }));
+  attach(&grid, 8, "After each track", &track_hook);
+  let album_hook = hook_entry(&current.album_hook);
+  edited.push(on_edited(&album_hook, |entry| {
+    let hook = text(entry);
+    settings::update(move |s| s.album_hook = hook);
+  }));
+  attach(&grid, 9, "After each album", &album_hook);
+  let help = Label::new(
+    "A command, that gets the track in $MOBYDICK_FILE, $MOBYDICK_TITLE, $MOBYDICK_ALBUM… or a URL to send them to.",
+  );
+  help.set_line_wrap(true);
+  help.set_xalign(0.0);
+  if let Some(c) = help.get_style_context() {
+    c.add_class("dim-label")
+  }
+  grid.attach(&help, 1, 10, 1, 1);
+
+  dialog.get_content_area().add(&grid);
+  dialog.show_all();
+  dialog.run();
+  for (entry, apply) in edited {
+    if entry.is_focus() {
+      apply(&entry);
+    }
+  }
+  dialog.destroy();
+}
+
+fn hook_entry(hook: &str) -> Entry {
+  let entry = Entry::new();
+  entry.set_text(hook);
+  entry.set_placeholder_text("beet import -q \"$MOBYDICK_DIR\"");
+  entry
+}
+
+/// Calls `apply` when Enter is pressed in `entry` or when it loses the focus,
+/// rather than on every key press.
+fn on_edited<F: Fn(&Entry) + 'static>(entry: &Entry, apply: F) -> (Entry, Apply) {
+  let apply: Apply = Rc::new(apply);
+  entry.connect_activate(clone!(apply => move |entry| apply(entry)));
+  entry.connect_focus_out_event(clone!(apply => move |entry, _| {
+    apply(entry);
+    Inhibit(false)
+  }));
+  (entry.clone(), apply)
+}
+
+fn text(entry: &Entry) -> String {
+  entry.get_text().map(|t| t.to_string()).unwrap_or_default()
+}
+
+/// Shows why the content of an entry can't be used, or that it can with `None`.
+fn invalid(entry: &Entry, error: Option<&str>) {
+  if let Some(c) = entry.get_style_context() {
+    match error {
+      Some(_) => c.add_class("error"),
+      None => c.remove_class("error"),
+    }
+  }
+  entry.set_tooltip_text(error);
+}
+
+fn attach<W: IsA<Widget>>(grid: &Grid, row: i32, label: &str, widget: &W) {
+  let label = Label::new(label);
+  label.set_halign(Align::End);
+  grid.attach(&label, 0, row, 1, 1);
+  grid.attach(widget, 1, row, 1, 1);
+}
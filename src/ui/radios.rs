@@ -0,0 +1,242 @@
+use crate::{
+  api::{self, background, execute},
+  ui::{card::CardModel, title},
+  Download,
+};
+use gtk::*;
+use std::collections::HashSet;
+
+const DEFAULT_COUNT: f64 = 50.0;
+
+#[derive(Debug, Clone)]
+pub enum Radio {
+  Random,
+  Favorites,
+  LessListened,
+  Tag(String),
+  /// ID and name of the artist
+  Artist(i32, String),
+  /// ID and name of the radio
+  Custom(i32, String),
+}
+
+impl Radio {
+  fn request(&self) -> api::RadioSessionRequest {
+    let (radio_type, related_object_id, custom_radio) = match self {
+      Radio::Random => ("random", None, None),
+      Radio::Favorites => ("favorites", None, None),
+      Radio::LessListened => ("less-listened", None, None),
+      Radio::Tag(tag) => ("tag", Some(tag.clone()), None),
+      Radio::Artist(id, _) => ("artist", Some(id.to_string()), None),
+      Radio::Custom(id, _) => ("custom", None, Some(*id)),
+    };
+    api::RadioSessionRequest {
+      radio_type: radio_type.to_string(),
+      related_object_id,
+      custom_radio,
+    }
+  }
+
+  pub fn name(&self) -> String {
+    match self {
+      Radio::Random => "Random".to_string(),
+      Radio::Favorites => "Favorites".to_string(),
+      Radio::LessListened => "Less listened".to_string(),
+      Radio::Tag(tag) => format!("#{}", tag),
+      Radio::Artist(_, name) => format!("{} radio", name),
+      Radio::Custom(_, name) => name.clone(),
+    }
+  }
+}
+
+pub fn render() -> gtk::Box {
+  let cont = gtk::Box::new(Orientation::Vertical, 12);
+  cont.set_valign(Align::Start);
+  cont.set_margin_top(48);
+  cont.set_margin_bottom(48);
+  cont.set_margin_start(96);
+  cont.set_margin_end(96);
+
+  let title = title("Radios");
+  title.set_halign(Align::Start);
+  cont.add(&title);
+  let help = Label::new("Pick some tracks from a radio of your instance, and download them.");
+  help.set_halign(Align::Start);
+  cont.add(&help);
+
+  let form = Grid::new();
+  form.set_column_spacing(12);
+  form.set_row_spacing(12);
+
+  let kind = ComboBoxText::new();
+  kind.append(Some("random"), "Random");
+  kind.append(Some("favorites"), "Favorites");
+  kind.append(Some("less-listened"), "Less listened");
+  kind.append(Some("tag"), "Tag");
+  kind.set_active_id(Some("random"));
+  form.attach(&Label::new("Radio"), 0, 0, 1, 1);
+  form.attach(&kind, 1, 0, 1, 1);
+
+  let tag = Entry::new();
+  tag.set_placeholder_text("jazz");
+  tag.set_no_show_all(true);
+  form.attach(&tag, 2, 0, 1, 1);
+  kind.connect_changed(clone!(tag => move |kind| {
+      tag.set_visible(kind.get_active_id() == Some("tag".to_string()));
+  }));
+
+  let count = SpinButton::new_with_range(1.0, 500.0, 1.0);
+  count.set_value(DEFAULT_COUNT);
+  form.attach(&Label::new("Tracks"), 0, 1, 1, 1);
+  form.attach(&count, 1, 1, 1, 1);
+  cont.add(&form);
+
+  let dl_bt = Button::new_with_label("Download");
+  dl_bt.set_halign(Align::Start);
+  if let Some(c) = dl_bt.get_style_context() {
+    c.add_class("suggested-action")
+  }
+  cont.add(&dl_bt);
+
+  let status = Label::new("");
+  status.set_halign(Align::Start);
+  cont.add(&status);
+
+  // The custom radios of the user and the public ones
+  let widget = kind.clone();
+  let radios = kind.clone();
+  let req = client!().api_get("/radios/radios/").query(&[("page_size", "100")]);
+  wait!(for widget, execute(req) => |res| {
+    if let Ok(page) = res.and_then(|r| r.json::<api::Page<api::CustomRadio>>()) {
+      for radio in page.results {
+        radios.append(Some(format!("custom:{}", radio.id).as_ref()), &radio.name);
+      }
+    }
+  });
+
+  dl_bt.connect_clicked(move |bt| {
+    let id = kind.get_active_id().unwrap_or_default();
+    let radio = match id.as_ref() {
+      "favorites" => Radio::Favorites,
+      "less-listened" => Radio::LessListened,
+      "tag" => {
+        let name = tag.get_text().unwrap_or_default().trim().trim_start_matches('#').to_string();
+        if name.is_empty() {
+          status.set_text("Which tag do you want to listen to?");
+          return;
+        }
+        Radio::Tag(name)
+      }
+      custom if custom.starts_with("custom:") => Radio::Custom(
+        custom["custom:".len()..].parse().unwrap_or_default(),
+        kind.get_active_text().unwrap_or_default(),
+      ),
+      _ => Radio::Random,
+    };
+    start(radio, count.get_value_as_int() as usize, bt, &status);
+  });
+
+  cont.show_all();
+  cont
+}
+
+/// A menu to download tracks from the radio of an artist.
+pub fn artist_menu(id: i32, name: String) -> Menu {
+  let menu = Menu::new();
+  let item = MenuItem::new_with_label("Download from this artist's radio…");
+  item.connect_activate(move |_| {
+    ask_count(Radio::Artist(id, name.clone()));
+  });
+  menu.append(&item);
+  menu.show_all();
+  menu
+}
+
+/// Asks how many tracks to download from a radio, and downloads them.
+pub fn ask_count(radio: Radio) {
+  let dialog = Dialog::new_with_buttons(
+    Some(radio.name().as_ref()),
+    None::<&Window>,
+    DialogFlags::MODAL,
+    &[
+      ("Cancel", ResponseType::Cancel.into()),
+      ("Download", ResponseType::Ok.into()),
+    ],
+  );
+  let content = dialog.get_content_area();
+  content.set_spacing(12);
+  content.set_margin_top(12);
+  content.set_margin_bottom(12);
+  content.set_margin_start(12);
+  content.set_margin_end(12);
+  content.add(&Label::new("How many tracks do you want to download?"));
+  let count = SpinButton::new_with_range(1.0, 500.0, 1.0);
+  count.set_value(DEFAULT_COUNT);
+  content.add(&count);
+  dialog.show_all();
+
+  let answer = dialog.run();
+  let count = count.get_value_as_int() as usize;
+  dialog.destroy();
+
+  if ResponseType::from(answer) == ResponseType::Ok {
+    wait!(background(move || sample(&radio, count)) => |const res| {
+      if let Ok(dls) = res {
+        crate::enqueue(dls);
+      }
+    });
+  }
+}
+
+fn start(radio: Radio, count: usize, bt: &Button, status: &Label) {
+  bt.set_sensitive(false);
+  status.set_text(&format!("Picking {} tracks from {}…", count, radio.name()));
+  clone!(bt, status);
+  wait!(background(move || sample(&radio, count)) => |const res| {
+    match res {
+      Ok(dls) => {
+        status.set_text(&format!("{} new tracks are being downloaded.", dls.len()));
+        crate::enqueue(dls);
+      }
+      Err(e) => status.set_text(&format!("Couldn't start the radio. {}", e)),
+    }
+    bt.set_sensitive(true);
+  });
+}
+
+/// Starts a radio session, and gets tracks from it until we have `count` new
+/// tracks to download.
+///
+/// This is blocking.
+fn sample(radio: &Radio, count: usize) -> Result<Vec<Download>, String> {
+  let client = client!().clone();
+  let res = api::send(client.api_post("/radios/sessions/").json(&radio.request()))
+    .map_err(|e| e.to_string())?;
+  if !res.status().is_success() {
+    return Err(String::from_utf8_lossy(res.body()).to_string());
+  }
+  let session: api::RadioSession = res.json().map_err(|e| e.to_string())?;
+
+  let mut seen = HashSet::new();
+  let mut dls = vec![];
+  // Small radios run out of tracks, and then keep giving the same ones
+  for _ in 0..count * 2 {
+    if dls.len() >= count {
+      break;
+    }
+    let req = client
+      .api_post("/radios/tracks/")
+      .json(&api::RadioTrackRequest { session: session.id });
+    let track = match api::send(req) {
+      Ok(ref res) if res.status().is_success() => match res.json::<api::RadioTrack>() {
+        Ok(t) => t.track,
+        Err(_) => break,
+      },
+      _ => break,
+    };
+    if seen.insert(track.id) {
+      dls.extend(track.downloads().into_iter().filter(|dl| !crate::on_disk(dl)));
+    }
+  }
+  Ok(dls)
+}
@@ -0,0 +1,153 @@
+use crate::{
+  api::{self, execute, ApiVersion},
+  ui::{card, radios, title},
+};
+use gtk::*;
+use std::cell::RefCell;
+
+/// How many tags are shown on a card
+const CARD_TAGS: usize = 4;
+
+thread_local! {
+  /// The main stack, and the stack of this page (with the list of tags, and
+  /// the page of the current tag)
+  static NAV: RefCell<Option<(Stack, Stack, gtk::Box)>> = const { RefCell::new(None) };
+}
+
+pub fn render(main_stack: &Stack) -> Stack {
+  let stack = Stack::new();
+  stack.set_transition_type(StackTransitionType::SlideLeftRight);
+
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  list.set_valign(Align::Start);
+  list.set_margin_top(48);
+  list.set_margin_bottom(48);
+  list.set_margin_start(96);
+  list.set_margin_end(96);
+
+  let list_title = title("Popular tags");
+  list_title.set_halign(Align::Start);
+  list.add(&list_title);
+
+  let tags = FlowBox::new();
+  tags.set_selection_mode(SelectionMode::None);
+  tags.set_column_spacing(6);
+  tags.set_row_spacing(6);
+  list.add(&tags);
+  stack.add_named(&list, "list");
+
+  let tag_page = gtk::Box::new(Orientation::Vertical, 12);
+  tag_page.set_valign(Align::Start);
+  tag_page.set_margin_top(48);
+  tag_page.set_margin_bottom(48);
+  tag_page.set_margin_start(96);
+  tag_page.set_margin_end(96);
+  stack.add_named(&tag_page, "tag");
+
+  NAV.with(|nav| *nav.borrow_mut() = Some((main_stack.clone(), stack.clone(), tag_page)));
+
+  if client!().version() == ApiVersion::Legacy {
+    tags.add(&Label::new("Tags need Funkwhale 1.0 or newer."));
+  } else {
+    let widget = tags.clone();
+    let req = client!()
+      .api_get("/tags/")
+      .query(&[("ordering", "-length"), ("page_size", "100")]);
+    wait!(for widget, execute(req) => |res| {
+      match res.and_then(|r| r.json::<api::Page<api::Tag>>()) {
+        Ok(page) => {
+          for tag in page.results {
+            tags.add(&chip(&tag.name));
+          }
+        }
+        Err(e) => {
+          tags.add(&Label::new(format!("Couldn't load the tags. {}", e).as_ref()));
+        }
+      }
+      tags.show_all();
+    });
+  }
+
+  stack.show_all();
+  stack
+}
+
+/// A button that opens the page of a tag.
+pub fn chip(tag: &str) -> Button {
+  let bt = Button::new_with_label(&format!("#{}", tag));
+  bt.set_relief(ReliefStyle::None);
+  bt.set_valign(Align::Center);
+  let tag = tag.to_string();
+  bt.connect_clicked(move |_| show(&tag));
+  bt
+}
+
+/// The tags of a card.
+pub fn chips(tags: &[String]) -> gtk::Box {
+  let chips = gtk::Box::new(Orientation::Horizontal, 0);
+  for tag in tags.iter().take(CARD_TAGS) {
+    chips.add(&chip(tag));
+  }
+  chips
+}
+
+/// Shows the artists, albums and tracks with a tag.
+pub fn show(tag: &str) {
+  let (main_stack, stack, page) = match NAV.with(|nav| nav.borrow().clone()) {
+    Some(nav) => nav,
+    None => return,
+  };
+  for ch in page.get_children() {
+    page.remove(&ch);
+  }
+
+  let header = gtk::Box::new(Orientation::Horizontal, 12);
+  let back = Button::new_from_icon_name("go-previous-symbolic", IconSize::Button.into());
+  back.connect_clicked(clone!(stack => move |_| {
+      stack.set_visible_child_name("list");
+  }));
+  header.add(&back);
+  let tag_title = title(&format!("#{}", tag));
+  tag_title.set_hexpand(true);
+  tag_title.set_halign(Align::Start);
+  header.add(&tag_title);
+  let radio_bt = Button::new_with_label("Download from this tag's radio…");
+  let name = tag.to_string();
+  radio_bt.connect_clicked(move |_| {
+    radios::ask_count(radios::Radio::Tag(name.clone()));
+  });
+  header.add(&radio_bt);
+  page.add(&header);
+
+  let query = [("tag", tag), ("page_size", "20")];
+  section::<api::Artist>(&page, "Artists", client!().api_get("/artists/").query(&query));
+  section::<api::Album>(&page, "Albums", client!().api_get("/albums/").query(&query));
+  section::<api::Track>(&page, "Songs", client!().api_get("/tracks/").query(&query));
+
+  page.show_all();
+  stack.set_visible_child_name("tag");
+  main_stack.set_visible_child_name("tags");
+}
+
+fn section<T>(page: &gtk::Box, name: &str, req: api::Request)
+where
+  T: card::CardModel + serde::de::DeserializeOwned + Send + 'static,
+{
+  let sec_title = title(name);
+  sec_title.set_halign(Align::Start);
+  sec_title.set_no_show_all(true);
+  page.add(&sec_title);
+  let list = gtk::Box::new(Orientation::Vertical, 12);
+  page.add(&list);
+
+  let widget = list.clone();
+  wait!(for widget, execute(req) => |res| {
+    if let Ok(page) = res.and_then(|r| r.json::<api::Page<T>>()) {
+      sec_title.set_visible(!page.results.is_empty());
+      for item in page.results {
+        list.add(&*card::render(item).borrow());
+      }
+      list.show_all();
+    }
+  });
+}